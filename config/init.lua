@@ -2,15 +2,13 @@
 ---@class Config
 ---  Optional tmux configuration. Including sessions and windows to be created.
 ---@field tmux Tmux|nil
----  Optional configuration for cache-shell-setup
----@field shell_caching ShellCache|nil
+---  Optional configuration for cache-shell-setup, either a single entry or a list of named  entries.
+---@field shell_caching ShellCaching|nil
+---  Optional configuration for setup-local-dotfiles: the files it creates within the local  dotfiles directory and the symlinks it points at them.
+---@field local_dotfiles LocalDotfiles|nil
 ---  Optional list of crate locations (used as a lookup path for tmux windows `linked_crates`)
 ---@field crate_locations string[]|nil
 
----@class ShellCache
----@field source string
----@field destination string
-
 ---  Tmux configuration.
 ---@class Tmux
 ---  List of tmux sessions.
@@ -25,18 +23,75 @@
 ---  List of windows in the session.
 ---@field windows Window[]
 
----@alias Command string|string[]
-
 ---  Configuration for a tmux window.
 ---@class Window
 ---  Name of the window.
 ---@field name string
 ---  Optional path to set as the working directory for the window.
 ---@field path string|nil
----  Optional command to run in the window.
----@field command Command|nil
+---  Optional command to run in the window, which can be a function that is called (with the  config context) when the window is created.
+---@field command Command|(fun(ctx: ConfigContext): Command)|nil
 ---  Additional environment variables to set in the window.
 ---@field env table<string, string>|nil
 ---  The names of any of the workspaces crates that provide binaries that should be available on  $PATH inside the new window.
 ---@field linked_crates string[]|nil
 
+---@alias Command string|string[]
+
+---  Describes the invocation that is loading the config. Configs (and lazy values) that are  functions are called with it.
+---@class ConfigContext
+---  The hostname of the machine.
+---@field hostname string
+---  The current working directory.
+---@field cwd string
+---  The name of the binary that is loading the config (e.g. `startup-tmux`).
+---@field binary string
+---  The arguments that the binary was invoked with (excluding the binary itself).
+---@field args string[]
+---  The environment variables of the process.
+---@field env table<string, string>
+
+---@alias ShellCaching ShellCache|ShellCache[]
+
+---@class ShellCache
+---  The name of the entry (e.g. for `cache-shell-startup --only <name>`). Required when  `shell_caching` is a list of entries.
+---@field name string|nil
+---@field source string
+---@field destination string
+---  Whether the destination directory is cleared (`clear`, the default) or merged into  (`merge`). The `--destination-strategy` flag takes precedence.
+---@field destination_strategy DestinationStrategy|nil
+---  Glob patterns (relative to `source`, e.g. `plugins/*.zsh`) of the files whose directives  are processed. Defaults to every file; other files are copied as is.
+---@field include string[]|nil
+---  Glob patterns (relative to `source`, e.g. `bin/*`) of the files that are copied as is,  without processing their directives.
+---@field exclude string[]|nil
+---  The shell (`zsh`, the default, `bash`, `sh` or `fish`) that sources the files whose name  doesn't identify one (unlike e.g. `config.fish`, `*.bash` or `zshrc`). It determines the  syntax of generated lines, e.g. `export` vs `set -gx` for `# ENV:` directives.
+---@field target_shell string|nil
+---  The comment prefixes that directives use within the files matching glob patterns (relative  to `source`), e.g. `{ ["*.lua"] = "--" }`. Defaults to `#`.
+---@field comment_prefixes table<string, string>|nil
+---  How long commands may run (e.g. `30s`) before they are killed. Defaults to no timeout,  and can be overridden per directive (e.g. `# CMD[timeout=1m]: ...`).
+---@field timeout string|nil
+---  The shell that runs commands: `sh` (the default), `bash` or `zsh`. Can be overridden per  directive (e.g. `# CMD[shell=zsh]: ...`).
+---@field shell string|nil
+---  The working directory of commands. Defaults to the current directory, and can be  overridden per directive (e.g. `# CMD[cwd=~/src]: ...`).
+---@field cwd string|nil
+---  Additional environment variables for commands, which can be extended per directive (e.g.  `# CMD[env=NAME=value]: ...`).
+---@field env table<string, string>|nil
+---  Whether failing commands are replaced with a commented error instead of aborting. Defaults  to `false`, and can be overridden per directive (e.g. `# CMD[allow_failure=true]: ...`).
+---@field allow_failure boolean|nil
+
+---@alias DestinationStrategy "clear"|"merge"
+
+---  The layout that setup-local-dotfiles manages within the local dotfiles directory.
+---@class LocalDotfiles
+---  The files to create (unless they already exist) keyed by their path relative to the local  dotfiles directory, with their initial contents (e.g. `{ ["nvim/snippets/.gitkeep"] = "" }`).  Defaults to the binutils and nvim layout.
+---@field files table<string, string>|nil
+---  The symlinks to create, replacing any existing symlink at their target. Defaults to  linking `~/.config/nvim/lua/local_nvim` and `~/.config/binutils/local.config.lua`.
+---@field symlinks Symlink[]|nil
+
+---  A symlink that setup-local-dotfiles creates.
+---@class Symlink
+---  The path (relative to the local dotfiles directory) that the symlink points at.
+---@field source string
+---  Where the symlink is created (e.g. `~/.config/ghostty/local.config`).
+---@field target string
+
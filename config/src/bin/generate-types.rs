@@ -0,0 +1,36 @@
+use clap::Parser;
+use lua_config_utils::LuaTypeRegistry;
+use lua_config_utils::lua_type_gen::{GenerateArgs, OutputFormat, generate};
+
+use anyhow::Result;
+use tracing_subscriber::EnvFilter;
+
+/// Generate lua types for the config (or a given file's desearializable structs)
+#[derive(Parser, Debug)]
+#[command()]
+struct Args {
+    #[command(flatten)]
+    generate: GenerateArgs,
+
+    /// The format of the generated types (e.g. `teal` for a `.d.tl` declaration file)
+    #[arg(long, value_enum, default_value_t = OutputFormat::LuaLs)]
+    format: OutputFormat,
+}
+
+fn main() -> Result<()> {
+    // Initialize tracing, use `info` by default
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
+
+    latest_bin::ensure_latest_bin()?;
+
+    let args = Args::parse();
+
+    let mut registry = LuaTypeRegistry::new();
+    registry.register::<config::Config>();
+
+    generate(&args.generate, Some(&registry), args.format)
+}
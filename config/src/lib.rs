@@ -5,32 +5,262 @@ use std::path::{Path, PathBuf};
 use tracing::{debug, trace};
 
 use anyhow::Result;
+use lua_config_utils::{Lazy, LuaConfigLoader, LuaType};
+use serde::de::value::{MapAccessDeserializer, SeqAccessDeserializer};
+use serde::de::{MapAccess, SeqAccess, Visitor};
 use serde::{Deserialize, Serialize};
 
 /// Configuration for the application.
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, LuaType)]
 pub struct Config {
     /// Optional tmux configuration. Including sessions and windows to be created.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tmux: Option<Tmux>,
 
-    /// Optional configuration for cache-shell-setup
+    /// Optional configuration for cache-shell-setup, either a single entry or a list of named
+    /// entries.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub shell_caching: Option<ShellCache>,
+    pub shell_caching: Option<ShellCaching>,
+
+    /// Optional configuration for setup-local-dotfiles: the files it creates within the local
+    /// dotfiles directory and the symlinks it points at them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local_dotfiles: Option<LocalDotfiles>,
 
     /// Optional list of crate locations (used as a lookup path for tmux windows `linked_crates`)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub crate_locations: Option<Vec<String>>,
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// The entries that cache-shell-setup processes.
+#[derive(Debug, Clone, PartialEq, Serialize, LuaType)]
+#[serde(untagged)]
+pub enum ShellCaching {
+    /// A single entry, e.g. `{ source = "~/zsh", destination = "~/zsh/dist" }`.
+    Single(Box<ShellCache>),
+    /// A list of entries, which must each have a (unique) `name`.
+    Multiple(Vec<ShellCache>),
+}
+
+// deserialized by hand rather than with `#[serde(untagged)]`, so that errors within an entry
+// (e.g. a missing `destination`) are reported instead of "did not match any variant"
+impl<'de> Deserialize<'de> for ShellCaching {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct ShellCachingVisitor;
+
+        impl<'de> Visitor<'de> for ShellCachingVisitor {
+            type Value = ShellCaching;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a shell_caching entry or a list of entries")
+            }
+
+            fn visit_seq<A>(self, seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                Vec::deserialize(SeqAccessDeserializer::new(seq)).map(ShellCaching::Multiple)
+            }
+
+            fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                ShellCache::deserialize(MapAccessDeserializer::new(map))
+                    .map(|entry| ShellCaching::Single(Box::new(entry)))
+            }
+        }
+
+        deserializer.deserialize_any(ShellCachingVisitor)
+    }
+}
+
+impl ShellCaching {
+    pub fn entries(&self) -> &[ShellCache] {
+        match self {
+            ShellCaching::Single(entry) => std::slice::from_ref(entry),
+            ShellCaching::Multiple(entries) => entries,
+        }
+    }
+
+    /// The entries with the given names, or every entry when `only` is empty.
+    pub fn select(&self, only: &[String]) -> Result<Vec<&ShellCache>> {
+        let entries = self.entries();
+        if only.is_empty() {
+            return Ok(entries.iter().collect());
+        }
+
+        only.iter()
+            .map(|name| {
+                entries
+                    .iter()
+                    .find(|entry| entry.name.as_ref() == Some(name))
+                    .ok_or_else(|| {
+                        let names: Vec<String> = entries
+                            .iter()
+                            .filter_map(|entry| entry.name.as_ref())
+                            .map(|name| format!("`{}`", name))
+                            .collect();
+                        anyhow::anyhow!(
+                            "Unknown shell_caching entry `{}` (configured entries: {})",
+                            name,
+                            if names.is_empty() {
+                                "none with a name".to_string()
+                            } else {
+                                names.join(", ")
+                            }
+                        )
+                    })
+            })
+            .collect()
+    }
+}
+
+/// Whether the destination directory is cleared before the output is written into it.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, LuaType, clap::ValueEnum,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum DestinationStrategy {
+    /// Replace the destination directory with the output.
+    #[default]
+    Clear,
+    /// Write the output into the destination directory, keeping any other files.
+    Merge,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, LuaType)]
 pub struct ShellCache {
+    /// The name of the entry (e.g. for `cache-shell-startup --only <name>`). Required when
+    /// `shell_caching` is a list of entries.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
     pub source: String,
     pub destination: String,
+
+    /// Whether the destination directory is cleared (`clear`, the default) or merged into
+    /// (`merge`). The `--destination-strategy` flag takes precedence.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub destination_strategy: Option<DestinationStrategy>,
+
+    /// Glob patterns (relative to `source`, e.g. `plugins/*.zsh`) of the files whose directives
+    /// are processed. Defaults to every file; other files are copied as is.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include: Option<Vec<String>>,
+
+    /// Glob patterns (relative to `source`, e.g. `bin/*`) of the files that are copied as is,
+    /// without processing their directives.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclude: Option<Vec<String>>,
+
+    /// The shell (`zsh`, the default, `bash`, `sh` or `fish`) that sources the files whose name
+    /// doesn't identify one (unlike e.g. `config.fish`, `*.bash` or `zshrc`). It determines the
+    /// syntax of generated lines, e.g. `export` vs `set -gx` for `# ENV:` directives.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_shell: Option<String>,
+
+    /// The comment prefixes that directives use within the files matching glob patterns (relative
+    /// to `source`), e.g. `{ ["*.lua"] = "--" }`. Defaults to `#`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment_prefixes: Option<BTreeMap<String, String>>,
+
+    /// How long commands may run (e.g. `30s`) before they are killed. Defaults to no timeout,
+    /// and can be overridden per directive (e.g. `# CMD[timeout=1m]: ...`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<String>,
+
+    /// The shell that runs commands: `sh` (the default), `bash` or `zsh`. Can be overridden per
+    /// directive (e.g. `# CMD[shell=zsh]: ...`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shell: Option<String>,
+
+    /// The working directory of commands. Defaults to the current directory, and can be
+    /// overridden per directive (e.g. `# CMD[cwd=~/src]: ...`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+
+    /// Additional environment variables for commands, which can be extended per directive (e.g.
+    /// `# CMD[env=NAME=value]: ...`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub env: Option<BTreeMap<String, String>>,
+
+    /// Whether failing commands are replaced with a commented error instead of aborting. Defaults
+    /// to `false`, and can be overridden per directive (e.g. `# CMD[allow_failure=true]: ...`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_failure: Option<bool>,
+}
+
+/// The layout that setup-local-dotfiles manages within the local dotfiles directory.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, LuaType)]
+pub struct LocalDotfiles {
+    /// The files to create (unless they already exist) keyed by their path relative to the local
+    /// dotfiles directory, with their initial contents (e.g. `{ ["nvim/snippets/.gitkeep"] = "" }`).
+    /// Defaults to the binutils and nvim layout.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub files: Option<BTreeMap<String, String>>,
+
+    /// The symlinks to create, replacing any existing symlink at their target. Defaults to
+    /// linking `~/.config/nvim/lua/local_nvim` and `~/.config/binutils/local.config.lua`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub symlinks: Option<Vec<Symlink>>,
+}
+
+impl LocalDotfiles {
+    /// The configured files, or the default layout.
+    pub fn files(&self) -> BTreeMap<String, String> {
+        self.files.clone().unwrap_or_else(|| {
+            [
+                (
+                    "binutils/config/local.config.lua",
+                    "return require('config')",
+                ),
+                // TODO: set up Cargo.toml
+                ("binutils/crates/.gitkeep", ""),
+                ("nvim/lua/config/autocmds.lua", ""),
+                ("nvim/lua/config/options.lua", ""),
+                ("nvim/lua/config/keymaps.lua", ""),
+                ("nvim/lua/plugins/.gitkeep", ""),
+                ("nvim/snippets/.gitkeep", ""),
+            ]
+            .into_iter()
+            .map(|(path, contents)| (path.to_string(), contents.to_string()))
+            .collect()
+        })
+    }
+
+    /// The configured symlinks, or the default ones.
+    pub fn symlinks(&self) -> Vec<Symlink> {
+        self.symlinks.clone().unwrap_or_else(|| {
+            vec![
+                Symlink {
+                    source: "nvim/lua".to_string(),
+                    target: "~/.config/nvim/lua/local_nvim".to_string(),
+                },
+                Symlink {
+                    source: "binutils/config/local.config.lua".to_string(),
+                    target: "~/.config/binutils/local.config.lua".to_string(),
+                },
+            ]
+        })
+    }
+}
+
+/// A symlink that setup-local-dotfiles creates.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, LuaType)]
+pub struct Symlink {
+    /// The path (relative to the local dotfiles directory) that the symlink points at.
+    pub source: String,
+
+    /// Where the symlink is created (e.g. `~/.config/ghostty/local.config`).
+    pub target: String,
 }
 
 /// Tmux configuration.
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, LuaType)]
 pub struct Tmux {
     /// List of tmux sessions.
     pub sessions: Vec<Session>,
@@ -41,7 +271,7 @@ pub struct Tmux {
 }
 
 /// Configuration for a tmux session.
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, LuaType)]
 pub struct Session {
     /// Name of the session.
     pub name: String,
@@ -50,7 +280,7 @@ pub struct Session {
 }
 
 /// Command to be executed in a tmux window.
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, LuaType)]
 #[serde(untagged)]
 pub enum Command {
     /// A single command as a string.
@@ -60,7 +290,7 @@ pub enum Command {
 }
 
 /// Configuration for a tmux window.
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, LuaType)]
 pub struct Window {
     /// Name of the window.
     pub name: String,
@@ -71,11 +301,13 @@ pub struct Window {
         deserialize_with = "string_to_path",
         skip_serializing_if = "Option::is_none"
     )]
+    #[lua(as = "Option<String>")]
     pub path: Option<PathBuf>,
 
-    /// Optional command to run in the window.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub command: Option<Command>,
+    /// Optional command to run in the window, which can be a function that is called (with the
+    /// config context) when the window is created.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<Lazy<Command>>,
 
     /// Additional environment variables to set in the window.
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -129,6 +361,57 @@ impl Config {
             }
         }
 
+        if let Some(ShellCaching::Multiple(entries)) = &self.shell_caching {
+            let mut seen_entries = BTreeMap::new();
+
+            for (index, entry) in entries.iter().enumerate() {
+                let Some(name) = &entry.name else {
+                    issues.push(format!(
+                        "shell_caching entry at index {} ({}) has no name",
+                        index, entry.source
+                    ));
+                    continue;
+                };
+
+                if let Some(previous_index) = seen_entries.get(name) {
+                    issues.push(format!(
+                        "Duplicate shell_caching entry name '{}' found at indices {} and {}",
+                        name, previous_index, index
+                    ));
+                }
+                seen_entries.insert(name.clone(), index);
+            }
+        }
+
+        if let Some(local_dotfiles) = &self.local_dotfiles {
+            for path in local_dotfiles.files.iter().flat_map(|files| files.keys()) {
+                if !is_relative_path(path) {
+                    issues.push(format!(
+                        "local_dotfiles file '{}' must be relative to the local dotfiles directory",
+                        path
+                    ));
+                }
+            }
+
+            let mut seen_targets = BTreeMap::new();
+            for (index, symlink) in local_dotfiles.symlinks.iter().flatten().enumerate() {
+                if !is_relative_path(&symlink.source) {
+                    issues.push(format!(
+                        "local_dotfiles symlink source '{}' must be relative to the local dotfiles directory",
+                        symlink.source
+                    ));
+                }
+
+                if let Some(previous_index) = seen_targets.get(&symlink.target) {
+                    issues.push(format!(
+                        "Duplicate local_dotfiles symlink target '{}' found at indices {} and {}",
+                        symlink.target, previous_index, index
+                    ));
+                }
+                seen_targets.insert(symlink.target.clone(), index);
+            }
+        }
+
         if issues.is_empty() {
             Ok(())
         } else {
@@ -141,6 +424,17 @@ impl Config {
     }
 }
 
+/// Whether `path` stays within the directory it is relative to (e.g. no `/etc/foo` or `../foo`).
+fn is_relative_path(path: &str) -> bool {
+    let path = Path::new(path);
+
+    path.is_relative()
+        && !path.as_os_str().is_empty()
+        && path
+            .components()
+            .all(|component| matches!(component, std::path::Component::Normal(_)))
+}
+
 fn path_to_string<S>(path: &Option<PathBuf>, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: serde::Serializer,
@@ -202,8 +496,8 @@ fn expand_tilde(path: PathBuf) -> PathBuf {
     }
 }
 
-pub fn read_config(config_path: Option<PathBuf>) -> Result<Config> {
-    let config_path = match config_path {
+fn resolve_config_path(config_path: Option<PathBuf>) -> PathBuf {
+    match config_path {
         Some(config_path) => expand_tilde(config_path),
         None => {
             trace!("No config path specified, using default config path");
@@ -217,20 +511,62 @@ pub fn read_config(config_path: Option<PathBuf>) -> Result<Config> {
                 Path::new(&home_dir).join(".config/binutils/config.lua")
             }
         }
-    };
+    }
+}
+
+fn empty_config() -> Config {
+    Config {
+        tmux: None,
+        shell_caching: None,
+        local_dotfiles: None,
+        crate_locations: None,
+    }
+}
+
+/// The directory used for on-disk caches (`$XDG_CACHE_HOME/binutils`, falling back to
+/// `~/.cache/binutils`).
+pub fn cache_dir() -> PathBuf {
+    match env::var("XDG_CACHE_HOME") {
+        Ok(xdg_cache_home) if !xdg_cache_home.is_empty() => {
+            PathBuf::from(xdg_cache_home).join("binutils")
+        }
+        _ => {
+            let home_dir = env::var("HOME").expect("HOME environment variable not set");
+
+            Path::new(&home_dir).join(".cache/binutils")
+        }
+    }
+}
+
+pub fn read_config(config_path: Option<PathBuf>) -> Result<Config> {
+    let config_path = resolve_config_path(config_path);
 
     if !config_path.is_file() {
-        return Ok(Config {
-            tmux: None,
-            shell_caching: None,
-            crate_locations: None,
-        });
+        return Ok(empty_config());
+    }
+
+    config_loader(&config_path).load()
+}
+
+/// Like [`read_config`], but reuses the previously evaluated config from [`cache_dir`] when none
+/// of the Lua files it loaded (or environment variables it read) have changed since.
+pub fn read_config_cached(config_path: Option<PathBuf>) -> Result<Config> {
+    let config_path = resolve_config_path(config_path);
+
+    if !config_path.is_file() {
+        return Ok(empty_config());
     }
 
-    let config: Config = lua_config_utils::read_config(&config_path)?;
-    config.validate(Some(&config_path))?;
+    config_loader(&config_path).load_cached(&cache_dir())
+}
+
+fn config_loader(config_path: &Path) -> LuaConfigLoader<Config> {
+    let validated_path = config_path.to_path_buf();
 
-    Ok(config)
+    LuaConfigLoader::new(config_path)
+        // `HOME` is used to expand `~` in window paths during deserialization
+        .track_env("HOME")
+        .validate(move |config: &Config| config.validate(Some(&validated_path)))
 }
 
 pub fn gather_crate_locations(config: &Config) -> Result<BTreeMap<String, PathBuf>> {
@@ -384,6 +720,7 @@ mod tests {
         Config {
             tmux: None,
             shell_caching: None,
+            local_dotfiles: None,
             crate_locations: None,
         }
         "###);
@@ -402,6 +739,7 @@ mod tests {
         Config {
             tmux: None,
             shell_caching: None,
+            local_dotfiles: None,
             crate_locations: None,
         }
         "###);
@@ -463,6 +801,7 @@ mod tests {
                 },
             ),
             shell_caching: None,
+            local_dotfiles: None,
             crate_locations: None,
         }
         "###);
@@ -599,6 +938,7 @@ mod tests {
                 },
             ),
             shell_caching: None,
+            local_dotfiles: None,
             crate_locations: None,
         }
         "###);
@@ -624,6 +964,7 @@ mod tests {
         Config {
             tmux: None,
             shell_caching: None,
+            local_dotfiles: None,
             crate_locations: None,
         }
         "###);
@@ -684,6 +1025,7 @@ mod tests {
                 },
             ),
             shell_caching: None,
+            local_dotfiles: None,
             crate_locations: None,
         }
         "###);
@@ -722,6 +1064,68 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_read_config_cached_matches_uncached() -> Result<()> {
+        let env = setup_test_environment();
+
+        fs::write(
+            &env.config_file,
+            r###"
+            return {
+                tmux = {
+                    sessions = {
+                        {
+                            name = "Test Session",
+                            windows = { { name = "Test Window", path = "~/some/path" } }
+                        }
+                    }
+                }
+            }
+            "###,
+        )?;
+
+        temp_env::with_var_unset("XDG_CACHE_HOME", || -> Result<()> {
+            let uncached = read_config(None)?;
+            let first = read_config_cached(None)?;
+            let second = read_config_cached(None)?;
+
+            assert_eq!(uncached, first);
+            assert_eq!(uncached, second);
+
+            Ok(())
+        })?;
+
+        let cache_files: Vec<String> = fixturify::read(&env.home)?
+            .into_keys()
+            .filter(|path| path.starts_with(".cache/"))
+            .map(|path| {
+                regex::Regex::new(r"config-\w+\.json")
+                    .unwrap()
+                    .replace(&path, "config-{key}.json")
+                    .to_string()
+            })
+            .collect();
+
+        assert_debug_snapshot!(cache_files, @r###"
+        [
+            ".cache/binutils/config-{key}.json",
+        ]
+        "###);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cache_dir_respects_xdg_cache_home() {
+        let env = setup_test_environment();
+
+        let default_dir = temp_env::with_var_unset("XDG_CACHE_HOME", cache_dir);
+        let xdg_dir = temp_env::with_var("XDG_CACHE_HOME", Some("/tmp/xdg-cache"), cache_dir);
+
+        assert_eq!(default_dir, env.home.join(".cache/binutils"));
+        assert_eq!(xdg_dir, PathBuf::from("/tmp/xdg-cache/binutils"));
+    }
+
     #[test]
     fn test_read_config_tmux_windows_without_path() {
         let env = setup_test_environment();
@@ -751,6 +1155,7 @@ mod tests {
         let expected = Config {
             crate_locations: None,
             shell_caching: None,
+            local_dotfiles: None,
             tmux: Some(Tmux {
                 default_session: Some("Test Session".to_string()),
                 sessions: vec![Session {
@@ -758,7 +1163,7 @@ mod tests {
                     windows: vec![Window {
                         name: "Test Window".to_string(),
                         path: None,
-                        command: Some(Command::Single("echo 'Hello, world!'".to_string())),
+                        command: Some(Command::Single("echo 'Hello, world!'".to_string()).into()),
                         env: None,
                         linked_crates: None,
                     }],
@@ -769,6 +1174,48 @@ mod tests {
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn test_read_config_lazy_window_command() -> Result<()> {
+        let env = setup_test_environment();
+
+        fs::write(
+            &env.config_file,
+            r###"
+        return function(ctx)
+            return {
+                tmux = {
+                    sessions = {
+                        {
+                            name = ctx.hostname,
+                            windows = {
+                                {
+                                    name = "editor",
+                                    command = function(ctx)
+                                        return { "cd " .. ctx.cwd, "nvim" }
+                                    end,
+                                },
+                            },
+                        },
+                    },
+                },
+            }
+        end
+        "###,
+        )?;
+
+        let config = read_config(None)?;
+        let window = &config.tmux.as_ref().unwrap().sessions[0].windows[0];
+        let command = window.command.as_ref().unwrap().resolve()?;
+
+        let cwd = env::current_dir()?.to_string_lossy().to_string();
+        assert_eq!(
+            command,
+            Command::Multiple(vec![format!("cd {}", cwd), "nvim".to_string()])
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn gather_crate_locations_with_tilde() -> Result<()> {
         let env = setup_test_environment();
@@ -796,6 +1243,7 @@ mod tests {
             crate_locations: Some(vec![String::from("~/workspace")]),
             tmux: None,
             shell_caching: None,
+            local_dotfiles: None,
         };
 
         let crates = gather_crate_locations(&config)?;
@@ -859,6 +1307,7 @@ mod tests {
             crate_locations: Some(vec![String::from("~/workspace")]),
             tmux: None,
             shell_caching: None,
+            local_dotfiles: None,
         };
 
         let crates = gather_crate_locations(&config)?;
@@ -901,6 +1350,7 @@ mod tests {
                 default_session: None,
             }),
             shell_caching: None,
+            local_dotfiles: None,
             crate_locations: None,
         };
 
@@ -941,10 +1391,246 @@ mod tests {
                 default_session: None,
             }),
             shell_caching: None,
+            local_dotfiles: None,
             crate_locations: None,
         };
 
         let config_path = Path::new("some/file/test.lua");
         assert!(config.validate(Some(config_path)).is_ok());
     }
+
+    #[test]
+    fn test_read_config_shell_caching_forms() {
+        let env = setup_test_environment();
+
+        fs::write(
+            &env.config_file,
+            r###"return { shell_caching = { source = "~/zsh", destination = "~/zsh/dist" } }"###,
+        )
+        .unwrap();
+        let config = read_config(None).expect("error reading from config");
+        assert_debug_snapshot!(config.shell_caching, @r###"
+        Some(
+            Single(
+                ShellCache {
+                    name: None,
+                    source: "~/zsh",
+                    destination: "~/zsh/dist",
+                    destination_strategy: None,
+                    include: None,
+                    exclude: None,
+                    target_shell: None,
+                    comment_prefixes: None,
+                    timeout: None,
+                    shell: None,
+                    cwd: None,
+                    env: None,
+                    allow_failure: None,
+                },
+            ),
+        )
+        "###);
+
+        fs::write(
+            &env.config_file,
+            r###"
+            return {
+                shell_caching = {
+                    { name = "zsh", source = "~/zsh", destination = "~/zsh/dist", shell = "zsh" },
+                    {
+                        name = "bash",
+                        source = "~/bash",
+                        destination = "~/bash/dist",
+                        destination_strategy = "merge",
+                        include = { "*.bash" },
+                    },
+                },
+            }"###,
+        )
+        .unwrap();
+        let config = read_config(None).expect("error reading from config");
+        assert_debug_snapshot!(config.shell_caching, @r###"
+        Some(
+            Multiple(
+                [
+                    ShellCache {
+                        name: Some(
+                            "zsh",
+                        ),
+                        source: "~/zsh",
+                        destination: "~/zsh/dist",
+                        destination_strategy: None,
+                        include: None,
+                        exclude: None,
+                        target_shell: None,
+                        comment_prefixes: None,
+                        timeout: None,
+                        shell: Some(
+                            "zsh",
+                        ),
+                        cwd: None,
+                        env: None,
+                        allow_failure: None,
+                    },
+                    ShellCache {
+                        name: Some(
+                            "bash",
+                        ),
+                        source: "~/bash",
+                        destination: "~/bash/dist",
+                        destination_strategy: Some(
+                            Merge,
+                        ),
+                        include: Some(
+                            [
+                                "*.bash",
+                            ],
+                        ),
+                        exclude: None,
+                        target_shell: None,
+                        comment_prefixes: None,
+                        timeout: None,
+                        shell: None,
+                        cwd: None,
+                        env: None,
+                        allow_failure: None,
+                    },
+                ],
+            ),
+        )
+        "###);
+    }
+
+    #[test]
+    fn test_read_config_shell_caching_reports_entry_errors() {
+        let env = setup_test_environment();
+
+        fs::write(
+            &env.config_file,
+            r###"return { shell_caching = { source = "~/zsh" } }"###,
+        )
+        .unwrap();
+        let err = read_config(None).unwrap_err();
+        assert_snapshot!(format!("{:#}", err), @r###"
+        deserialize error: missing field `destination`
+        "###);
+
+        fs::write(
+            &env.config_file,
+            r###"return { shell_caching = { { name = "zsh", destination = "~/zsh/dist" } } }"###,
+        )
+        .unwrap();
+        let err = read_config(None).unwrap_err();
+        assert_snapshot!(format!("{:#}", err), @r###"
+        deserialize error: missing field `source`
+        "###);
+    }
+
+    #[test]
+    fn test_config_validation_shell_caching_entry_names() {
+        let entry = |name: Option<&str>| ShellCache {
+            name: name.map(str::to_string),
+            source: "~/zsh".to_string(),
+            destination: "~/zsh/dist".to_string(),
+            ..Default::default()
+        };
+        let config = Config {
+            tmux: None,
+            shell_caching: Some(ShellCaching::Multiple(vec![
+                entry(Some("zsh")),
+                entry(None),
+                entry(Some("zsh")),
+            ])),
+            local_dotfiles: None,
+            crate_locations: None,
+        };
+
+        let err = config.validate(None).unwrap_err();
+        assert_snapshot!(err.to_string(), @r###"
+        Configuration validation failed
+
+        Issues found:
+        - shell_caching entry at index 1 (~/zsh) has no name
+        - Duplicate shell_caching entry name 'zsh' found at indices 0 and 2
+        "###);
+
+        let config = Config {
+            shell_caching: Some(ShellCaching::Single(Box::new(entry(None)))),
+            ..config
+        };
+        assert!(config.validate(None).is_ok());
+    }
+
+    #[test]
+    fn test_config_validation_local_dotfiles() {
+        let symlink = |source: &str, target: &str| Symlink {
+            source: source.to_string(),
+            target: target.to_string(),
+        };
+        let config = Config {
+            tmux: None,
+            shell_caching: None,
+            local_dotfiles: Some(LocalDotfiles {
+                files: Some(BTreeMap::from([
+                    ("/etc/profile".to_string(), String::new()),
+                    ("nvim/init.lua".to_string(), String::new()),
+                ])),
+                symlinks: Some(vec![
+                    symlink("nvim", "~/.config/nvim/lua/local_nvim"),
+                    symlink("../nvim", "~/.config/nvim/lua/local_nvim"),
+                ]),
+            }),
+            crate_locations: None,
+        };
+
+        let err = config.validate(None).unwrap_err();
+        assert_snapshot!(err.to_string(), @r###"
+        Configuration validation failed
+
+        Issues found:
+        - local_dotfiles file '/etc/profile' must be relative to the local dotfiles directory
+        - local_dotfiles symlink source '../nvim' must be relative to the local dotfiles directory
+        - Duplicate local_dotfiles symlink target '~/.config/nvim/lua/local_nvim' found at indices 0 and 1
+        "###);
+
+        let config = Config {
+            local_dotfiles: Some(LocalDotfiles::default()),
+            ..config
+        };
+        assert!(config.validate(None).is_ok());
+    }
+
+    #[test]
+    fn test_shell_caching_select() -> Result<()> {
+        let entry = |name: &str| ShellCache {
+            name: Some(name.to_string()),
+            ..Default::default()
+        };
+        let shell_caching = ShellCaching::Multiple(vec![entry("zsh"), entry("bash")]);
+
+        let names = |entries: Vec<&ShellCache>| -> Vec<Option<String>> {
+            entries
+                .into_iter()
+                .map(|entry| entry.name.clone())
+                .collect()
+        };
+        assert_eq!(
+            names(shell_caching.select(&[])?),
+            [Some("zsh".to_string()), Some("bash".to_string())]
+        );
+        assert_eq!(
+            names(shell_caching.select(&["bash".to_string()])?),
+            [Some("bash".to_string())]
+        );
+
+        let err = shell_caching.select(&["fish".to_string()]).unwrap_err();
+        assert_snapshot!(err.to_string(), @"Unknown shell_caching entry `fish` (configured entries: `zsh`, `bash`)");
+
+        let err = ShellCaching::Single(Box::default())
+            .select(&["zsh".to_string()])
+            .unwrap_err();
+        assert_snapshot!(err.to_string(), @"Unknown shell_caching entry `zsh` (configured entries: none with a name)");
+
+        Ok(())
+    }
 }
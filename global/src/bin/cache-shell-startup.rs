@@ -1,199 +1,14 @@
 use anyhow::{Context, Result};
-use clap::{Parser, ValueEnum};
-use std::fs::{self, File};
-use std::io::{BufRead, BufReader, Write};
+use clap::Parser;
+use config::{DestinationStrategy, ShellCache};
+use shared_global::shell_cache::{
+    CommandOptions, FetchCache, FileFilter, OutputCache, ProcessOptions, SyntaxOptions,
+    TargetShell, diff_directories, install, process_directory, rollback,
+};
 use std::path::{Path, PathBuf};
-use std::process::Command;
-use tracing::{debug, info, trace};
+use tracing::info;
 use tracing_subscriber::EnvFilter;
 
-#[derive(Debug)]
-enum LineAction {
-    Command { command: String, silent: bool },
-    Fetch(String),
-    Other(String),
-}
-
-fn parse_line(line: &str) -> LineAction {
-    let trimmed_line = line.trim_start();
-
-    if let Some(command) = trimmed_line.strip_prefix("# CMD:") {
-        LineAction::Command {
-            command: command.trim().to_string(),
-            silent: false,
-        }
-    } else if let Some(command) = trimmed_line.strip_prefix("# CMD_SILENT:") {
-        LineAction::Command {
-            command: command.trim().to_string(),
-            silent: true,
-        }
-    } else if let Some(url) = trimmed_line.strip_prefix("# FETCH:") {
-        LineAction::Fetch(url.trim().to_string())
-    } else {
-        LineAction::Other(line.to_string())
-    }
-}
-
-fn handle_command(command: String, silent: bool) -> Result<Vec<String>> {
-    let mut result = Vec::new();
-
-    if !silent {
-        result.push(format!("# CMD: {}", &command));
-    }
-
-    trace!("Running command: {}", &command);
-
-    let output = Command::new("sh")
-        .arg("-c")
-        .arg(&command)
-        .output()
-        .context(format!("Failed to execute command (`{}`)", &command))?;
-
-    if output.status.success() {
-        let output_str = String::from_utf8_lossy(&output.stdout);
-
-        if silent {
-            result.push(output_str.to_string());
-        } else {
-            result.push(format!(
-                "# OUTPUT START: {}\n{}\n# OUTPUT END: {}",
-                &command, output_str, &command
-            ));
-        }
-        Ok(result)
-    } else {
-        let error_message = format!(
-            "Failed to run command (`{}`):\n{}",
-            &command,
-            String::from_utf8_lossy(&output.stderr)
-        );
-        anyhow::bail!("{}", error_message);
-    }
-}
-
-fn handle_fetch(url: String) -> Result<Vec<String>> {
-    let mut result = Vec::new();
-    result.push(format!("# FETCH: {}", &url));
-    trace!("Fetching URL: {}", &url);
-
-    let response = ureq::get(&url)
-        .call()
-        .context(format!("Failed to fetch URL: {}", &url))?;
-
-    if response.status() == 200 {
-        let content = response
-            .into_body()
-            .read_to_string()
-            .context("Failed to read response content")?;
-        result.push(format!(
-            "# FETCHED CONTENT START: {}\n{}\n# FETCHED CONTENT END: {}",
-            &url, content, &url
-        ));
-        Ok(result)
-    } else {
-        let status = response.status();
-        let error_body = response
-            .into_body()
-            .read_to_string()
-            .unwrap_or_else(|_| "Failed to read error response body".to_string());
-        anyhow::bail!(
-            "Failed to fetch URL '{}' (Status Code: {}):\nError Body: {}",
-            &url,
-            status,
-            error_body
-        );
-    }
-}
-
-fn process_file<S: AsRef<Path>>(source_file: S, dest_file: S) -> Result<()> {
-    let source_file = source_file.as_ref();
-    let dest_file = dest_file.as_ref();
-
-    debug!("Processing file: {}", source_file.display());
-
-    let file = File::open(source_file).context("Failed to open file for reading")?;
-    let reader = BufReader::new(file);
-
-    let mut new_content = Vec::new();
-
-    for line in reader.lines() {
-        let line = line.context("Failed to read line")?;
-        match parse_line(&line) {
-            LineAction::Command { command, silent } => {
-                let lines = handle_command(command, silent)?;
-                new_content.extend(lines);
-            }
-            LineAction::Fetch(url) => {
-                let lines = handle_fetch(url)?;
-                new_content.extend(lines);
-            }
-            LineAction::Other(line) => {
-                new_content.push(line);
-            }
-        }
-    }
-
-    if let Some(parent) = dest_file.parent() {
-        fs::create_dir_all(parent)
-            .with_context(|| format!("Failed to create directories for path: {:?}", parent))?;
-    }
-
-    debug!("Writing to file: {}", dest_file.display());
-    trace!("New content:\n{}", new_content.join("\n"));
-
-    let mut output_file = File::create(dest_file)
-        .with_context(|| format!("Failed to open file for writing: {}", dest_file.display()))?;
-
-    for line in new_content {
-        writeln!(output_file, "{}", line).context("Failed to write line")?;
-    }
-
-    output_file.flush().context("Failed to flush file")?;
-
-    Ok(())
-}
-
-fn process_directory(source_dir: &Path, dest_dir: &Path, final_dest_dir: &Path) -> Result<()> {
-    info!("Scanning directory: {}", source_dir.display());
-
-    for entry in fs::read_dir(source_dir)
-        .with_context(|| format!("Failed to read directory: {}", source_dir.display()))?
-    {
-        let entry = entry.context("Failed to process directory entry")?;
-        let path = entry.path();
-
-        if path.starts_with(final_dest_dir) {
-            continue;
-        }
-
-        if path.is_dir() {
-            let relative_path = path
-                .strip_prefix(source_dir)
-                .context("Failed to get relative path")?;
-            let new_dest_dir = dest_dir.join(relative_path);
-
-            fs::create_dir_all(&new_dest_dir).context("Failed to create destination directory")?;
-            process_directory(&path, &new_dest_dir, final_dest_dir)
-                .context(format!("Failed to process directory {:?}", path))?;
-        } else {
-            let relative_path = path
-                .strip_prefix(source_dir)
-                .context("Failed to get relative path")?;
-            let dest_file = dest_dir.join(relative_path);
-            process_file(&path, &dest_file)
-                .context(format!("Failed to process file {:?}", path))?;
-        }
-    }
-    Ok(())
-}
-
-/// Represents whether to clear the destination directory
-#[derive(ValueEnum, Clone, Debug, PartialEq)]
-enum DestinationStrategy {
-    Clear,
-    Merge,
-}
-
 /// cache-shell-setup
 ///
 /// Processes .zsh files to cache environment variables by running commands
@@ -202,6 +17,43 @@ enum DestinationStrategy {
 /// It processes all `.zsh` files in a specified directory, looks for specific
 /// commands (e.g., `# CMD:`), executes them, and stores their output directly
 /// in the `.zsh` files, ensuring the operation is idempotent.
+///
+/// Commands with a TTL (e.g., `# CMD[ttl=7d]: brew shellenv`) reuse their
+/// output from a previous run until it is older than the TTL, while
+/// `# EVAL_ONCE: ...` commands reuse it until `--refresh`. Fetched URLs
+/// are cached (and revalidated), and can be pinned to a checksum (e.g.,
+/// `# FETCH[sha256=...]: https://...`).
+///
+/// Files can also `# INCLUDE:` other files (which are processed too), inline
+/// a file verbatim with `# FILE:`, `export` a command's output with
+/// `# ENV: NAME=command`, and wrap lines in `# IF: <condition>` / `# ENDIF`
+/// blocks that are only kept when the condition succeeds.
+///
+/// Commands can be given a timeout, shell, working directory, additional
+/// environment variables and be allowed to fail (in which case the error is
+/// emitted as a comment), e.g. `# CMD[timeout=10s,shell=zsh]: ...`. The
+/// defaults for these can be set in the config file.
+///
+/// Files whose source hasn't changed since the previous run (and whose
+/// commands all have a TTL or are `# EVAL_ONCE:`, with their output still
+/// cached) are copied from the destination as is, according to the manifest
+/// that is kept within it.
+///
+/// File permissions are preserved, and symlinks and files that aren't text
+/// are copied as is (as are files that don't match `--include`, or do match
+/// `--exclude`).
+///
+/// Startup files of sh, bash and fish are supported as well as zsh ones.
+/// The shell of each file is detected from its name (e.g. `config.fish`,
+/// `bashrc` or `*.bash`), falling back to `--target-shell`, and determines
+/// the syntax of generated lines (e.g. `set -gx` instead of `export` for
+/// `# ENV:` in fish). Directives in files with other comment syntax (e.g.
+/// `-- CMD: ...` in Lua files) can be enabled with `comment_prefixes` in the
+/// config file.
+///
+/// The config file can list several (named) entries, each with their own
+/// source, destination and options, which are all processed unless `--only`
+/// selects some of them.
 #[derive(Parser, Debug)]
 #[command(name = "cache-shell-setup")]
 struct Args {
@@ -217,315 +69,269 @@ struct Args {
     #[clap(short, long)]
     destination: Option<String>,
 
-    /// Whether to clear the destination directory before processing
-    #[clap(value_enum, long, default_value_t = DestinationStrategy::Clear)]
-    destination_strategy: DestinationStrategy,
-}
+    /// Whether to clear the destination directory before processing. Overrides
+    /// `destination_strategy` in the config file, and defaults to `clear`.
+    #[clap(value_enum, long)]
+    destination_strategy: Option<DestinationStrategy>,
 
-fn run(args: Vec<String>) -> Result<()> {
-    let args = Args::parse_from(args);
-    let config_file = args.config_file.as_ref().map(PathBuf::from);
-    let config = config::read_config(config_file)?;
+    /// Name of the `shell_caching` entry (in the config file) to process, can be repeated.
+    /// Defaults to every entry.
+    #[arg(long)]
+    only: Vec<String>,
 
-    let source_dir = if let Some(source) = args.source {
-        source
-    } else if let Some(shell_caching) = &config.shell_caching {
-        shell_caching.source.clone()
-    } else {
-        anyhow::bail!(
-            "No source directory provided. Either use the --source flag or set it in the config file. \nArgs: {:?} \nConfig: {:?}",
-            args,
-            config
-        );
-    };
+    /// Always evaluate the Lua config instead of reusing the cached result.
+    #[arg(long)]
+    no_config_cache: bool,
 
-    let destination_dir = if let Some(destination) = args.destination {
-        destination
-    } else if let Some(shell_caching) = &config.shell_caching {
-        shell_caching.destination.clone()
-    } else {
-        anyhow::bail!(
-            "No source directory provided. Either use the --source flag or set it in the config file"
-        );
-    };
+    /// Rerun every command, even when a previous output is still within its TTL (or the file it is
+    /// in hasn't changed since the previous run).
+    #[arg(long)]
+    refresh: bool,
 
-    let source_dir = shellexpand::tilde(&source_dir).to_string();
-    let source_dir = Path::new(&source_dir);
+    /// Never download `# FETCH:` URLs, use the content cached by previous runs instead (failing
+    /// for URLs that haven't been cached yet).
+    #[arg(long)]
+    offline: bool,
 
-    let dest_dir = shellexpand::tilde(&destination_dir).to_string();
-    let dest_dir = Path::new(&dest_dir);
+    /// Only report whether the destination is out of date (printing a diff for every stale
+    /// file), without changing it. Exits with an error when it is out of date.
+    #[arg(long)]
+    check: bool,
 
-    let temp_dest_dir = tempfile::tempdir()?;
-    let temp_dest_dir = temp_dest_dir.path();
+    /// Restore the previous output (kept next to the destination, e.g. `dist.backup`) instead of
+    /// processing the source directory. Rolling back again undoes the rollback.
+    #[arg(long)]
+    rollback: bool,
 
-    process_directory(source_dir, temp_dest_dir, dest_dir)
-        .context("Failed to process directory")?;
+    /// Glob pattern (relative to the source directory) of files whose directives are processed,
+    /// can be repeated. Overrides `include` in the config file. Defaults to every file.
+    #[arg(long)]
+    include: Vec<String>,
 
-    if args.destination_strategy == DestinationStrategy::Clear && dest_dir.exists() {
-        info!("Clearing destination directory");
-        fs::remove_dir_all(dest_dir).context("Failed to clear destination directory")?;
-    }
+    /// Glob pattern (relative to the source directory) of files that are copied as is, can be
+    /// repeated. Overrides `exclude` in the config file.
+    #[arg(long)]
+    exclude: Vec<String>,
 
-    copy_recursively(temp_dest_dir, dest_dir)?;
+    /// The shell (`zsh`, `bash`, `sh` or `fish`) that sources the files whose name doesn't identify
+    /// one. Overrides `target_shell` in the config file, and defaults to `zsh`.
+    #[arg(long)]
+    target_shell: Option<String>,
 
-    Ok(())
+    /// Maximum number of commands and fetches to run concurrently. Defaults to the number of
+    /// available CPUs.
+    #[arg(short, long)]
+    jobs: Option<usize>,
 }
 
-fn copy_recursively(src: &Path, dest: &Path) -> Result<()> {
-    fs::create_dir_all(dest)?;
-    for entry in fs::read_dir(src)? {
-        let entry = entry?;
-        let file_type = entry.file_type()?;
-        let dest_path = dest.join(entry.file_name());
-        if file_type.is_dir() {
-            copy_recursively(&entry.path(), &dest_path)?;
-        } else {
-            fs::copy(entry.path(), dest_path)?;
+fn run(args: Vec<String>) -> Result<()> {
+    let args = Args::parse_from(args);
+    let config_file = args.config_file.as_ref().map(PathBuf::from);
+    let config = if args.no_config_cache {
+        config::read_config(config_file)?
+    } else {
+        config::read_config_cached(config_file)?
+    };
+
+    let mut out_of_date = Vec::new();
+    for entry in selected_entries(&args, &config)? {
+        if let Some(name) = &entry.name {
+            info!("Processing shell_caching entry `{}`", name);
+        }
+
+        if let Some(stale) = run_entry(&args, &entry)? {
+            out_of_date.push(stale);
         }
     }
 
+    if !out_of_date.is_empty() {
+        anyhow::bail!(
+            "{} are out of date, run without `--check` to update them",
+            out_of_date.join(", ")
+        );
+    }
+
     Ok(())
 }
 
-fn main() -> Result<()> {
-    // Initialize tracing, use `info` by default
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
-        )
-        .init();
-
-    latest_bin::ensure_latest_bin()?;
+/// The `shell_caching` entries to process, with the `--source` and `--destination` flags applied
+/// (to the only selected entry, if any), as well as `--target-shell`.
+fn selected_entries(args: &Args, config: &config::Config) -> Result<Vec<ShellCache>> {
+    let mut entries = configured_entries(args, config)?;
+    if let Some(target_shell) = &args.target_shell {
+        TargetShell::parse(target_shell).context("Invalid `--target-shell`")?;
+        for entry in &mut entries {
+            entry.target_shell = Some(target_shell.clone());
+        }
+    }
 
-    let args: Vec<String> = std::env::args().collect();
-    run(args)
+    Ok(entries)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use insta::{assert_debug_snapshot, assert_snapshot};
-    use pretty_assertions::assert_eq;
-    use std::collections::BTreeMap;
-    use std::fs::write;
-    use tempfile::tempdir;
-    use test_utils::setup_test_environment;
-
-    #[test]
-    fn test_parse_command() {
-        assert_debug_snapshot!(parse_line("# CMD: echo hello"), @r###"
-        Command {
-            command: "echo hello",
-            silent: false,
+fn configured_entries(args: &Args, config: &config::Config) -> Result<Vec<ShellCache>> {
+    let mut entries: Vec<ShellCache> = match &config.shell_caching {
+        Some(shell_caching) => shell_caching
+            .select(&args.only)?
+            .into_iter()
+            .cloned()
+            .collect(),
+        None if !args.only.is_empty() => {
+            anyhow::bail!("No shell_caching entries are configured, so `--only` can't be used")
         }
-        "###);
+        None => Vec::new(),
+    };
 
-        assert_debug_snapshot!(parse_line("   # CMD: echo hello"), @r###"
-        Command {
-            command: "echo hello",
-            silent: false,
-        }
-        "###);
+    if args.source.is_none() && args.destination.is_none() && !entries.is_empty() {
+        return Ok(entries);
     }
 
-    #[test]
-    fn test_parse_command_silent() {
-        assert_debug_snapshot!(parse_line("# CMD_SILENT: echo hello"), @r###"
-        Command {
-            command: "echo hello",
-            silent: true,
-        }
-        "###);
-
-        assert_debug_snapshot!(parse_line("     # CMD_SILENT: echo hello"), @r###"
-        Command {
-            command: "echo hello",
-            silent: true,
-        }
-        "###);
+    if entries.len() > 1 {
+        anyhow::bail!(
+            "The --source and --destination flags can only be used with a single shell_caching entry, use --only to select one"
+        );
     }
 
-    #[test]
-    fn test_parse_fetch() {
-        assert_debug_snapshot!(parse_line("# FETCH: http://example.com"), @r###"
-        Fetch(
-            "http://example.com",
-        )
-        "###);
-
-        assert_debug_snapshot!(parse_line("    # FETCH: http://example.com"), @r###"
-        Fetch(
-            "http://example.com",
-        )
-        "###);
+    let mut entry = entries.pop().unwrap_or_default();
+    if let Some(source) = &args.source {
+        entry.source = source.clone();
     }
-
-    #[test]
-    fn test_parse_other() {
-        assert_debug_snapshot!(parse_line("This is a regular line"), @r###"
-        Other(
-            "This is a regular line",
-        )
-        "###);
-
-        assert_debug_snapshot!(parse_line("      This is a regular line"), @r###"
-        Other(
-            "      This is a regular line",
-        )
-        "###);
+    if let Some(destination) = &args.destination {
+        entry.destination = destination.clone();
     }
 
-    #[test]
-    fn test_process_file_with_valid_command() -> Result<()> {
-        let dir = tempdir()?;
-        let source_file = dir.path().join("test.zsh");
-        let dest_file = dir.path().join("output.zsh");
-
-        let content = "# CMD: echo 'hello world'\n";
-        write(&source_file, content)?;
-
-        process_file(&source_file, &dest_file)?;
-
-        let source_contents = fs::read_to_string(&source_file)?;
-
-        assert_snapshot!(source_contents, @r###"
-        # CMD: echo 'hello world'
-        "###);
-
-        let processed_content = fs::read_to_string(&dest_file)?;
-        assert_snapshot!(processed_content, @r###"
-        # CMD: echo 'hello world'
-        # OUTPUT START: echo 'hello world'
-        hello world
-
-        # OUTPUT END: echo 'hello world'
-        "###);
+    Ok(vec![entry])
+}
 
-        Ok(())
+/// Processes a single `shell_caching` entry. With `--check`, returns a description of the out of
+/// date files (if any) instead of updating the destination.
+fn run_entry(args: &Args, entry: &ShellCache) -> Result<Option<String>> {
+    if entry.destination.is_empty() {
+        anyhow::bail!(
+            "No destination directory provided. Either use the --destination flag or set it in the config file"
+        );
     }
 
-    #[test]
-    fn test_process_file_with_silent_command() -> Result<()> {
-        let dir = tempdir()?;
-        let source_file = dir.path().join("test.zsh");
-        let dest_file = dir.path().join("output.zsh");
-
-        let content = "# CMD_SILENT: echo 'hello world'\n";
-        write(&source_file, content)?;
-
-        process_file(&source_file, &dest_file)?;
-
-        let source_contents = fs::read_to_string(&source_file)?;
-
-        assert_snapshot!(source_contents, @r###"
-        # CMD_SILENT: echo 'hello world'
-        "###);
-
-        let processed_content = fs::read_to_string(&dest_file)?;
-        assert_snapshot!(processed_content, @r###"
-        hello world
-
-        "###);
+    let dest_dir = shellexpand::tilde(&entry.destination).to_string();
+    let dest_dir = Path::new(&dest_dir);
 
-        Ok(())
+    if args.rollback {
+        rollback(dest_dir)?;
+        return Ok(None);
     }
 
-    #[test]
-    fn test_process_file_with_existing_output() -> Result<()> {
-        let dir = tempdir()?;
-        let source_file = dir.path().join("test.zsh");
-        let dest_file = dir.path().join("output.zsh");
-
-        write(&source_file, "# CMD: echo 'hello world'\n")?;
-        write(
-            &dest_file,
-            "# CMD: echo 'hello world'\n# OUTPUT START: echo 'hello world'\nold output\n# OUTPUT END: echo 'hello world'\n",
-        )?;
-
-        process_file(&source_file, &dest_file)?;
+    if entry.source.is_empty() {
+        anyhow::bail!(
+            "No source directory provided. Either use the --source flag or set it in the config file. \nArgs: {:?} \nEntry: {:?}",
+            args,
+            entry
+        );
+    }
 
-        let source_contents = fs::read_to_string(&source_file)?;
+    let source_dir = shellexpand::tilde(&entry.source).to_string();
+    let source_dir = Path::new(&source_dir);
 
-        assert_snapshot!(source_contents, @r###"
-        # CMD: echo 'hello world'
-        "###);
+    let destination_strategy = args
+        .destination_strategy
+        .or(entry.destination_strategy)
+        .unwrap_or_default();
 
-        let processed_content = fs::read_to_string(&dest_file)?;
-        assert_snapshot!(processed_content, @r###"
-        # CMD: echo 'hello world'
-        # OUTPUT START: echo 'hello world'
-        hello world
+    let temp_dest_dir = tempfile::tempdir()?;
+    let temp_dest_dir = temp_dest_dir.path();
 
-        # OUTPUT END: echo 'hello world'
-        "###);
+    let include = if args.include.is_empty() {
+        entry.include.clone().unwrap_or_default()
+    } else {
+        args.include.clone()
+    };
+    let exclude = if args.exclude.is_empty() {
+        entry.exclude.clone().unwrap_or_default()
+    } else {
+        args.exclude.clone()
+    };
 
-        Ok(())
+    // `--check` must compare fresh output, so never reuse cached output or previously generated
+    // files
+    let mut options = ProcessOptions {
+        output_cache: Some(
+            OutputCache::new(config::cache_dir().join("shell-cache"))
+                .refresh(args.refresh || args.check),
+        ),
+        fetch_cache: Some(
+            FetchCache::new(config::cache_dir().join("fetch-cache")).offline(args.offline),
+        ),
+        incremental: !args.refresh && !args.check,
+        filter: FileFilter::new(&include, &exclude)?,
+        command_defaults: CommandOptions::from_config(entry)?,
+        syntax: SyntaxOptions::from_config(entry)?,
+        ..Default::default()
+    };
+    if let Some(jobs) = args.jobs {
+        options.jobs = jobs;
     }
 
-    #[test]
-    fn test_process_file_with_invalid_command() -> Result<()> {
-        let dir = tempdir()?;
-        let source_file = dir.path().join("test.zsh");
-        let dest_file = dir.path().join("output.zsh");
-
-        let content = "# CMD: invalidcommand\n";
-        write(&source_file, content)?;
-
-        // Process the file (should not panic, just print error)
-        let result = process_file(&source_file, &dest_file);
-
-        let err = result.unwrap_err();
-
-        let error_alternate_output = format!("{:#}", err);
+    process_directory(source_dir, temp_dest_dir, dest_dir, &options)
+        .context("Failed to process directory")?;
 
-        // the alternate output is not stable between local and CI, so we can't use snapshots here
-        assert!(
-            error_alternate_output.contains("Failed to run command (`invalidcommand`)"),
-            "Error output: {}",
-            error_alternate_output
-        );
-        assert!(
-            error_alternate_output.contains("not found"),
-            "Error output: {}",
-            error_alternate_output
-        );
+    if args.check {
+        let diffs = diff_directories(
+            temp_dest_dir,
+            dest_dir,
+            destination_strategy == DestinationStrategy::Clear,
+        )?;
+        if diffs.is_empty() {
+            info!("{} is up to date", dest_dir.display());
+            return Ok(None);
+        }
 
-        Ok(())
+        for diff in &diffs {
+            print!("{}", diff);
+        }
+        return Ok(Some(format!(
+            "{} file(s) in {}",
+            diffs.len(),
+            dest_dir.display()
+        )));
     }
 
-    #[test]
-    fn test_process_directory() {
-        let temp_dir = tempdir().unwrap();
-        let base_dir = temp_dir.path();
-
-        let source_files: BTreeMap<String, String> = BTreeMap::from([
-            (
-                "zsh/zshrc".to_string(),
-                "# CMD: echo 'hello world'\n".to_string(),
-            ),
-            (
-                "zsh/plugins/thing.zsh".to_string(),
-                "# CMD: echo 'goodbye world'\n".to_string(),
-            ),
-        ]);
+    install(
+        temp_dest_dir,
+        dest_dir,
+        destination_strategy == DestinationStrategy::Merge,
+    )?;
 
-        fixturify::write(base_dir, &source_files).unwrap();
+    Ok(None)
+}
 
-        let source_dir = base_dir.join("zsh");
-        let dest_dir = base_dir.join("zsh/dist");
+fn main() -> Result<()> {
+    // Initialize tracing, use `info` by default
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
 
-        process_directory(&source_dir, &dest_dir, &dest_dir).unwrap();
+    latest_bin::ensure_latest_bin()?;
 
-        let file_map = fixturify::read(base_dir).unwrap();
+    let args: Vec<String> = std::env::args().collect();
+    run(args)
+}
 
-        assert_debug_snapshot!(file_map, @r###"
-        {
-            "zsh/dist/plugins/thing.zsh": "# CMD: echo 'goodbye world'\n# OUTPUT START: echo 'goodbye world'\ngoodbye world\n\n# OUTPUT END: echo 'goodbye world'\n",
-            "zsh/dist/zshrc": "# CMD: echo 'hello world'\n# OUTPUT START: echo 'hello world'\nhello world\n\n# OUTPUT END: echo 'hello world'\n",
-            "zsh/plugins/thing.zsh": "# CMD: echo 'goodbye world'\n",
-            "zsh/zshrc": "# CMD: echo 'hello world'\n",
-        }
-        "###)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use insta::{assert_debug_snapshot, assert_snapshot};
+    use pretty_assertions::assert_eq;
+    use shared_global::shell_cache::MANIFEST_FILE;
+    use std::collections::BTreeMap;
+    use std::fs;
+    use test_utils::{setup_test_environment, stabilize_home_paths};
+
+    /// Reads every file within `dir`, except for manifests (whose hashes would only add noise to
+    /// the snapshots).
+    fn read_files(dir: &Path) -> BTreeMap<String, String> {
+        let mut files = fixturify::read(dir).unwrap();
+        files.retain(|path, _| !path.ends_with(MANIFEST_FILE));
+        files
     }
 
     #[test]
@@ -556,10 +362,11 @@ mod tests {
         ])
         .unwrap();
 
-        let file_map = fixturify::read(&env.home).unwrap();
+        let file_map = read_files(&env.home);
 
         assert_debug_snapshot!(file_map, @r###"
         {
+            "src/rwjblue/dotfiles/zsh/dist.backup/plugins/thing.zsh": "# CMD: echo 'goodbye world'\n# OLD OUTPUT SHOULD BE DELETED",
             "src/rwjblue/dotfiles/zsh/dist/plugins/thing.zsh": "# CMD: echo 'goodbye world'\n# OUTPUT START: echo 'goodbye world'\ngoodbye world\n\n# OUTPUT END: echo 'goodbye world'\n",
             "src/rwjblue/dotfiles/zsh/dist/zshrc": "# CMD: echo 'hello world'\n# OUTPUT START: echo 'hello world'\nhello world\n\n# OUTPUT END: echo 'hello world'\n",
             "src/rwjblue/dotfiles/zsh/plugins/thing.zsh": "# CMD: echo 'goodbye world'\n",
@@ -623,7 +430,7 @@ mod tests {
             assert!(err_output.contains("not found"));
         }
 
-        let file_map = fixturify::read(&env.home).unwrap();
+        let file_map = read_files(&env.home);
 
         assert_eq!(file_map, source_files);
     }
@@ -656,13 +463,18 @@ mod tests {
 
         fixturify::write(&env.home, &source_files).unwrap();
 
-        run(vec![]).unwrap();
+        run(vec![
+            "cache-shell-setup".to_string(),
+            "--no-config-cache".to_string(),
+        ])
+        .unwrap();
 
-        let file_map = fixturify::read(&env.home).unwrap();
+        let file_map = read_files(&env.home);
 
         assert_debug_snapshot!(file_map, @r###"
         {
             ".config/binutils/config.lua": "return { shell_caching = { source = \"~/other-path/zsh\", destination = \"~/other-path/zsh/dist\" } }",
+            "other-path/zsh/dist.backup/plugins/thing.zsh": "# CMD: echo 'goodbye world'\n# OLD OUTPUT SHOULD BE DELETED",
             "other-path/zsh/dist/plugins/thing.zsh": "# CMD: echo 'goodbye world'\n# OUTPUT START: echo 'goodbye world'\ngoodbye world\n\n# OUTPUT END: echo 'goodbye world'\n",
             "other-path/zsh/dist/zshrc": "# CMD: echo 'hello world'\n# OUTPUT START: echo 'hello world'\nhello world\n\n# OUTPUT END: echo 'hello world'\n",
             "other-path/zsh/plugins/thing.zsh": "# CMD: echo 'goodbye world'\n",
@@ -671,6 +483,168 @@ mod tests {
         "###)
     }
 
+    #[test]
+    fn test_run_with_multiple_config_entries() {
+        let env = setup_test_environment();
+
+        fs::write(
+            &env.config_file,
+            r###"
+            return {
+                shell_caching = {
+                    { name = "zsh", source = "~/zsh", destination = "~/zsh/dist" },
+                    {
+                        name = "bash",
+                        source = "~/bash",
+                        destination = "~/bash/dist",
+                        destination_strategy = "merge",
+                        shell = "bash",
+                    },
+                },
+            }"###,
+        )
+        .expect("Could not write to config file");
+
+        let source_files: BTreeMap<String, String> = BTreeMap::from([
+            (
+                "zsh/zshrc".to_string(),
+                "# CMD: echo 'hello zsh'\n".to_string(),
+            ),
+            (
+                "bash/bashrc".to_string(),
+                "# CMD: echo \"hello $0\"\n".to_string(),
+            ),
+            (
+                "bash/dist/unrelated".to_string(),
+                "# KEPT BY MERGING\n".to_string(),
+            ),
+        ]);
+        fixturify::write(&env.home, &source_files).unwrap();
+
+        run(vec![
+            "cache-shell-setup".to_string(),
+            "--no-config-cache".to_string(),
+            "--only=bash".to_string(),
+        ])
+        .unwrap();
+
+        let mut file_map = read_files(&env.home);
+        file_map.remove(".config/binutils/config.lua");
+        assert_debug_snapshot!(file_map, @r###"
+        {
+            "bash/bashrc": "# CMD: echo \"hello $0\"\n",
+            "bash/dist.backup/unrelated": "# KEPT BY MERGING\n",
+            "bash/dist/bashrc": "# CMD: echo \"hello $0\"\n# OUTPUT START: echo \"hello $0\"\nhello bash\n\n# OUTPUT END: echo \"hello $0\"\n",
+            "bash/dist/unrelated": "# KEPT BY MERGING\n",
+            "zsh/zshrc": "# CMD: echo 'hello zsh'\n",
+        }
+        "###);
+
+        run(vec![
+            "cache-shell-setup".to_string(),
+            "--no-config-cache".to_string(),
+        ])
+        .unwrap();
+
+        let mut file_map = read_files(&env.home);
+        file_map.remove(".config/binutils/config.lua");
+        assert_debug_snapshot!(file_map, @r###"
+        {
+            "bash/bashrc": "# CMD: echo \"hello $0\"\n",
+            "bash/dist.backup/bashrc": "# CMD: echo \"hello $0\"\n# OUTPUT START: echo \"hello $0\"\nhello bash\n\n# OUTPUT END: echo \"hello $0\"\n",
+            "bash/dist.backup/unrelated": "# KEPT BY MERGING\n",
+            "bash/dist/bashrc": "# CMD: echo \"hello $0\"\n# OUTPUT START: echo \"hello $0\"\nhello bash\n\n# OUTPUT END: echo \"hello $0\"\n",
+            "bash/dist/unrelated": "# KEPT BY MERGING\n",
+            "zsh/dist/zshrc": "# CMD: echo 'hello zsh'\n# OUTPUT START: echo 'hello zsh'\nhello zsh\n\n# OUTPUT END: echo 'hello zsh'\n",
+            "zsh/zshrc": "# CMD: echo 'hello zsh'\n",
+        }
+        "###);
+
+        let err = run(vec![
+            "cache-shell-setup".to_string(),
+            "--no-config-cache".to_string(),
+            "--only=fish".to_string(),
+        ])
+        .unwrap_err();
+        assert_snapshot!(err.to_string(), @"Unknown shell_caching entry `fish` (configured entries: `zsh`, `bash`)");
+
+        let err = run(vec![
+            "cache-shell-setup".to_string(),
+            "--no-config-cache".to_string(),
+            "--source=~/zsh".to_string(),
+        ])
+        .unwrap_err();
+        assert_snapshot!(err.to_string(), @"The --source and --destination flags can only be used with a single shell_caching entry, use --only to select one");
+    }
+
+    #[test]
+    fn test_run_with_target_shell_and_comment_prefixes() {
+        let env = setup_test_environment();
+
+        fs::write(
+            &env.config_file,
+            r###"
+            return {
+                shell_caching = {
+                    source = "~/shells",
+                    destination = "~/shells/dist",
+                    target_shell = "bash",
+                    comment_prefixes = { ["*.lua"] = "--" },
+                },
+            }"###,
+        )
+        .expect("Could not write to config file");
+
+        let source_files: BTreeMap<String, String> = BTreeMap::from([
+            (
+                "shells/aliases".to_string(),
+                "# ENV: GREETING=echo hello\n".to_string(),
+            ),
+            (
+                "shells/init.lua".to_string(),
+                "-- CMD: echo 'vim.g.loaded = 1'\n".to_string(),
+            ),
+        ]);
+        fixturify::write(&env.home, &source_files).unwrap();
+
+        run(vec![
+            "cache-shell-setup".to_string(),
+            "--no-config-cache".to_string(),
+        ])
+        .unwrap();
+
+        let file_map = read_files(&env.home.join("shells/dist"));
+        assert_debug_snapshot!(file_map, @r###"
+        {
+            "aliases": "# ENV: GREETING=echo hello\nexport GREETING='hello'\n",
+            "init.lua": "-- CMD: echo 'vim.g.loaded = 1'\n-- OUTPUT START: echo 'vim.g.loaded = 1'\nvim.g.loaded = 1\n\n-- OUTPUT END: echo 'vim.g.loaded = 1'\n",
+        }
+        "###);
+
+        run(vec![
+            "cache-shell-setup".to_string(),
+            "--no-config-cache".to_string(),
+            "--target-shell=fish".to_string(),
+        ])
+        .unwrap();
+
+        let file_map = read_files(&env.home.join("shells/dist"));
+        assert_debug_snapshot!(file_map, @r###"
+        {
+            "aliases": "# ENV: GREETING=echo hello\nset -gx GREETING 'hello'\n",
+            "init.lua": "-- CMD: echo 'vim.g.loaded = 1'\n-- OUTPUT START: echo 'vim.g.loaded = 1'\nvim.g.loaded = 1\n\n-- OUTPUT END: echo 'vim.g.loaded = 1'\n",
+        }
+        "###);
+
+        let err = run(vec![
+            "cache-shell-setup".to_string(),
+            "--no-config-cache".to_string(),
+            "--target-shell=nu".to_string(),
+        ])
+        .unwrap_err();
+        assert_snapshot!(format!("{:#}", err), @"Invalid `--target-shell`: Unknown target shell `nu`, expected one of `sh`, `bash`, `zsh` or `fish`");
+    }
+
     #[test]
     fn test_run_with_merging() {
         let env = setup_test_environment();
@@ -704,10 +678,12 @@ mod tests {
         ])
         .unwrap();
 
-        let file_map = fixturify::read(&env.home).unwrap();
+        let file_map = read_files(&env.home);
 
         assert_debug_snapshot!(file_map, @r###"
         {
+            "src/rwjblue/dotfiles/zsh/dist.backup/plugins/thing.zsh": "# CMD: echo 'goodbye world'\n# OLD OUTPUT SHOULD BE DELETED",
+            "src/rwjblue/dotfiles/zsh/dist.backup/plugins/weird-other-thing.zsh": "# HAHAHA WTF IS THIS?!?! DO NOT WORRY ABOUT",
             "src/rwjblue/dotfiles/zsh/dist/plugins/thing.zsh": "# CMD: echo 'goodbye world'\n# OUTPUT START: echo 'goodbye world'\ngoodbye world\n\n# OUTPUT END: echo 'goodbye world'\n",
             "src/rwjblue/dotfiles/zsh/dist/plugins/weird-other-thing.zsh": "# HAHAHA WTF IS THIS?!?! DO NOT WORRY ABOUT",
             "src/rwjblue/dotfiles/zsh/dist/zshrc": "# CMD: echo 'hello world'\n# OUTPUT START: echo 'hello world'\nhello world\n\n# OUTPUT END: echo 'hello world'\n",
@@ -718,109 +694,391 @@ mod tests {
     }
 
     #[test]
-    fn test_run_with_nonexistent_destination() {
+    fn test_run_with_refresh() {
         let env = setup_test_environment();
 
         let source_files: BTreeMap<String, String> = BTreeMap::from([(
             "src/rwjblue/dotfiles/zsh/zshrc".to_string(),
-            "# CMD: echo 'hello world'\n".to_string(),
+            "# CMD_SILENT[ttl=7d]: echo run >> ~/count && wc -l < ~/count | tr -d ' '\n"
+                .to_string(),
         )]);
 
         fixturify::write(&env.home, &source_files).unwrap();
 
-        run(vec![
-            "cache-shell-setup".to_string(),
-            "--source=~/src/rwjblue/dotfiles/zsh".to_string(),
-            "--destination=~/src/rwjblue/dotfiles/nonexistent/dist".to_string(),
-        ])
+        let run_with = |extra_args: &[&str]| {
+            let mut args = vec![
+                "cache-shell-setup".to_string(),
+                "--source=~/src/rwjblue/dotfiles/zsh".to_string(),
+                "--destination=~/src/rwjblue/dotfiles/zsh/dist".to_string(),
+            ];
+            args.extend(extra_args.iter().map(ToString::to_string));
+            run(args).unwrap();
+
+            fs::read_to_string(env.home.join("src/rwjblue/dotfiles/zsh/dist/zshrc")).unwrap()
+        };
+
+        let first = run_with(&[]);
+        let reused = run_with(&[]);
+        let refreshed = run_with(&["--refresh"]);
+
+        assert_snapshot!(format!("{first}---\n{reused}---\n{refreshed}"), @r###"
+        1
+
+        ---
+        1
+
+        ---
+        2
+        "###);
+    }
+
+    #[test]
+    fn test_run_incrementally() {
+        let env = setup_test_environment();
+
+        let counting_command = |name: &str| {
+            format!(
+                "# CMD_SILENT[ttl=1d]: echo run >> ~/{name}-count && echo \"{name} $(wc -l < ~/{name}-count | tr -d ' ')\"\n"
+            )
+        };
+        fixturify::write(
+            &env.home,
+            &BTreeMap::from([
+                (
+                    "src/rwjblue/dotfiles/zsh/zshrc".to_string(),
+                    counting_command("zshrc"),
+                ),
+                (
+                    "src/rwjblue/dotfiles/zsh/plugins/thing.zsh".to_string(),
+                    counting_command("thing"),
+                ),
+            ]),
+        )
+        .unwrap();
+
+        let run_with = |extra_args: &[&str]| {
+            let mut args = vec![
+                "cache-shell-setup".to_string(),
+                "--source=~/src/rwjblue/dotfiles/zsh".to_string(),
+                "--destination=~/src/rwjblue/dotfiles/zsh/dist".to_string(),
+            ];
+            args.extend(extra_args.iter().map(ToString::to_string));
+            run(args).unwrap();
+
+            let dist = env.home.join("src/rwjblue/dotfiles/zsh/dist");
+            let zshrc = fs::read_to_string(dist.join("zshrc")).unwrap();
+            let thing = fs::read_to_string(dist.join("plugins/thing.zsh")).unwrap();
+            format!("{}{}", zshrc, thing)
+        };
+
+        let first = run_with(&[]);
+        let unchanged = run_with(&[]);
+        fs::write(
+            env.home.join("src/rwjblue/dotfiles/zsh/zshrc"),
+            format!("export CHANGED=1\n{}", counting_command("zshrc")),
+        )
+        .unwrap();
+        let changed = run_with(&[]);
+        let refreshed = run_with(&["--refresh"]);
+
+        assert_snapshot!(format!("{first}---\n{unchanged}---\n{changed}---\n{refreshed}"), @r###"
+        zshrc 1
+
+        thing 1
+
+        ---
+        zshrc 1
+
+        thing 1
+
+        ---
+        export CHANGED=1
+        zshrc 1
+
+        thing 1
+
+        ---
+        export CHANGED=1
+        zshrc 2
+
+        thing 2
+        "###);
+        assert!(
+            env.home
+                .join("src/rwjblue/dotfiles/zsh/dist")
+                .join(MANIFEST_FILE)
+                .exists()
+        );
+    }
+
+    #[test]
+    fn test_run_reruns_commands_without_ttl_for_unchanged_sources() {
+        let env = setup_test_environment();
+
+        fixturify::write(
+            &env.home,
+            &BTreeMap::from([
+                (
+                    "src/rwjblue/dotfiles/zsh/zshrc".to_string(),
+                    "# CMD_SILENT: cat ~/prefix\n# ENV: GREETING=cat ~/greeting\n".to_string(),
+                ),
+                ("prefix".to_string(), "/opt/homebrew".to_string()),
+                ("greeting".to_string(), "hello".to_string()),
+            ]),
+        )
+        .unwrap();
+
+        let run_once = || {
+            run(vec![
+                "cache-shell-setup".to_string(),
+                "--source=~/src/rwjblue/dotfiles/zsh".to_string(),
+                "--destination=~/src/rwjblue/dotfiles/zsh/dist".to_string(),
+            ])
+            .unwrap();
+
+            fs::read_to_string(env.home.join("src/rwjblue/dotfiles/zsh/dist/zshrc")).unwrap()
+        };
+
+        let first = run_once();
+        fs::write(env.home.join("prefix"), "/usr/local").unwrap();
+        fs::write(env.home.join("greeting"), "goodbye").unwrap();
+        let second = run_once();
+
+        assert_snapshot!(format!("{first}---\n{second}"), @r###"
+        /opt/homebrew
+        # ENV: GREETING=cat ~/greeting
+        export GREETING='hello'
+        ---
+        /usr/local
+        # ENV: GREETING=cat ~/greeting
+        export GREETING='goodbye'
+        "###);
+    }
+
+    #[test]
+    fn test_run_with_merging_removes_outputs_of_removed_sources() {
+        let env = setup_test_environment();
+
+        fixturify::write(
+            &env.home,
+            &BTreeMap::from([
+                (
+                    "src/rwjblue/dotfiles/zsh/zshrc".to_string(),
+                    "# CMD: echo 'hello world'\n".to_string(),
+                ),
+                (
+                    "src/rwjblue/dotfiles/zsh/plugins/thing.zsh".to_string(),
+                    "# CMD: echo 'goodbye world'\n".to_string(),
+                ),
+                (
+                    "src/rwjblue/dotfiles/zsh/dist/unmanaged.zsh".to_string(),
+                    "# not generated, so it is kept".to_string(),
+                ),
+            ]),
+        )
         .unwrap();
 
-        let file_map = fixturify::read(&env.home).unwrap();
+        let run_with_merging = || {
+            run(vec![
+                "cache-shell-setup".to_string(),
+                "--source=~/src/rwjblue/dotfiles/zsh".to_string(),
+                "--destination=~/src/rwjblue/dotfiles/zsh/dist".to_string(),
+                "--destination-strategy=merge".to_string(),
+            ])
+            .unwrap();
+        };
+
+        run_with_merging();
+        fs::remove_file(env.home.join("src/rwjblue/dotfiles/zsh/plugins/thing.zsh")).unwrap();
+        run_with_merging();
 
+        let file_map: BTreeMap<String, String> = read_files(&env.home)
+            .into_iter()
+            .filter(|(path, _)| !path.contains("dist.backup/"))
+            .collect();
         assert_debug_snapshot!(file_map, @r###"
         {
-            "src/rwjblue/dotfiles/nonexistent/dist/zshrc": "# CMD: echo 'hello world'\n# OUTPUT START: echo 'hello world'\nhello world\n\n# OUTPUT END: echo 'hello world'\n",
+            "src/rwjblue/dotfiles/zsh/dist/unmanaged.zsh": "# not generated, so it is kept",
+            "src/rwjblue/dotfiles/zsh/dist/zshrc": "# CMD: echo 'hello world'\n# OUTPUT START: echo 'hello world'\nhello world\n\n# OUTPUT END: echo 'hello world'\n",
             "src/rwjblue/dotfiles/zsh/zshrc": "# CMD: echo 'hello world'\n",
         }
         "###);
     }
 
     #[test]
-    fn test_process_file_with_fetch() -> Result<()> {
-        let mut server = mockito::Server::new();
+    fn test_run_with_check() {
+        let env = setup_test_environment();
 
-        let server_url = server.url();
+        let source_files: BTreeMap<String, String> = BTreeMap::from([(
+            "src/rwjblue/dotfiles/zsh/zshrc".to_string(),
+            "# CMD: echo 'hello world'\n".to_string(),
+        )]);
+        fixturify::write(&env.home, &source_files).unwrap();
 
-        let mock = server
-            .mock("GET", "/test")
-            .with_status(200)
-            .with_header("content-type", "text/plain")
-            .with_body("# some content returned here!!")
-            .create();
+        let run_with = |extra_args: &[&str]| {
+            let mut args = vec![
+                "cache-shell-setup".to_string(),
+                "--source=~/src/rwjblue/dotfiles/zsh".to_string(),
+                "--destination=~/src/rwjblue/dotfiles/zsh/dist".to_string(),
+            ];
+            args.extend(extra_args.iter().map(ToString::to_string));
+            run(args)
+        };
 
-        let dir = tempdir()?;
-        let source_file = dir.path().join("test.zsh");
-        let dest_file = dir.path().join("output.zsh");
+        run_with(&[]).unwrap();
+        run_with(&["--check"]).unwrap();
 
-        let content = format!("# FETCH: {}/test", server_url);
-        write(&source_file, content)?;
+        fs::write(
+            env.home.join("src/rwjblue/dotfiles/zsh/zshrc"),
+            "# CMD: echo 'goodbye world'\n",
+        )
+        .unwrap();
+        let before_check = read_files(&env.home);
+
+        let err = run_with(&["--check"]).unwrap_err();
 
-        process_file(&source_file, &dest_file)?;
+        assert_snapshot!(stabilize_home_paths(&env, &err.to_string()), @r###"
+        1 file(s) in ~/src/rwjblue/dotfiles/zsh/dist are out of date, run without `--check` to update them
+        "###);
+        assert_eq!(read_files(&env.home), before_check);
+    }
+
+    #[test]
+    fn test_run_with_check_detects_changed_command_output() {
+        let env = setup_test_environment();
 
-        mock.assert();
+        fixturify::write(
+            &env.home,
+            &BTreeMap::from([
+                (
+                    "src/rwjblue/dotfiles/zsh/zshrc".to_string(),
+                    "# CMD: cat ~/greeting\n".to_string(),
+                ),
+                (
+                    "src/rwjblue/dotfiles/zsh/env.zsh".to_string(),
+                    "# CMD[ttl=1d]: cat ~/other\n".to_string(),
+                ),
+                ("greeting".to_string(), "hello".to_string()),
+                ("other".to_string(), "before".to_string()),
+            ]),
+        )
+        .unwrap();
 
-        let replace_server_addr = |content: &str, server_url: &str| -> String {
-            content.replace(server_url, "{server_url}")
+        let run_with = |extra_args: &[&str]| {
+            let mut args = vec![
+                "cache-shell-setup".to_string(),
+                "--source=~/src/rwjblue/dotfiles/zsh".to_string(),
+                "--destination=~/src/rwjblue/dotfiles/zsh/dist".to_string(),
+            ];
+            args.extend(extra_args.iter().map(ToString::to_string));
+            run(args)
         };
 
-        let source_contents = fs::read_to_string(&source_file)?;
-        assert_snapshot!(replace_server_addr(&source_contents, &server_url), @r###"
-        # FETCH: {server_url}/test
-        "###);
+        run_with(&[]).unwrap();
+        run_with(&["--check"]).unwrap();
+
+        fs::write(env.home.join("greeting"), "goodbye").unwrap();
 
-        let processed_content = fs::read_to_string(&dest_file)?;
-        assert_snapshot!(replace_server_addr(&processed_content, &server_url), @r###"
-        # FETCH: {server_url}/test
-        # FETCHED CONTENT START: {server_url}/test
-        # some content returned here!!
-        # FETCHED CONTENT END: {server_url}/test
+        let err = run_with(&["--check"]).unwrap_err();
+
+        assert_snapshot!(stabilize_home_paths(&env, &err.to_string()), @r###"
+        1 file(s) in ~/src/rwjblue/dotfiles/zsh/dist are out of date, run without `--check` to update them
         "###);
 
-        Ok(())
+        // the output of commands with a TTL is still cached, but must not hide changes either
+        run_with(&[]).unwrap();
+        fs::write(env.home.join("other"), "after").unwrap();
+
+        let err = run_with(&["--check"]).unwrap_err();
+
+        assert_snapshot!(stabilize_home_paths(&env, &err.to_string()), @r###"
+        1 file(s) in ~/src/rwjblue/dotfiles/zsh/dist are out of date, run without `--check` to update them
+        "###);
     }
 
     #[test]
-    fn test_process_file_with_invalid_fetch() -> Result<()> {
-        let mut server = mockito::Server::new();
+    fn test_run_with_rollback() {
+        let env = setup_test_environment();
 
-        let server_url = server.url();
+        let zshrc = env.home.join("src/rwjblue/dotfiles/zsh/zshrc");
+        fixturify::write(
+            &env.home,
+            &BTreeMap::from([(
+                "src/rwjblue/dotfiles/zsh/zshrc".to_string(),
+                "# CMD: echo 'first'\n".to_string(),
+            )]),
+        )
+        .unwrap();
 
-        let mock = server
-            .mock("GET", "/test")
-            .with_status(500)
-            .with_header("content-type", "text/plain")
-            .with_body("ZOMG ERROR")
-            .create();
+        let run_with = |extra_args: &[&str]| {
+            let mut args = vec![
+                "cache-shell-setup".to_string(),
+                "--source=~/src/rwjblue/dotfiles/zsh".to_string(),
+                "--destination=~/src/rwjblue/dotfiles/zsh/dist".to_string(),
+            ];
+            args.extend(extra_args.iter().map(ToString::to_string));
+            run(args)
+        };
 
-        let dir = tempdir()?;
-        let source_file = dir.path().join("test.zsh");
-        let dest_file = dir.path().join("output.zsh");
+        let err = run_with(&["--rollback"]).unwrap_err();
+        assert_snapshot!(stabilize_home_paths(&env, &err.to_string()), @r###"
+        There is no backup of ~/src/rwjblue/dotfiles/zsh/dist to roll back to (expected it at ~/src/rwjblue/dotfiles/zsh/dist.backup)
+        "###);
 
-        let content = format!("# FETCH: {}/test", server_url);
-        write(&source_file, content)?;
+        run_with(&[]).unwrap();
+        fs::write(&zshrc, "# CMD: echo 'second'\n").unwrap();
+        run_with(&[]).unwrap();
 
-        let replace_server_addr = |content: String, server_url: &str| -> String {
-            content.replace(server_url, "{server_url}")
-        };
+        let after_second_run = read_files(&env.home);
+        assert_debug_snapshot!(after_second_run, @r###"
+        {
+            "src/rwjblue/dotfiles/zsh/dist.backup/zshrc": "# CMD: echo 'first'\n# OUTPUT START: echo 'first'\nfirst\n\n# OUTPUT END: echo 'first'\n",
+            "src/rwjblue/dotfiles/zsh/dist/zshrc": "# CMD: echo 'second'\n# OUTPUT START: echo 'second'\nsecond\n\n# OUTPUT END: echo 'second'\n",
+            "src/rwjblue/dotfiles/zsh/zshrc": "# CMD: echo 'second'\n",
+        }
+        "###);
 
-        let result = process_file(&source_file, &dest_file);
+        run_with(&["--rollback"]).unwrap();
 
-        let err = result.unwrap_err();
-        assert_snapshot!(replace_server_addr(format!("{:#}", err), &server_url), @"Failed to fetch URL: {server_url}/test: http status: 500");
+        let after_rollback = read_files(&env.home);
+        assert_debug_snapshot!(after_rollback, @r###"
+        {
+            "src/rwjblue/dotfiles/zsh/dist.backup/zshrc": "# CMD: echo 'second'\n# OUTPUT START: echo 'second'\nsecond\n\n# OUTPUT END: echo 'second'\n",
+            "src/rwjblue/dotfiles/zsh/dist/zshrc": "# CMD: echo 'first'\n# OUTPUT START: echo 'first'\nfirst\n\n# OUTPUT END: echo 'first'\n",
+            "src/rwjblue/dotfiles/zsh/zshrc": "# CMD: echo 'second'\n",
+        }
+        "###);
+
+        run_with(&["--rollback"]).unwrap();
+        assert_eq!(read_files(&env.home), after_second_run);
+    }
+
+    #[test]
+    fn test_run_with_nonexistent_destination() {
+        let env = setup_test_environment();
 
-        mock.assert();
+        let source_files: BTreeMap<String, String> = BTreeMap::from([(
+            "src/rwjblue/dotfiles/zsh/zshrc".to_string(),
+            "# CMD: echo 'hello world'\n".to_string(),
+        )]);
 
-        Ok(())
+        fixturify::write(&env.home, &source_files).unwrap();
+
+        run(vec![
+            "cache-shell-setup".to_string(),
+            "--source=~/src/rwjblue/dotfiles/zsh".to_string(),
+            "--destination=~/src/rwjblue/dotfiles/nonexistent/dist".to_string(),
+        ])
+        .unwrap();
+
+        let file_map = read_files(&env.home);
+
+        assert_debug_snapshot!(file_map, @r###"
+        {
+            "src/rwjblue/dotfiles/nonexistent/dist/zshrc": "# CMD: echo 'hello world'\n# OUTPUT START: echo 'hello world'\nhello world\n\n# OUTPUT END: echo 'hello world'\n",
+            "src/rwjblue/dotfiles/zsh/zshrc": "# CMD: echo 'hello world'\n",
+        }
+        "###);
     }
 }
 
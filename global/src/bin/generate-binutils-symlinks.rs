@@ -1,6 +1,6 @@
 use anyhow::Result;
 use clap::Parser;
-use config::{Config, read_config};
+use config::{Config, read_config, read_config_cached};
 use std::path::PathBuf;
 use tracing::debug;
 use tracing_subscriber::EnvFilter;
@@ -11,6 +11,10 @@ struct Args {
     /// Paths to the workspaces. Pass multiple times to add more.
     #[arg(long = "workspace-path", short)]
     workspace_paths: Option<Vec<PathBuf>>,
+
+    /// Always evaluate the Lua config instead of reusing the cached result.
+    #[arg(long)]
+    no_config_cache: bool,
 }
 
 fn get_workspace_paths(arg_values: Vec<String>, config: &Config) -> Result<Vec<PathBuf>> {
@@ -60,8 +64,12 @@ fn main() -> Result<()> {
 
     latest_bin::ensure_latest_bin()?;
 
-    let config = read_config(None)?;
-    let args = std::env::args().collect();
+    let args: Vec<String> = std::env::args().collect();
+    let config = if Args::parse_from(&args).no_config_cache {
+        read_config(None)?
+    } else {
+        read_config_cached(None)?
+    };
 
     run(args, &config)
 }
@@ -81,6 +89,7 @@ mod tests {
             crate_locations: None,
             tmux: None,
             shell_caching: None,
+            local_dotfiles: None,
         };
 
         // Test when no paths are provided (expect default path).
@@ -98,6 +107,7 @@ mod tests {
             crate_locations: None,
             tmux: None,
             shell_caching: None,
+            local_dotfiles: None,
         };
 
         // Test when multiple paths are provided.
@@ -144,6 +154,7 @@ mod tests {
             crate_locations: Some(vec![String::from("~/workspace")]),
             tmux: None,
             shell_caching: None,
+            local_dotfiles: None,
         };
 
         let args = vec!["generate-binutils-symlinks".to_string()];
@@ -170,6 +181,7 @@ mod tests {
             crate_locations: Some(vec![String::from("~/invalid_dir")]),
             tmux: None,
             shell_caching: None,
+            local_dotfiles: None,
         };
 
         let args = vec!["generate-binutils-symlinks".to_string()];
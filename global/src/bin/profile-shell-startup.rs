@@ -0,0 +1,646 @@
+use anyhow::{Context, Result};
+use clap::{Parser, ValueEnum};
+use config::ShellCache;
+use serde::Serialize;
+use shared_global::shell_cache::{
+    CommandOptions, FileFilter, MANIFEST_FILE, ProcessOptions, SyntaxOptions, render_live_directory,
+};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+use tracing_subscriber::EnvFilter;
+use walkdir::WalkDir;
+
+/// Separates the fields of the `PS4` prompt that traced startups use, so that trace lines can be
+/// told apart from anything else the startup files print to stderr.
+const TRACE_SEPARATOR: char = '\x1f';
+
+#[derive(ValueEnum, Clone, Debug, PartialEq)]
+enum Format {
+    Table,
+    Json,
+}
+
+/// profile-shell-startup
+///
+/// Measures how long an interactive zsh takes to start (`zsh -i -c exit`)
+/// with the startup files generated by `cache-shell-startup`, and without
+/// caching (i.e. with every directive running its command on each startup
+/// instead), reporting the mean and p95 of several runs.
+///
+/// Startups with the generated files are also traced (`zprof`-style, using
+/// `xtrace` with timestamps) to attribute time to each sourced file, and to
+/// each command that still runs on every startup (i.e. a command substitution
+/// within the generated files).
+#[derive(Parser, Debug)]
+#[command(name = "profile-shell-startup")]
+struct Args {
+    /// Path to the configuration file. Defaults to `~/.config/binutils/config.yaml`.
+    #[arg(long)]
+    config_file: Option<String>,
+
+    /// Directory containing the source startup files (see `cache-shell-startup`)
+    #[clap(short, long)]
+    source: Option<String>,
+
+    /// Directory containing the generated startup files (see `cache-shell-startup`)
+    #[clap(short, long)]
+    destination: Option<String>,
+
+    /// Always evaluate the Lua config instead of reusing the cached result.
+    #[arg(long)]
+    no_config_cache: bool,
+
+    /// Name of the `shell_caching` entry (in the config file) to profile. Required when several
+    /// entries are configured.
+    #[arg(long)]
+    only: Option<String>,
+
+    /// The file (relative to the source and destination directories) that zsh sources on
+    /// startup, e.g. from `~/.zshrc`.
+    #[arg(long, default_value = "zshrc")]
+    entry: String,
+
+    /// How many times zsh is started (for each variant).
+    #[arg(short = 'n', long, default_value_t = 10)]
+    runs: usize,
+
+    /// How to output the report.
+    #[clap(value_enum, long, default_value_t = Format::Table)]
+    format: Format,
+}
+
+/// The mean and 95th percentile of the startup times of a variant, in milliseconds.
+#[derive(Debug, Serialize, PartialEq)]
+struct Timings {
+    mean_ms: f64,
+    p95_ms: f64,
+}
+
+impl Timings {
+    fn new(durations: &[Duration]) -> Self {
+        let mut millis: Vec<f64> = durations
+            .iter()
+            .map(|duration| duration.as_secs_f64() * 1000.0)
+            .collect();
+        millis.sort_by(f64::total_cmp);
+
+        let mean = millis.iter().sum::<f64>() / millis.len().max(1) as f64;
+        // nearest-rank percentile
+        let p95_index = ((millis.len() as f64 * 0.95).ceil() as usize).saturating_sub(1);
+
+        Self {
+            mean_ms: round_ms(mean),
+            p95_ms: round_ms(millis.get(p95_index).copied().unwrap_or_default()),
+        }
+    }
+}
+
+/// The time spent (on average) executing the lines of a sourced file.
+#[derive(Debug, Serialize, PartialEq)]
+struct FileTiming {
+    file: String,
+    time_ms: f64,
+}
+
+/// The time spent (on average) on a line of a generated file that runs a command on every
+/// startup.
+#[derive(Debug, Serialize, PartialEq)]
+struct CommandTiming {
+    file: String,
+    line: usize,
+    command: String,
+    time_ms: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    runs: usize,
+    cached: Timings,
+    uncached: Timings,
+    files: Vec<FileTiming>,
+    uncached_commands: Vec<CommandTiming>,
+}
+
+/// A line of `xtrace` output: when a line of which file started executing.
+#[derive(Debug, PartialEq)]
+struct TraceEntry {
+    time: f64,
+    file: String,
+    line: usize,
+}
+
+/// A `ZDOTDIR` whose `.zshrc` sources `entry`, optionally tracing it.
+fn write_zdotdir(dir: &Path, entry: &Path, traced: bool) -> Result<()> {
+    let mut zshrc = vec!["unset ZDOTDIR".to_string()];
+    if traced {
+        zshrc.extend([
+            "zmodload zsh/datetime".to_string(),
+            "setopt prompt_subst".to_string(),
+            format!(
+                "PS4=$'\\x{sep:02x}${{EPOCHREALTIME}}\\x{sep:02x}%x\\x{sep:02x}%I\\x{sep:02x}'",
+                sep = TRACE_SEPARATOR as u32
+            ),
+            "setopt xtrace".to_string(),
+        ]);
+    }
+    zshrc.push(format!(
+        "source {}",
+        shell_quote(&entry.display().to_string())
+    ));
+    if traced {
+        zshrc.push("unsetopt xtrace".to_string());
+    }
+
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join(".zshrc"), zshrc.join("\n") + "\n")
+        .with_context(|| format!("Failed to write {}", dir.join(".zshrc").display()))
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Starts an interactive zsh (with the given `ZDOTDIR`) that exits right away, returning how
+/// long it took along with its stderr.
+fn start_zsh(zdotdir: &Path) -> Result<(Duration, String)> {
+    let start = Instant::now();
+    let output = Command::new("zsh")
+        .args(["-i", "-c", "exit"])
+        .env("ZDOTDIR", zdotdir)
+        .stdin(Stdio::null())
+        .output()
+        .context("Failed to start zsh")?;
+    let elapsed = start.elapsed();
+
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+    if !output.status.success() {
+        anyhow::bail!("zsh exited with {}:\n{}", output.status, stderr);
+    }
+
+    Ok((elapsed, stderr))
+}
+
+fn parse_trace(stderr: &str) -> Vec<TraceEntry> {
+    stderr
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.strip_prefix(TRACE_SEPARATOR)?.split(TRACE_SEPARATOR);
+            let time = fields.next()?.parse().ok()?;
+            let file = fields.next()?.to_string();
+            let line = fields.next()?.parse().ok()?;
+
+            Some(TraceEntry { time, file, line })
+        })
+        .collect()
+}
+
+/// Attributes the time until the next trace entry to the file and line of each entry, in
+/// milliseconds.
+fn attribute_trace(entries: &[TraceEntry], times: &mut BTreeMap<(String, usize), f64>) {
+    for pair in entries.windows(2) {
+        let elapsed = (pair[1].time - pair[0].time) * 1000.0;
+        *times
+            .entry((pair[0].file.clone(), pair[0].line))
+            .or_default() += elapsed;
+    }
+}
+
+/// The lines of the generated files that still run a command on every startup, keyed by the
+/// file (relative to `dest_dir`) and line number.
+fn find_uncached_commands(dest_dir: &Path) -> Result<BTreeMap<(String, usize), String>> {
+    let mut commands = BTreeMap::new();
+
+    for entry in WalkDir::new(dest_dir) {
+        let entry = entry.context("Failed to read destination directory")?;
+        if !entry.file_type().is_file() || entry.file_name() == MANIFEST_FILE {
+            continue;
+        }
+        let Ok(contents) = fs::read_to_string(entry.path()) else {
+            continue;
+        };
+
+        let file = relative_name(entry.path(), dest_dir);
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if !line.starts_with('#') && (line.contains("$(") || line.contains('`')) {
+                commands.insert((file.clone(), index + 1), line.to_string());
+            }
+        }
+    }
+
+    Ok(commands)
+}
+
+/// Names files within `dest_dir` by their relative path, and other files by their full path.
+fn relative_name(file: &Path, dest_dir: &Path) -> String {
+    file.strip_prefix(dest_dir)
+        .unwrap_or(file)
+        .display()
+        .to_string()
+}
+
+fn build_report(
+    runs: usize,
+    cached: &[Duration],
+    uncached: &[Duration],
+    line_times: &BTreeMap<(String, usize), f64>,
+    uncached_commands: &BTreeMap<(String, usize), String>,
+) -> Report {
+    let mut file_times: BTreeMap<&str, f64> = BTreeMap::new();
+    for ((file, _), time) in line_times {
+        *file_times.entry(file).or_default() += time;
+    }
+
+    let mut files: Vec<FileTiming> = file_times
+        .into_iter()
+        .map(|(file, time)| FileTiming {
+            file: file.to_string(),
+            time_ms: round_ms(time / runs as f64),
+        })
+        .collect();
+    files.sort_by(|a, b| b.time_ms.total_cmp(&a.time_ms));
+
+    let mut commands: Vec<CommandTiming> = uncached_commands
+        .iter()
+        .map(|((file, line), command)| CommandTiming {
+            file: file.clone(),
+            line: *line,
+            command: command.clone(),
+            time_ms: round_ms(
+                line_times
+                    .get(&(file.clone(), *line))
+                    .copied()
+                    .unwrap_or_default()
+                    / runs as f64,
+            ),
+        })
+        .collect();
+    commands.sort_by(|a, b| b.time_ms.total_cmp(&a.time_ms));
+
+    Report {
+        runs,
+        cached: Timings::new(cached),
+        uncached: Timings::new(uncached),
+        files,
+        uncached_commands: commands,
+    }
+}
+
+fn round_ms(ms: f64) -> f64 {
+    (ms * 100.0).round() / 100.0
+}
+
+fn render_table(report: &Report) -> String {
+    let mut lines = vec![
+        format!("Startup time ({} runs)", report.runs),
+        format!("{:<12} {:>10} {:>10}", "variant", "mean", "p95"),
+    ];
+    for (name, timings) in [("cached", &report.cached), ("uncached", &report.uncached)] {
+        lines.push(format!(
+            "{:<12} {:>8.2}ms {:>8.2}ms",
+            name, timings.mean_ms, timings.p95_ms
+        ));
+    }
+
+    let file_width = report
+        .files
+        .iter()
+        .map(|file| file.file.len())
+        .chain([4])
+        .max()
+        .unwrap_or_default();
+    lines.push(String::new());
+    lines.push("Time per file (cached)".to_string());
+    lines.push(format!("{:<file_width$} {:>10}", "file", "time"));
+    for file in &report.files {
+        lines.push(format!(
+            "{:<file_width$} {:>8.2}ms",
+            file.file, file.time_ms
+        ));
+    }
+
+    lines.push(String::new());
+    lines.push("Commands that still run on every startup (cached)".to_string());
+    if report.uncached_commands.is_empty() {
+        lines.push("none".to_string());
+    }
+    for command in &report.uncached_commands {
+        lines.push(format!(
+            "{:>8.2}ms  {}:{}  {}",
+            command.time_ms, command.file, command.line, command.command
+        ));
+    }
+
+    lines.join("\n")
+}
+
+fn run(args: Vec<String>) -> Result<()> {
+    let args = Args::parse_from(args);
+    let config_file = args.config_file.as_ref().map(PathBuf::from);
+    let config = if args.no_config_cache {
+        config::read_config(config_file)?
+    } else {
+        config::read_config_cached(config_file)?
+    };
+
+    let mut entry = match (&config.shell_caching, &args.only) {
+        (Some(shell_caching), Some(only)) => {
+            shell_caching.select(std::slice::from_ref(only))?[0].clone()
+        }
+        (Some(shell_caching), None) => match shell_caching.entries() {
+            [entry] => entry.clone(),
+            _ => anyhow::bail!(
+                "Several shell_caching entries are configured, use --only to select one"
+            ),
+        },
+        (None, Some(_)) => {
+            anyhow::bail!("No shell_caching entries are configured, so `--only` can't be used")
+        }
+        (None, None) => ShellCache::default(),
+    };
+    if let Some(source) = &args.source {
+        entry.source = source.clone();
+    }
+    if let Some(destination) = &args.destination {
+        entry.destination = destination.clone();
+    }
+    if entry.source.is_empty() || entry.destination.is_empty() {
+        anyhow::bail!(
+            "No source and destination directories provided. Either use the --source and --destination flags or set them in the config file"
+        );
+    }
+
+    let source_dir = PathBuf::from(shellexpand::tilde(&entry.source).to_string());
+    let dest_dir = PathBuf::from(shellexpand::tilde(&entry.destination).to_string());
+
+    let cached_entry = dest_dir.join(&args.entry);
+    if !cached_entry.exists() {
+        anyhow::bail!(
+            "{} doesn't exist, run `cache-shell-startup` first",
+            cached_entry.display()
+        );
+    }
+
+    let temp_dir = tempfile::tempdir()?;
+    let live_dir = temp_dir.path().join("live");
+    let options = ProcessOptions {
+        filter: FileFilter::new(
+            &entry.include.clone().unwrap_or_default(),
+            &entry.exclude.clone().unwrap_or_default(),
+        )?,
+        command_defaults: CommandOptions::from_config(&entry)?,
+        syntax: SyntaxOptions::from_config(&entry)?,
+        ..Default::default()
+    };
+    render_live_directory(&source_dir, &live_dir, &dest_dir, &options)
+        .context("Failed to render the startup files without caching")?;
+
+    let variants = [
+        ("cached", cached_entry, false),
+        ("uncached", live_dir.join(&args.entry), false),
+        ("traced", dest_dir.join(&args.entry), true),
+    ];
+    for (name, entry, traced) in &variants {
+        write_zdotdir(&temp_dir.path().join(name), entry, *traced)?;
+    }
+
+    let mut durations: BTreeMap<&str, Vec<Duration>> = BTreeMap::new();
+    let mut line_times = BTreeMap::new();
+    for run in 1..=args.runs {
+        info!("Starting zsh (run {} of {})", run, args.runs);
+
+        for (name, _, traced) in &variants {
+            let (elapsed, stderr) = start_zsh(&temp_dir.path().join(name))?;
+            debug!("{} startup took {:?}", name, elapsed);
+
+            if *traced {
+                let mut entries = parse_trace(&stderr);
+                for entry in &mut entries {
+                    entry.file = relative_name(Path::new(&entry.file), &dest_dir);
+                }
+                // the wrapping `.zshrc` isn't part of the startup files
+                let zdotdir_zshrc = temp_dir.path().join(name).join(".zshrc");
+                attribute_trace(&entries, &mut line_times);
+                line_times.retain(|(file, _), _| Path::new(file) != zdotdir_zshrc);
+            } else {
+                durations.entry(name).or_default().push(elapsed);
+            }
+        }
+    }
+
+    let report = build_report(
+        args.runs,
+        &durations.remove("cached").unwrap_or_default(),
+        &durations.remove("uncached").unwrap_or_default(),
+        &line_times,
+        &find_uncached_commands(&dest_dir)?,
+    );
+
+    match args.format {
+        Format::Table => println!("{}", render_table(&report)),
+        Format::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+    }
+
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    // Initialize tracing, use `info` by default
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .with_writer(std::io::stderr)
+        .init();
+
+    latest_bin::ensure_latest_bin()?;
+
+    let args: Vec<String> = std::env::args().collect();
+    run(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use insta::{assert_debug_snapshot, assert_snapshot};
+
+    fn durations(millis: &[u64]) -> Vec<Duration> {
+        millis.iter().copied().map(Duration::from_millis).collect()
+    }
+
+    #[test]
+    fn test_timings() {
+        assert_eq!(
+            Timings::new(&durations(&[
+                40, 42, 41, 39, 40, 43, 41, 40, 42, 41, 40, 39, 41, 42, 40, 41, 40, 42, 41, 90
+            ])),
+            Timings {
+                mean_ms: 43.25,
+                p95_ms: 43.0,
+            }
+        );
+        assert_eq!(
+            Timings::new(&durations(&[10])),
+            Timings {
+                mean_ms: 10.0,
+                p95_ms: 10.0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_trace() {
+        let stderr = "\x1f1700000000.100000\x1f/dist/zshrc\x1f1\x1fexport A=1\n\
+                      unrelated output\n\
+                      \x1f1700000000.102500\x1f/dist/zshrc\x1f2\x1feval \"$(fnm env)\"\n\
+                      \x1f1700000000.150000\x1f/dist/plugins/thing.zsh\x1f1\x1falias ll='ls -l'\n\
+                      \x1f1700000000.151000\x1f/zdotdir/.zshrc\x1f7\x1funsetopt xtrace\n";
+
+        let entries = parse_trace(stderr);
+        assert_debug_snapshot!(entries, @r###"
+        [
+            TraceEntry {
+                time: 1700000000.1,
+                file: "/dist/zshrc",
+                line: 1,
+            },
+            TraceEntry {
+                time: 1700000000.1025,
+                file: "/dist/zshrc",
+                line: 2,
+            },
+            TraceEntry {
+                time: 1700000000.15,
+                file: "/dist/plugins/thing.zsh",
+                line: 1,
+            },
+            TraceEntry {
+                time: 1700000000.151,
+                file: "/zdotdir/.zshrc",
+                line: 7,
+            },
+        ]
+        "###);
+
+        let mut line_times = BTreeMap::new();
+        attribute_trace(&entries, &mut line_times);
+        let line_times: BTreeMap<(String, usize), f64> = line_times
+            .into_iter()
+            .map(|(key, time)| (key, round_ms(time)))
+            .collect();
+        assert_debug_snapshot!(line_times, @r###"
+        {
+            (
+                "/dist/plugins/thing.zsh",
+                1,
+            ): 1.0,
+            (
+                "/dist/zshrc",
+                1,
+            ): 2.5,
+            (
+                "/dist/zshrc",
+                2,
+            ): 47.5,
+        }
+        "###);
+    }
+
+    #[test]
+    fn test_render_report() {
+        let line_times = BTreeMap::from([
+            (("zshrc".to_string(), 1), 5.0),
+            (("zshrc".to_string(), 2), 95.0),
+            (("plugins/thing.zsh".to_string(), 1), 2.0),
+        ]);
+        let uncached_commands = BTreeMap::from([
+            (("zshrc".to_string(), 2), "eval \"$(fnm env)\"".to_string()),
+            (
+                ("plugins/thing.zsh".to_string(), 3),
+                "export X=$(date)".to_string(),
+            ),
+        ]);
+
+        let report = build_report(
+            2,
+            &durations(&[50, 60]),
+            &durations(&[300, 320]),
+            &line_times,
+            &uncached_commands,
+        );
+
+        assert_snapshot!(render_table(&report), @r###"
+        Startup time (2 runs)
+        variant            mean        p95
+        cached          55.00ms    60.00ms
+        uncached       310.00ms   320.00ms
+
+        Time per file (cached)
+        file                    time
+        zshrc                50.00ms
+        plugins/thing.zsh     1.00ms
+
+        Commands that still run on every startup (cached)
+           47.50ms  zshrc:2  eval "$(fnm env)"
+            0.00ms  plugins/thing.zsh:3  export X=$(date)
+        "###);
+        assert_snapshot!(serde_json::to_string_pretty(&report).unwrap(), @r###"
+        {
+          "runs": 2,
+          "cached": {
+            "mean_ms": 55.0,
+            "p95_ms": 60.0
+          },
+          "uncached": {
+            "mean_ms": 310.0,
+            "p95_ms": 320.0
+          },
+          "files": [
+            {
+              "file": "zshrc",
+              "time_ms": 50.0
+            },
+            {
+              "file": "plugins/thing.zsh",
+              "time_ms": 1.0
+            }
+          ],
+          "uncached_commands": [
+            {
+              "file": "zshrc",
+              "line": 2,
+              "command": "eval \"$(fnm env)\"",
+              "time_ms": 47.5
+            },
+            {
+              "file": "plugins/thing.zsh",
+              "line": 3,
+              "command": "export X=$(date)",
+              "time_ms": 0.0
+            }
+          ]
+        }
+        "###);
+    }
+
+    #[test]
+    fn test_write_zdotdir() {
+        let dir = tempfile::tempdir().unwrap();
+
+        write_zdotdir(dir.path(), Path::new("/dist/zshrc"), true).unwrap();
+
+        assert_snapshot!(fs::read_to_string(dir.path().join(".zshrc")).unwrap(), @r###"
+        unset ZDOTDIR
+        zmodload zsh/datetime
+        setopt prompt_subst
+        PS4=$'\x1f${EPOCHREALTIME}\x1f%x\x1f%I\x1f'
+        setopt xtrace
+        source '/dist/zshrc'
+        unsetopt xtrace
+        "###);
+    }
+}
@@ -1,26 +1,46 @@
 use std::collections::BTreeMap;
 use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use anyhow::{Context, Result};
 use clap::Parser;
+use config::Symlink;
+use serde::{Deserialize, Serialize};
 use tracing::{debug, info, warn};
 use tracing_subscriber::EnvFilter;
 
-/// Sets up a local dotfiles repository with a predefined directory structure and symlinks.
+/// Sets up a local dotfiles repository with a directory structure and symlinks.
 ///
 /// This command:
-/// - Creates a directory structure for local dotfiles (crates, nvim configs, snippets)
 /// - Optionally clones a git repository as the base
-/// - Sets up symlinks for:
+/// - Creates the files of the `local_dotfiles` config (defaulting to binutils crates, nvim
+///   configs and snippets) that don't exist yet
+/// - Sets up the symlinks of the `local_dotfiles` config, defaulting to:
 ///   - Neovim local config (~/.config/nvim/lua/local_nvim -> local-dotfiles/nvim/lua)
+///   - binutils local config (~/.config/binutils/local.config.lua ->
+///     local-dotfiles/binutils/config/local.config.lua)
+///
+/// Existing files (that aren't symlinks) where the symlinks go are an error, unless `--backup`
+/// (move them into `local-dotfiles/.backups/<timestamp>`) or `--adopt` (move them into the local
+/// dotfiles, replacing the symlink's source) is passed. Either way, a manifest of the moved files
+/// is recorded so that `--restore` can move them back.
 ///
 /// # Environment Variables:
 /// - HOME: Required for path expansion
 /// - RUST_LOG: Optional, controls logging verbosity (e.g. debug, info)
 #[derive(Parser, Debug)]
 struct Args {
+    /// Path to the configuration file. Defaults to `~/.config/binutils/config.lua`.
+    #[arg(long)]
+    config_file: Option<String>,
+
+    /// Always evaluate the Lua config instead of reusing the cached result.
+    #[arg(long)]
+    no_config_cache: bool,
+
     /// Git repository URL to clone (optional)
     #[arg(long)]
     repo: Option<String>,
@@ -32,24 +52,77 @@ struct Args {
     /// Show what would happen without making any changes
     #[arg(long)]
     dry_run: bool,
+
+    /// Move existing files that are in the way of symlinks into a timestamped backup directory
+    #[arg(long, conflicts_with = "adopt")]
+    backup: bool,
+
+    /// Move existing files that are in the way of symlinks into the local dotfiles (as the source
+    /// of their symlink)
+    #[arg(long)]
+    adopt: bool,
+
+    /// Move the files of the most recent `--backup` or `--adopt` run back, replacing their symlinks
+    #[arg(long, conflicts_with_all = ["repo", "backup", "adopt"])]
+    restore: bool,
 }
 
-fn ensure_directory_structure(base_path: &Path, dry_run: bool) -> Result<()> {
-    debug!("Creating directory structure at {}", base_path.display());
+/// How `setup_symlinks` handles existing files (that aren't symlinks) at the target of a symlink.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Conflicts {
+    Fail,
+    Backup,
+    Adopt,
+}
+
+/// The directory (within the local dotfiles) that holds a directory of moved files per run.
+const BACKUPS_DIR: &str = ".backups";
+
+/// The name of the manifest within each backup directory.
+const BACKUP_MANIFEST_FILE: &str = "manifest.json";
+
+/// Records where `--backup` and `--adopt` moved the files that were in the way of symlinks, so
+/// that `--restore` can move them back.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct BackupManifest {
+    entries: Vec<BackupEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BackupEntry {
+    /// Where the file was, and where its symlink is now.
+    target: PathBuf,
+    /// Where the file was moved to: within the backup directory, or within the local dotfiles when
+    /// it was adopted.
+    moved_to: PathBuf,
+}
+
+impl BackupManifest {
+    fn read(backup_dir: &Path) -> Result<Self> {
+        let manifest_file = backup_dir.join(BACKUP_MANIFEST_FILE);
+        let contents = fs::read_to_string(&manifest_file)
+            .with_context(|| format!("Failed to read: {}", manifest_file.display()))?;
+
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse: {}", manifest_file.display()))
+    }
+
+    fn write(&self, backup_dir: &Path) -> Result<()> {
+        let manifest_file = backup_dir.join(BACKUP_MANIFEST_FILE);
+        let contents =
+            serde_json::to_string_pretty(self).context("Failed to serialize backup manifest")?;
 
-    let files: BTreeMap<&str, &str> = BTreeMap::from([
-        (
-            "binutils/config/local.config.lua",
-            "return require('config')",
-        ),
-        // TODO: set up Cargo.toml
-        ("binutils/crates/.gitkeep", ""),
-        ("nvim/lua/config/autocmds.lua", ""),
-        ("nvim/lua/config/options.lua", ""),
-        ("nvim/lua/config/keymaps.lua", ""),
-        ("nvim/lua/plugins/.gitkeep", ""),
-        ("nvim/snippets/.gitkeep", ""),
-    ]);
+        fs::write(&manifest_file, contents)
+            .with_context(|| format!("Failed to write: {}", manifest_file.display()))
+    }
+}
+
+fn ensure_directory_structure(
+    base_path: &Path,
+    files: &BTreeMap<String, String>,
+    dry_run: bool,
+) -> Result<()> {
+    debug!("Creating directory structure at {}", base_path.display());
 
     for (file_path, contents) in files {
         let path = base_path.join(file_path);
@@ -130,82 +203,358 @@ fn clone_repo(repo_url: &str, target_path: &Path, dry_run: bool) -> Result<()> {
     Ok(())
 }
 
-fn setup_symlinks(base_path: &Path, dry_run: bool) -> Result<()> {
+fn setup_symlinks(
+    base_path: &Path,
+    symlinks: &[Symlink],
+    conflicts: Conflicts,
+    dry_run: bool,
+) -> Result<()> {
     debug!("Setting up symlinks");
 
-    let home = std::env::var("HOME").context("HOME environment variable not set")?;
-    let local_nvim_path = PathBuf::from(&home).join(".config/nvim/lua/local_nvim");
-    let binutils_local_config_path = PathBuf::from(&home).join(".config/binutils/local.config.lua");
+    // check every target up front, so that nothing is moved or linked when any of them is in the
+    // way
+    let mut in_the_way = Vec::new();
+    let symlinks = symlinks
+        .iter()
+        .map(|symlink| {
+            let source = base_path.join(&symlink.source);
+            let target = PathBuf::from(&*shellexpand::tilde(&symlink.target));
+
+            if target.exists() && !target.is_symlink() {
+                match conflicts {
+                    Conflicts::Fail => anyhow::bail!(
+                        "Target path exists but is not a symlink: {}",
+                        target.display()
+                    ),
+                    Conflicts::Backup => {}
+                    Conflicts::Adopt => {
+                        if source.exists() || source.is_symlink() {
+                            anyhow::bail!(
+                                "Can't adopt {}, {} already exists (use --backup instead)",
+                                target.display(),
+                                source.display()
+                            );
+                        }
+                    }
+                }
+                in_the_way.push((source.clone(), target.clone()));
+            }
+
+            Ok((source, target))
+        })
+        .collect::<Result<Vec<_>>>()?;
 
-    if let Some(parent) = local_nvim_path.parent() {
-        debug!("Creating ~/.config/nvim path: {}", parent.display());
-        if !dry_run {
-            fs::create_dir_all(parent)?;
+    let backup_dir = if in_the_way.is_empty() {
+        None
+    } else {
+        Some(new_backup_dir(&base_path.join(BACKUPS_DIR))?)
+    };
+
+    let result = (|| {
+        if let Some(backup_dir) = &backup_dir {
+            move_out_of_the_way(backup_dir, &in_the_way, conflicts, dry_run)?;
         }
+        create_symlinks(&symlinks, dry_run)
+    })();
+
+    // put back whatever was moved, rather than leaving files stranded in the backup directory
+    if let (Err(err), Some(backup_dir), false) = (&result, &backup_dir, dry_run) {
+        warn!("Failed to set up the symlinks, moving the files that were in the way back");
+        undo_backup(backup_dir).with_context(|| {
+            format!(
+                "Failed to move the files back after: {:#} (see {})",
+                err,
+                backup_dir.display()
+            )
+        })?;
     }
 
-    if let Some(parent) = binutils_local_config_path.parent() {
-        debug!("Creating ~/.config/binutils path: {}", parent.display());
-        if !dry_run {
-            fs::create_dir_all(parent)?;
+    result
+}
+
+fn create_symlinks(symlinks: &[(PathBuf, PathBuf)], dry_run: bool) -> Result<()> {
+    for (source, target) in symlinks {
+        if let Some(parent) = target.parent() {
+            debug!("Creating directory: {}", parent.display());
+            if !dry_run {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+            }
+        }
+
+        if target.is_symlink() {
+            debug!("Removing existing symlink: {}", target.display());
+            if !dry_run {
+                fs::remove_file(target)?;
+            }
         }
-    }
 
-    if local_nvim_path.is_symlink() {
         debug!(
-            "Removing existing nvim config symlink: {}",
-            local_nvim_path.display()
+            "Creating symlink: {} -> {}",
+            target.display(),
+            source.display()
         );
         if !dry_run {
-            fs::remove_file(&local_nvim_path)?;
+            std::os::unix::fs::symlink(source, target).with_context(|| {
+                format!(
+                    "Failed to create symlink: {} -> {}",
+                    target.display(),
+                    source.display()
+                )
+            })?;
         }
-    } else if local_nvim_path.exists() {
-        anyhow::bail!(
-            "Target path exists but is not a symlink: {}",
-            local_nvim_path.display()
-        );
     }
 
-    if binutils_local_config_path.is_symlink() {
-        debug!(
-            "Removing existing nvim config symlink: {}",
-            binutils_local_config_path.display()
-        );
-        if !dry_run {
-            fs::remove_file(&binutils_local_config_path)?;
+    Ok(())
+}
+
+/// The sources of the symlinks whose targets are existing files, which `--adopt` moves into the
+/// local dotfiles.
+fn adopted_sources(base_path: &Path, symlinks: &[Symlink]) -> Vec<PathBuf> {
+    symlinks
+        .iter()
+        .filter(|symlink| {
+            let target = PathBuf::from(&*shellexpand::tilde(&symlink.target));
+            target.exists() && !target.is_symlink()
+        })
+        .map(|symlink| base_path.join(&symlink.source))
+        .collect()
+}
+
+/// Moves the existing files at the targets of symlinks (paired with their sources) into
+/// `backup_dir`, or into the local dotfiles when adopting them, recording each of them in the
+/// backup directory's manifest.
+fn move_out_of_the_way(
+    backup_dir: &Path,
+    in_the_way: &[(PathBuf, PathBuf)],
+    conflicts: Conflicts,
+    dry_run: bool,
+) -> Result<()> {
+    info!("Recording moved files in {}", backup_dir.display());
+
+    if !dry_run {
+        fs::create_dir_all(backup_dir)
+            .with_context(|| format!("Failed to create directory: {}", backup_dir.display()))?;
+        // backups are specific to this machine, so keep them out of the local dotfiles repo
+        let gitignore = backup_dir.with_file_name(".gitignore");
+        fs::write(&gitignore, "*\n")
+            .with_context(|| format!("Failed to write: {}", gitignore.display()))?;
+    }
+
+    let mut manifest = BackupManifest::default();
+    for (source, target) in in_the_way {
+        let moved_to = match conflicts {
+            Conflicts::Adopt => source.clone(),
+            _ => backup_path(backup_dir, target),
+        };
+
+        info!("Moving {} to {}", target.display(), moved_to.display());
+        if dry_run {
+            continue;
         }
-    } else if binutils_local_config_path.exists() {
-        anyhow::bail!(
-            "Target path exists but is not a symlink: {}",
-            binutils_local_config_path.display()
-        );
+
+        if let Some(parent) = moved_to.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+        move_path(target, &moved_to)?;
+
+        // written after every move, so that whatever was moved can be restored even when a later
+        // move fails
+        manifest.entries.push(BackupEntry {
+            target: target.clone(),
+            moved_to,
+        });
+        manifest.write(backup_dir)?;
     }
 
-    debug!(
-        "Creating nvim local lua config symlink: {} -> {}",
-        base_path.join("nvim/lua/local_nvim").display().to_string(),
-        local_nvim_path.display()
-    );
+    Ok(())
+}
+
+/// Moves `from` to `to`, falling back to copying and removing it when they are on different
+/// filesystems (e.g. the home directory and local dotfiles on separate volumes).
+fn move_path(from: &Path, to: &Path) -> Result<()> {
+    let context = || format!("Failed to move {} to {}", from.display(), to.display());
+
+    match fs::rename(from, to) {
+        Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {
+            debug!(
+                "{} and {} are on different filesystems, copying instead",
+                from.display(),
+                to.display()
+            );
+            copy_recursively(from, to).with_context(context)?;
+            if from.is_dir() && !from.is_symlink() {
+                fs::remove_dir_all(from).with_context(context)
+            } else {
+                fs::remove_file(from).with_context(context)
+            }
+        }
+        result => result.with_context(context),
+    }
+}
+
+/// Copies `from` to `to`, preserving symlinks (rather than copying what they point to).
+fn copy_recursively(from: &Path, to: &Path) -> io::Result<()> {
+    let file_type = fs::symlink_metadata(from)?.file_type();
+
+    if file_type.is_symlink() {
+        std::os::unix::fs::symlink(fs::read_link(from)?, to)
+    } else if file_type.is_dir() {
+        fs::create_dir(to)?;
+        fs::set_permissions(to, fs::metadata(from)?.permissions())?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_recursively(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        Ok(())
+    } else {
+        fs::copy(from, to).map(|_| ())
+    }
+}
+
+/// Moves the files that `backup_dir`'s manifest records back (see [`move_back`]), and removes
+/// `backup_dir`.
+fn undo_backup(backup_dir: &Path) -> Result<()> {
+    // nothing was recorded when the first move failed
+    let manifest = if backup_dir.join(BACKUP_MANIFEST_FILE).exists() {
+        BackupManifest::read(backup_dir)?
+    } else {
+        BackupManifest::default()
+    };
+
+    move_back(&manifest.entries, false)?;
+    if backup_dir.exists() {
+        fs::remove_dir_all(backup_dir)
+            .with_context(|| format!("Failed to remove: {}", backup_dir.display()))?;
+    }
+
+    Ok(())
+}
+
+/// Where `target` is moved to within `backup_dir`: at its path relative to the home directory
+/// (or the root directory, when it isn't within the home directory).
+fn backup_path(backup_dir: &Path, target: &Path) -> PathBuf {
+    let home = PathBuf::from(&*shellexpand::tilde("~"));
+    let relative_path = target
+        .strip_prefix(&home)
+        .or_else(|_| target.strip_prefix("/"))
+        .unwrap_or(target);
+
+    backup_dir.join(relative_path)
+}
+
+/// A directory (within `backups_dir`) named after the current time, that doesn't exist yet.
+fn new_backup_dir(backups_dir: &Path) -> Result<PathBuf> {
+    let mut timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .context("System time is before the UNIX epoch")?
+        .as_secs();
+
+    loop {
+        let backup_dir = backups_dir.join(timestamp.to_string());
+        if !backup_dir.exists() {
+            return Ok(backup_dir);
+        }
+        timestamp += 1;
+    }
+}
+
+/// The most recent backup directory within `backups_dir`, if any.
+fn latest_backup_dir(backups_dir: &Path) -> Result<Option<PathBuf>> {
+    if !backups_dir.is_dir() {
+        return Ok(None);
+    }
+
+    let mut latest = None;
+    for entry in fs::read_dir(backups_dir)
+        .with_context(|| format!("Failed to read directory: {}", backups_dir.display()))?
+    {
+        let path = entry?.path();
+        let timestamp = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.parse::<u64>().ok());
+
+        if let Some(timestamp) = timestamp {
+            if path.is_dir()
+                && latest
+                    .as_ref()
+                    .is_none_or(|(latest, _)| timestamp > *latest)
+            {
+                latest = Some((timestamp, path));
+            }
+        }
+    }
+
+    Ok(latest.map(|(_, path)| path))
+}
+
+/// Moves the files of the most recent backup directory back to where they were (replacing the
+/// symlinks that were created in their place), and removes the backup directory.
+fn restore_backup(base_path: &Path, dry_run: bool) -> Result<()> {
+    let backups_dir = base_path.join(BACKUPS_DIR);
+    let Some(backup_dir) = latest_backup_dir(&backups_dir)? else {
+        anyhow::bail!("No backups to restore in {}", backups_dir.display());
+    };
+    let manifest = BackupManifest::read(&backup_dir)?;
+
+    // check every target up front, so that nothing is restored when any of them was replaced
+    for entry in &manifest.entries {
+        if entry.target.exists() && !entry.target.is_symlink() {
+            anyhow::bail!(
+                "Can't restore {}, it exists and is no longer a symlink",
+                entry.target.display()
+            );
+        }
+        if !entry.moved_to.exists() && !entry.moved_to.is_symlink() {
+            anyhow::bail!(
+                "Can't restore {}, {} no longer exists",
+                entry.target.display(),
+                entry.moved_to.display()
+            );
+        }
+    }
+
+    move_back(&manifest.entries, dry_run)?;
+
+    debug!("Removing backup directory: {}", backup_dir.display());
     if !dry_run {
-        std::os::unix::fs::symlink(base_path.join("nvim/lua"), local_nvim_path)
-            .with_context(|| "Failed to create symlink for nvim local_nvim directory")?;
+        fs::remove_dir_all(&backup_dir)
+            .with_context(|| format!("Failed to remove: {}", backup_dir.display()))?;
     }
 
-    debug!(
-        "Creating binutils local config symlink: {} -> {}",
-        base_path
-            .join("binutils/config/local.config.lua")
-            .display()
-            .to_string(),
-        binutils_local_config_path.display().to_string()
+    info!(
+        "Restored {} file(s) from {}",
+        manifest.entries.len(),
+        backup_dir.display()
     );
-    if !dry_run {
-        std::os::unix::fs::symlink(
-            base_path.join("binutils/config/local.config.lua"),
-            binutils_local_config_path,
-        )
-        .with_context(|| "Failed to create symlink for binutils local.config.lua")?;
+    Ok(())
+}
+
+/// Moves the files of `entries` back to where they were (in reverse order), replacing the
+/// symlinks that were created in their place.
+fn move_back(entries: &[BackupEntry], dry_run: bool) -> Result<()> {
+    for entry in entries.iter().rev() {
+        info!(
+            "Moving {} back to {}",
+            entry.moved_to.display(),
+            entry.target.display()
+        );
+        if dry_run {
+            continue;
+        }
+
+        if entry.target.is_symlink() {
+            fs::remove_file(&entry.target)?;
+        }
+        if let Some(parent) = entry.target.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+        move_path(&entry.moved_to, &entry.target)?;
     }
+
     Ok(())
 }
 
@@ -219,6 +568,10 @@ fn run(args: Vec<String>) -> Result<()> {
         info!("Running in dry-run mode - no changes will be made");
     }
 
+    if args.restore {
+        return restore_backup(&local_dotfiles_path, args.dry_run);
+    }
+
     if let Some(repo) = args.repo {
         if local_dotfiles_path.exists() {
             verify_remote_matches(&local_dotfiles_path, &repo)?;
@@ -227,8 +580,35 @@ fn run(args: Vec<String>) -> Result<()> {
         }
     }
 
-    ensure_directory_structure(&local_dotfiles_path, args.dry_run)?;
-    setup_symlinks(&local_dotfiles_path, args.dry_run)?;
+    let config_file = args.config_file.as_ref().map(PathBuf::from);
+    let config = if args.no_config_cache {
+        config::read_config(config_file)?
+    } else {
+        config::read_config_cached(config_file)?
+    };
+    let layout = config.local_dotfiles.unwrap_or_default();
+
+    let conflicts = if args.backup {
+        Conflicts::Backup
+    } else if args.adopt {
+        Conflicts::Adopt
+    } else {
+        Conflicts::Fail
+    };
+
+    let symlinks = layout.symlinks();
+    let mut files = layout.files();
+    if conflicts == Conflicts::Adopt {
+        // adopted files take the place of the ones that would otherwise be created
+        let adopted = adopted_sources(&local_dotfiles_path, &symlinks);
+        files.retain(|file, _| {
+            let path = local_dotfiles_path.join(file);
+            !adopted.iter().any(|source| path.starts_with(source))
+        });
+    }
+
+    ensure_directory_structure(&local_dotfiles_path, &files, args.dry_run)?;
+    setup_symlinks(&local_dotfiles_path, &symlinks, conflicts, args.dry_run)?;
 
     info!("Local dotfiles setup completed successfully!");
     info!(
@@ -254,11 +634,22 @@ fn main() -> Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use insta::assert_debug_snapshot;
+    use config::LocalDotfiles;
+    use insta::{assert_debug_snapshot, assert_snapshot};
     use std::collections::BTreeMap;
     use std::fs;
     use std::process::Command;
-    use test_utils::setup_test_environment;
+    use test_utils::{setup_test_environment, stabilize_home_paths};
+
+    fn run_with(flag: &str) -> Result<()> {
+        run(vec![
+            "setup-local-dotfiles".to_string(),
+            "--local-dotfiles-path".to_string(),
+            "~/local-dotfiles".to_string(),
+            "--no-config-cache".to_string(),
+            flag.to_string(),
+        ])
+    }
 
     fn setup_test_repo(git_repo_path: &PathBuf) -> Result<()> {
         fs::create_dir_all(git_repo_path)?;
@@ -391,7 +782,7 @@ mod tests {
         let env = setup_test_environment();
         let base_path = env.home.join("local-dotfiles");
 
-        ensure_directory_structure(&base_path, false)?;
+        ensure_directory_structure(&base_path, &LocalDotfiles::default().files(), false)?;
 
         let result = fixturify::read(&base_path)?;
         assert_debug_snapshot!(result, @r###"
@@ -414,8 +805,13 @@ mod tests {
         let env = setup_test_environment();
         let base_path = env.home.join("local-dotfiles");
 
-        ensure_directory_structure(&base_path, false)?;
-        setup_symlinks(&base_path, false)?;
+        ensure_directory_structure(&base_path, &LocalDotfiles::default().files(), false)?;
+        setup_symlinks(
+            &base_path,
+            &LocalDotfiles::default().symlinks(),
+            Conflicts::Fail,
+            false,
+        )?;
 
         // Verify symlinks
         let local_nvim_path = env.home.join(".config/nvim/lua/local_nvim");
@@ -490,7 +886,7 @@ mod tests {
         ]);
         fixturify::write(&base_path, &source_files)?;
 
-        ensure_directory_structure(&base_path, false)?;
+        ensure_directory_structure(&base_path, &LocalDotfiles::default().files(), false)?;
 
         let result = fixturify::read(&base_path)?;
         assert_debug_snapshot!(result, @r###"
@@ -518,7 +914,7 @@ mod tests {
             BTreeMap::from([("binutils/crates/.gitkeep".to_string(), "".to_string())]);
         fixturify::write(&base_path, &source_files)?;
 
-        ensure_directory_structure(&base_path, false)?;
+        ensure_directory_structure(&base_path, &LocalDotfiles::default().files(), false)?;
 
         let result = fixturify::read(&base_path)?;
         assert_debug_snapshot!(result, @r###"
@@ -553,7 +949,7 @@ mod tests {
         ]);
         fixturify::write(&base_path, &source_files)?;
 
-        ensure_directory_structure(&base_path, false)?;
+        ensure_directory_structure(&base_path, &LocalDotfiles::default().files(), false)?;
 
         let result = fixturify::read(&base_path)?;
         assert_debug_snapshot!(result, @r###"
@@ -579,12 +975,17 @@ mod tests {
         let base_path = env.home.join("local-dotfiles");
         let local_nvim_path = env.home.join(".config/nvim/lua/local_nvim");
 
-        ensure_directory_structure(&base_path, false)?;
+        ensure_directory_structure(&base_path, &LocalDotfiles::default().files(), false)?;
 
         fs::create_dir_all(local_nvim_path.join("foo-blah"))?;
         fs::write(local_nvim_path.join("foo-blah/foo.lua"), "return 'content'")?;
 
-        let result = setup_symlinks(&base_path, false);
+        let result = setup_symlinks(
+            &base_path,
+            &LocalDotfiles::default().symlinks(),
+            Conflicts::Fail,
+            false,
+        );
 
         assert!(result.is_err());
         assert_eq!(
@@ -598,6 +999,220 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_run_with_configured_layout() -> Result<()> {
+        let env = setup_test_environment();
+
+        fs::write(
+            &env.config_file,
+            r###"
+            return {
+                local_dotfiles = {
+                    files = {
+                        ["ghostty/config"] = "font-size = 14",
+                        ["git/config"] = "",
+                    },
+                    symlinks = {
+                        { source = "ghostty/config", target = "~/.config/ghostty/local.config" },
+                        { source = "git", target = "~/.config/git/local" },
+                    },
+                },
+            }
+            "###,
+        )?;
+
+        run(vec![
+            "setup-local-dotfiles".to_string(),
+            "--local-dotfiles-path".to_string(),
+            "~/src/workstuff/local-dotfiles".to_string(),
+            "--no-config-cache".to_string(),
+        ])?;
+
+        let base_path = env.home.join("src/workstuff/local-dotfiles");
+        assert_debug_snapshot!(fixturify::read(&base_path)?, @r###"
+        {
+            "ghostty/config": "font-size = 14",
+            "git/config": "",
+        }
+        "###);
+
+        assert_eq!(
+            fs::read_link(env.home.join(".config/ghostty/local.config"))?,
+            base_path.join("ghostty/config")
+        );
+        assert_eq!(
+            fs::read_link(env.home.join(".config/git/local"))?,
+            base_path.join("git")
+        );
+        assert!(!env.home.join(".config/nvim/lua/local_nvim").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_with_backup_and_restore() -> Result<()> {
+        let env = setup_test_environment();
+        let base_path = env.home.join("local-dotfiles");
+        let local_nvim_path = env.home.join(".config/nvim/lua/local_nvim");
+
+        fs::create_dir_all(&local_nvim_path)?;
+        fs::write(local_nvim_path.join("init.lua"), "-- existing")?;
+
+        run_with("--backup")?;
+
+        assert_eq!(fs::read_link(&local_nvim_path)?, base_path.join("nvim/lua"));
+        let backup_dir = latest_backup_dir(&base_path.join(BACKUPS_DIR))?.unwrap();
+        let backups = fixturify::read(&backup_dir)?;
+        assert_eq!(
+            backups.get(".config/nvim/lua/local_nvim/init.lua"),
+            Some(&"-- existing".to_string())
+        );
+        assert_snapshot!(
+            stabilize_home_paths(&env, &backups[BACKUP_MANIFEST_FILE])
+                .replace(&*backup_dir.file_name().unwrap().to_string_lossy(), "<timestamp>"),
+            @r###"
+        {
+          "entries": [
+            {
+              "target": "~/.config/nvim/lua/local_nvim",
+              "moved_to": "~/local-dotfiles/.backups/<timestamp>/.config/nvim/lua/local_nvim"
+            }
+          ]
+        }
+        "###
+        );
+
+        run_with("--restore")?;
+
+        assert!(!local_nvim_path.is_symlink());
+        assert_eq!(
+            fs::read_to_string(local_nvim_path.join("init.lua"))?,
+            "-- existing"
+        );
+        assert!(!backup_dir.exists());
+
+        let err = run_with("--restore").unwrap_err();
+        assert_snapshot!(
+            stabilize_home_paths(&env, &err.to_string()),
+            @"No backups to restore in ~/local-dotfiles/.backups"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_with_adopt_and_restore() -> Result<()> {
+        let env = setup_test_environment();
+        let base_path = env.home.join("local-dotfiles");
+        let binutils_local_config_path = env.home.join(".config/binutils/local.config.lua");
+
+        fs::create_dir_all(binutils_local_config_path.parent().unwrap())?;
+        fs::write(&binutils_local_config_path, "return { tmux = nil }")?;
+
+        run_with("--adopt")?;
+
+        let source = base_path.join("binutils/config/local.config.lua");
+        assert_eq!(fs::read_link(&binutils_local_config_path)?, source);
+        assert_eq!(fs::read_to_string(&source)?, "return { tmux = nil }");
+        assert!(base_path.join("nvim/snippets/.gitkeep").exists());
+
+        run_with("--restore")?;
+
+        assert!(!binutils_local_config_path.is_symlink());
+        assert_eq!(
+            fs::read_to_string(&binutils_local_config_path)?,
+            "return { tmux = nil }"
+        );
+        assert!(!source.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_setup_symlinks_adopt_errors_on_existing_source() -> Result<()> {
+        let env = setup_test_environment();
+        let base_path = env.home.join("local-dotfiles");
+        let local_nvim_path = env.home.join(".config/nvim/lua/local_nvim");
+        let symlinks = LocalDotfiles::default().symlinks();
+
+        ensure_directory_structure(&base_path, &LocalDotfiles::default().files(), false)?;
+        fs::create_dir_all(&local_nvim_path)?;
+
+        let err = setup_symlinks(&base_path, &symlinks, Conflicts::Adopt, false).unwrap_err();
+        assert_snapshot!(
+            stabilize_home_paths(&env, &err.to_string()),
+            @"Can't adopt ~/.config/nvim/lua/local_nvim, ~/local-dotfiles/nvim/lua already exists (use --backup instead)"
+        );
+        assert!(!local_nvim_path.is_symlink());
+        assert!(!base_path.join(BACKUPS_DIR).exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_setup_symlinks_moves_files_back_on_error() -> Result<()> {
+        let env = setup_test_environment();
+        let base_path = env.home.join("local-dotfiles");
+        let ghostty_config_path = env.home.join(".config/ghostty/local.config");
+
+        fs::create_dir_all(ghostty_config_path.parent().unwrap())?;
+        fs::write(&ghostty_config_path, "font-size = 14")?;
+        // a file where the directory of the second symlink would go
+        fs::write(env.home.join(".config/git"), "")?;
+
+        let symlinks = [
+            Symlink {
+                source: "ghostty/config".to_string(),
+                target: "~/.config/ghostty/local.config".to_string(),
+            },
+            Symlink {
+                source: "git".to_string(),
+                target: "~/.config/git/local".to_string(),
+            },
+        ];
+        let err = setup_symlinks(&base_path, &symlinks, Conflicts::Backup, false).unwrap_err();
+        assert_snapshot!(
+            stabilize_home_paths(&env, &err.to_string()),
+            @"Failed to create directory: ~/.config/git"
+        );
+
+        assert!(!ghostty_config_path.is_symlink());
+        assert_eq!(fs::read_to_string(&ghostty_config_path)?, "font-size = 14");
+        assert_eq!(latest_backup_dir(&base_path.join(BACKUPS_DIR))?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_recursively() -> Result<()> {
+        let env = setup_test_environment();
+        let from = env.home.join("from");
+        let to = env.home.join("to");
+
+        fixturify::write(
+            &from,
+            &BTreeMap::from([
+                ("init.lua".to_string(), "-- init".to_string()),
+                ("lua/plugins.lua".to_string(), "return {}".to_string()),
+            ]),
+        )?;
+        std::os::unix::fs::symlink("init.lua", from.join("link.lua"))?;
+
+        copy_recursively(&from, &to)?;
+
+        assert_debug_snapshot!(fixturify::read(&to)?, @r###"
+        {
+            "init.lua": "-- init",
+            "link.lua": "-- init",
+            "lua/plugins.lua": "return {}",
+        }
+        "###);
+        assert_eq!(fs::read_link(to.join("link.lua"))?, Path::new("init.lua"));
+        assert!(from.exists());
+
+        Ok(())
+    }
+
     #[test]
     fn test_setup_symlinks_handles_broken_symlinks() -> Result<()> {
         let env = setup_test_environment();
@@ -610,8 +1225,13 @@ mod tests {
         std::os::unix::fs::symlink(Path::new("/tmp/invalid"), local_nvim_path)
             .with_context(|| "Failed to create broken symlink for test")?;
 
-        ensure_directory_structure(&base_path, false)?;
-        setup_symlinks(&base_path, false)?;
+        ensure_directory_structure(&base_path, &LocalDotfiles::default().files(), false)?;
+        setup_symlinks(
+            &base_path,
+            &LocalDotfiles::default().symlinks(),
+            Conflicts::Fail,
+            false,
+        )?;
 
         let local_nvim_path = env.home.join(".config/nvim/lua/local_nvim");
         assert!(local_nvim_path.exists());
@@ -634,7 +1254,7 @@ mod tests {
         )]);
         fixturify::write(&base_path, &source_files)?;
 
-        ensure_directory_structure(&base_path, false)?;
+        ensure_directory_structure(&base_path, &LocalDotfiles::default().files(), false)?;
 
         let result = fixturify::read(&base_path)?;
         assert_eq!(
@@ -2,7 +2,7 @@ use std::path::PathBuf;
 
 use anyhow::Result;
 use clap::Parser;
-use config::read_config;
+use config::{read_config, read_config_cached};
 use shared_global::tmux::{TmuxOptions, startup_tmux};
 use tracing::debug;
 use tracing_subscriber::EnvFilter;
@@ -30,6 +30,10 @@ struct CliTmuxOptions {
     /// Path to the configuration file. Defaults to `~/.config/binutils/config.yaml`.
     #[arg(long)]
     config_file: Option<String>,
+
+    /// Always evaluate the Lua config instead of reusing the cached result.
+    #[arg(long)]
+    no_config_cache: bool,
 }
 
 impl TmuxOptions for CliTmuxOptions {
@@ -65,7 +69,11 @@ fn main() -> Result<()> {
     latest_bin::ensure_latest_bin()?;
 
     let options = CliTmuxOptions::parse();
-    let config = read_config(options.config_file())?;
+    let config = if options.no_config_cache {
+        read_config(options.config_file())?
+    } else {
+        read_config_cached(options.config_file())?
+    };
     debug!("Using config: \n{:#?}", config);
 
     let commands = startup_tmux(&config, &options)?;
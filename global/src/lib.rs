@@ -1,2 +1,3 @@
 pub mod build_utils;
+pub mod shell_cache;
 pub mod tmux;
@@ -0,0 +1,276 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Output, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::trace;
+
+use super::directive::{format_duration, parse_duration, quote_option_value};
+
+/// How often a command with a timeout is checked for having exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The shells that commands can be run with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Shell {
+    #[default]
+    Sh,
+    Bash,
+    Zsh,
+}
+
+impl Shell {
+    fn parse(value: &str) -> Result<Self> {
+        match value {
+            "sh" => Ok(Self::Sh),
+            "bash" => Ok(Self::Bash),
+            "zsh" => Ok(Self::Zsh),
+            _ => anyhow::bail!(
+                "Unknown shell `{}`, expected one of `sh`, `bash` or `zsh`",
+                value
+            ),
+        }
+    }
+
+    pub(crate) fn program(self) -> &'static str {
+        match self {
+            Self::Sh => "sh",
+            Self::Bash => "bash",
+            Self::Zsh => "zsh",
+        }
+    }
+}
+
+/// How the commands of `# CMD:` (and `# ENV:`) directives are run. The defaults come from the
+/// config, and can be overridden per directive (e.g. `# CMD[timeout=10s,shell=zsh]: ...`).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CommandOptions {
+    /// How long the command may run before it is killed (and fails).
+    pub timeout: Option<Duration>,
+    /// Which shell runs the command. Defaults to `sh`.
+    pub shell: Option<Shell>,
+    /// The working directory of the command (`~` is expanded). Defaults to the current directory.
+    pub cwd: Option<String>,
+    /// Additional environment variables for the command.
+    pub env: BTreeMap<String, String>,
+    /// Whether a failure is rendered as a commented error (instead of aborting).
+    pub allow_failure: Option<bool>,
+}
+
+impl CommandOptions {
+    /// The keys that [`CommandOptions::set`] accepts.
+    pub(crate) const KEYS: &[&str] = &["timeout", "shell", "cwd", "env", "allow_failure"];
+
+    /// Sets an option from its textual form, as used within directives (e.g. `timeout=10s` or
+    /// `env=NAME=value`, quoted as `env="NAME=a,b"` when the value contains `,` or `]`).
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "timeout" => self.timeout = Some(parse_duration(value)?),
+            "shell" => self.shell = Some(Shell::parse(value)?),
+            "cwd" => self.cwd = Some(value.to_string()),
+            "env" => {
+                let (name, value) = value.split_once('=').with_context(|| {
+                    format!("Invalid env `{}`, expected `env=NAME=value`", value)
+                })?;
+                self.env.insert(name.to_string(), value.to_string());
+            }
+            "allow_failure" => {
+                self.allow_failure = Some(value.parse().with_context(|| {
+                    format!(
+                        "Invalid allow_failure `{}`, expected `true` or `false`",
+                        value
+                    )
+                })?)
+            }
+            _ => anyhow::bail!("Unknown command option `{}`", key),
+        }
+
+        Ok(())
+    }
+
+    /// The defaults that a `shell_caching` entry of the config sets.
+    pub fn from_config(shell_caching: &config::ShellCache) -> Result<Self> {
+        let mut options = Self::default();
+
+        for (key, value) in [
+            ("timeout", &shell_caching.timeout),
+            ("shell", &shell_caching.shell),
+            ("cwd", &shell_caching.cwd),
+        ] {
+            if let Some(value) = value {
+                options
+                    .set(key, value)
+                    .with_context(|| format!("Invalid `shell_caching.{}` in the config", key))?;
+            }
+        }
+        options.env = shell_caching.env.clone().unwrap_or_default();
+        options.allow_failure = shell_caching.allow_failure;
+
+        Ok(options)
+    }
+
+    /// Returns these options, with the ones that are set in `overrides` taking precedence.
+    pub(crate) fn merge(&self, overrides: &CommandOptions) -> CommandOptions {
+        let mut env = self.env.clone();
+        env.extend(overrides.env.clone());
+
+        CommandOptions {
+            timeout: overrides.timeout.or(self.timeout),
+            shell: overrides.shell.or(self.shell),
+            cwd: overrides.cwd.clone().or_else(|| self.cwd.clone()),
+            env,
+            allow_failure: overrides.allow_failure.or(self.allow_failure),
+        }
+    }
+
+    pub(crate) fn allows_failure(&self) -> bool {
+        self.allow_failure.unwrap_or(false)
+    }
+
+    /// The options that are set, in their textual form (see [`CommandOptions::set`]).
+    pub(crate) fn to_directive_options(&self) -> Vec<String> {
+        let mut options = Vec::new();
+
+        if let Some(timeout) = self.timeout {
+            options.push(format!("timeout={}", format_duration(timeout)));
+        }
+        if let Some(shell) = self.shell {
+            options.push(format!("shell={}", shell.program()));
+        }
+        if let Some(cwd) = &self.cwd {
+            options.push(format!("cwd={}", quote_option_value(cwd)));
+        }
+        for (name, value) in &self.env {
+            let env = format!("{}={}", name, value);
+            options.push(format!("env={}", quote_option_value(&env)));
+        }
+        if let Some(allow_failure) = self.allow_failure {
+            options.push(format!("allow_failure={}", allow_failure));
+        }
+
+        options
+    }
+
+    /// The parts of the options that affect the output of a command, for caching it.
+    pub(crate) fn cache_key(&self) -> Vec<String> {
+        let mut key = vec![
+            self.shell.unwrap_or_default().program().to_string(),
+            self.cwd.clone().unwrap_or_default(),
+        ];
+        key.extend(
+            self.env
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value)),
+        );
+
+        key
+    }
+}
+
+/// Runs `command` (in the configured shell), returning its stdout.
+pub(crate) fn run_command(command: &str, options: &CommandOptions) -> Result<String> {
+    trace!("Running command: {}", &command);
+
+    let output = execute(command, options)?;
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        let error_message = format!(
+            "Failed to run command (`{}`):\n{}",
+            &command,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        anyhow::bail!("{}", error_message);
+    }
+}
+
+/// Runs `command` (in the configured shell), returning whether it exits successfully. Only
+/// failing to run it at all (e.g. timing out) is an error.
+pub(crate) fn command_succeeds(command: &str, options: &CommandOptions) -> Result<bool> {
+    trace!("Running command: {}", command);
+
+    Ok(execute(command, options)?.status.success())
+}
+
+fn execute(command: &str, options: &CommandOptions) -> Result<Output> {
+    let shell = options.shell.unwrap_or_default();
+    let mut process = Command::new(shell.program());
+    process.arg("-c").arg(command).envs(&options.env);
+    if let Some(cwd) = &options.cwd {
+        process.current_dir(shellexpand::tilde(cwd).as_ref());
+    }
+
+    match options.timeout {
+        Some(timeout) => output_with_timeout(process, timeout),
+        None => process.output().map_err(anyhow::Error::from),
+    }
+    .context(format!("Failed to execute command (`{}`)", &command))
+}
+
+/// Like [`Command::output`], but kills the process (and any processes it spawned) once it has
+/// been running for `timeout`.
+fn output_with_timeout(mut process: Command, timeout: Duration) -> Result<Output> {
+    // run the command in its own process group, so that processes it spawned are killed with it
+    let mut child = process
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()?;
+    // the process group has the id of the process that leads it
+    let process_group = child.id() as libc::pid_t;
+    let kill_process_group = || {
+        // SAFETY: `killpg` has no memory safety requirements
+        unsafe { libc::killpg(process_group, libc::SIGKILL) };
+    };
+
+    // read both pipes while waiting, so the process never blocks on a full pipe
+    let stdout = read_in_background(child.stdout.take());
+    let stderr = read_in_background(child.stderr.take());
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+
+        if Instant::now() >= deadline {
+            kill_process_group();
+            let _ = child.wait();
+            anyhow::bail!("Timed out after {}", format_duration(timeout));
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    };
+
+    // processes spawned in the background may keep the pipes open after the command exited
+    let read_before_deadline = |pipe: Receiver<Vec<u8>>| {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        pipe.recv_timeout(remaining).map_err(|_| {
+            kill_process_group();
+            anyhow::anyhow!("Timed out after {}", format_duration(timeout))
+        })
+    };
+
+    Ok(Output {
+        status,
+        stdout: read_before_deadline(stdout)?,
+        stderr: read_before_deadline(stderr)?,
+    })
+}
+
+fn read_in_background(pipe: Option<impl Read + Send + 'static>) -> Receiver<Vec<u8>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut contents = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut contents);
+        }
+        let _ = sender.send(contents);
+    });
+    receiver
+}
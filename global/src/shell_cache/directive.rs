@@ -0,0 +1,291 @@
+use anyhow::{Context, Result};
+use std::time::Duration;
+
+/// A `# NAME: argument` line, optionally with options (e.g. `# CMD[ttl=7d]: brew shellenv`).
+#[derive(Debug, PartialEq)]
+pub(crate) struct Directive<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) options: Vec<(&'a str, &'a str)>,
+    pub(crate) argument: &'a str,
+}
+
+/// The names of the directives, any other `# NAME: ...` comment (e.g. `# TODO[urgent]: ...`) is
+/// left as is.
+const DIRECTIVE_NAMES: &[&str] = &[
+    "CMD",
+    "CMD_SILENT",
+    "EVAL_ONCE",
+    "FETCH",
+    "ENV",
+    "INCLUDE",
+    "FILE",
+    "IF",
+];
+
+/// Parses the directive on the given line, returning `None` for lines that aren't directives.
+/// Directives are comments, which start with `comment` (e.g. `#`) followed by a space.
+pub(crate) fn parse_directive<'a>(line: &'a str, comment: &str) -> Result<Option<Directive<'a>>> {
+    let Some(rest) = line
+        .trim_start()
+        .strip_prefix(comment)
+        .and_then(|rest| rest.strip_prefix(' '))
+    else {
+        return Ok(None);
+    };
+
+    let name_len = rest
+        .find(|ch: char| !(ch.is_ascii_uppercase() || ch == '_'))
+        .unwrap_or(rest.len());
+    let (name, rest) = rest.split_at(name_len);
+    if !DIRECTIVE_NAMES.contains(&name) {
+        return Ok(None);
+    }
+
+    let (options, rest) = match rest.strip_prefix('[') {
+        Some(rest) => {
+            let Some(end) = find_unquoted(rest, ']') else {
+                return Ok(None);
+            };
+            (
+                parse_options(comment, name, &rest[..end])?,
+                &rest[end + 1..],
+            )
+        }
+        None => (vec![], rest),
+    };
+
+    let Some(argument) = rest.strip_prefix(':') else {
+        return Ok(None);
+    };
+
+    Ok(Some(Directive {
+        name,
+        options,
+        argument: argument.trim(),
+    }))
+}
+
+/// Parses `key=value` options separated by `,`. Values can be quoted (e.g. `env="PATH=a,b"`) to
+/// contain `,` or `]`.
+fn parse_options<'a>(
+    comment: &str,
+    name: &str,
+    mut options: &'a str,
+) -> Result<Vec<(&'a str, &'a str)>> {
+    let mut parsed = Vec::new();
+
+    while !options.is_empty() {
+        let end = find_unquoted(options, ',').unwrap_or(options.len());
+        let option = options[..end].trim();
+        options = options.get(end + 1..).unwrap_or_default();
+        if option.is_empty() {
+            continue;
+        }
+
+        let (key, value) = option
+            .split_once('=')
+            .map(|(key, value)| (key.trim(), value.trim()))
+            .with_context(|| {
+                format!(
+                    "Invalid option `{}` for `{} {}:`, expected `key=value`",
+                    option, comment, name
+                )
+            })?;
+        let value = match value.strip_prefix('"') {
+            Some(quoted) => quoted.strip_suffix('"').with_context(|| {
+                format!(
+                    "Invalid option `{}` for `{} {}:`, the quoted value is not terminated",
+                    option, comment, name
+                )
+            })?,
+            None => value,
+        };
+        parsed.push((key, value));
+    }
+
+    Ok(parsed)
+}
+
+/// Returns the index of the first `needle` that isn't within double quotes.
+fn find_unquoted(haystack: &str, needle: char) -> Option<usize> {
+    let mut quoted = false;
+    for (index, ch) in haystack.char_indices() {
+        if ch == '"' {
+            quoted = !quoted;
+        } else if ch == needle && !quoted {
+            return Some(index);
+        }
+    }
+    None
+}
+
+/// Formats an option value for use within directives, quoting it if it contains `,` or `]`.
+pub(crate) fn quote_option_value(value: &str) -> String {
+    if value.contains([',', ']']) {
+        format!("\"{}\"", value)
+    } else {
+        value.to_string()
+    }
+}
+
+/// Parses durations like `30s`, `15m`, `12h`, `7d` or `2w`.
+pub(crate) fn parse_duration(value: &str) -> Result<Duration> {
+    let unit_start = value
+        .find(|ch: char| !ch.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(unit_start);
+
+    let amount: u64 = amount
+        .parse()
+        .with_context(|| format!("Invalid duration `{}`, expected e.g. `7d`", value))?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => anyhow::bail!(
+            "Invalid duration unit in `{}`, expected one of `s`, `m`, `h`, `d` or `w`",
+            value
+        ),
+    };
+
+    let seconds = amount
+        .checked_mul(seconds)
+        .with_context(|| format!("Invalid duration `{}`, it is too long", value))?;
+
+    Ok(Duration::from_secs(seconds))
+}
+
+/// Formats a duration using the largest unit that represents it exactly.
+pub(crate) fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+
+    for (unit, unit_seconds) in [("d", 24 * 60 * 60), ("h", 60 * 60), ("m", 60)] {
+        if seconds > 0 && seconds % unit_seconds == 0 {
+            return format!("{}{}", seconds / unit_seconds, unit);
+        }
+    }
+
+    format!("{}s", seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use insta::{assert_debug_snapshot, assert_snapshot};
+
+    #[test]
+    fn test_parse_directive() {
+        assert_debug_snapshot!(parse_directive("  # CMD[ttl=7d, extra = 1]: brew shellenv", "#").unwrap(), @r###"
+        Some(
+            Directive {
+                name: "CMD",
+                options: [
+                    (
+                        "ttl",
+                        "7d",
+                    ),
+                    (
+                        "extra",
+                        "1",
+                    ),
+                ],
+                argument: "brew shellenv",
+            },
+        )
+        "###);
+        assert_debug_snapshot!(parse_directive("# FETCH:http://example.com", "#").unwrap(), @r###"
+        Some(
+            Directive {
+                name: "FETCH",
+                options: [],
+                argument: "http://example.com",
+            },
+        )
+        "###);
+        assert_debug_snapshot!(parse_directive("# Not a directive: really", "#").unwrap(), @"None");
+        assert_debug_snapshot!(parse_directive("# TODO[urgent]: fix", "#").unwrap(), @"None");
+        assert_debug_snapshot!(parse_directive("# NOTE[x]: see above", "#").unwrap(), @"None");
+        assert_debug_snapshot!(parse_directive("#CMD: missing space", "#").unwrap(), @"None");
+        assert_debug_snapshot!(parse_directive("# CMD: other comments", "--").unwrap(), @"None");
+        assert_debug_snapshot!(parse_directive("-- CMD: brew shellenv", "--").unwrap(), @r###"
+        Some(
+            Directive {
+                name: "CMD",
+                options: [],
+                argument: "brew shellenv",
+            },
+        )
+        "###);
+    }
+
+    #[test]
+    fn test_parse_directive_invalid_options() {
+        let err = parse_directive("# CMD[ttl]: echo hello", "#").unwrap_err();
+        assert_snapshot!(err, @"Invalid option `ttl` for `# CMD:`, expected `key=value`");
+
+        let err = parse_directive("-- CMD[ttl]: echo hello", "--").unwrap_err();
+        assert_snapshot!(err, @"Invalid option `ttl` for `-- CMD:`, expected `key=value`");
+
+        // without the closing quote, the options never end
+        let directive = parse_directive(r#"# CMD[env="PATH=a,b]: echo hello"#, "#").unwrap();
+        assert_debug_snapshot!(directive, @"None");
+
+        let err = parse_directive(r#"# CMD[env="PATH=a"b]: echo hello"#, "#").unwrap_err();
+        assert_snapshot!(err, @"Invalid option `env=\"PATH=a\"b` for `# CMD:`, the quoted value is not terminated");
+    }
+
+    #[test]
+    fn test_parse_directive_quoted_options() {
+        let directive = parse_directive(
+            r#"# CMD[env="PATH=a,b", cwd="~/x]y", timeout=1s]: echo hello"#,
+            "#",
+        );
+        assert_debug_snapshot!(directive.unwrap(), @r###"
+        Some(
+            Directive {
+                name: "CMD",
+                options: [
+                    (
+                        "env",
+                        "PATH=a,b",
+                    ),
+                    (
+                        "cwd",
+                        "~/x]y",
+                    ),
+                    (
+                        "timeout",
+                        "1s",
+                    ),
+                ],
+                argument: "echo hello",
+            },
+        )
+        "###);
+        assert_snapshot!(quote_option_value("PATH=a,b"), @"\"PATH=a,b\"");
+        assert_snapshot!(quote_option_value("PATH=a"), @"PATH=a");
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        let durations: Vec<String> = ["30s", "15m", "12h", "7d", "2w", "90m"]
+            .iter()
+            .map(|value| format_duration(parse_duration(value).unwrap()))
+            .collect();
+
+        assert_debug_snapshot!(durations, @r###"
+        [
+            "30s",
+            "15m",
+            "12h",
+            "7d",
+            "14d",
+            "90m",
+        ]
+        "###);
+        assert_snapshot!(parse_duration("7 days").unwrap_err(), @"Invalid duration unit in `7 days`, expected one of `s`, `m`, `h`, `d` or `w`");
+        assert_snapshot!(parse_duration("99999999999999999w").unwrap_err(), @"Invalid duration `99999999999999999w`, it is too long");
+    }
+}
@@ -0,0 +1,149 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use super::manifest::MANIFEST_FILE;
+
+/// Compares freshly generated output (in `generated_dir`) with the existing destination,
+/// returning a unified diff for every file that differs.
+///
+/// Files that only exist in the destination are reported too when `include_extra_files` is set
+/// (i.e. when the destination would be cleared before copying the generated output over).
+pub fn diff_directories(
+    generated_dir: &Path,
+    dest_dir: &Path,
+    include_extra_files: bool,
+) -> Result<Vec<String>> {
+    let mut relative_paths = relative_file_paths(generated_dir)?;
+    if include_extra_files && dest_dir.exists() {
+        relative_paths.extend(relative_file_paths(dest_dir)?);
+    }
+
+    let mut diffs = Vec::new();
+    for relative_path in relative_paths {
+        let generated = read_if_exists(&generated_dir.join(&relative_path))?;
+        let dest_file = dest_dir.join(&relative_path);
+        let existing = read_if_exists(&dest_file)?;
+
+        if generated == existing {
+            continue;
+        }
+
+        let dest_name = dest_file.display().to_string();
+        let diff = similar::TextDiff::from_lines(existing.as_str(), generated.as_str())
+            .unified_diff()
+            .header(&dest_name, &format!("{} (generated)", dest_name))
+            .to_string();
+        diffs.push(diff);
+    }
+
+    Ok(diffs)
+}
+
+fn relative_file_paths(dir: &Path) -> Result<BTreeSet<PathBuf>> {
+    let mut paths = BTreeSet::new();
+
+    for entry in WalkDir::new(dir) {
+        let entry =
+            entry.with_context(|| format!("Failed to read directory: {}", dir.display()))?;
+        if entry.file_type().is_dir() {
+            continue;
+        }
+
+        let relative_path = entry
+            .path()
+            .strip_prefix(dir)
+            .context("Failed to get relative path")?;
+        // the manifest differs whenever anything does, so it's not worth reporting
+        if relative_path == Path::new(MANIFEST_FILE) {
+            continue;
+        }
+        paths.insert(relative_path.to_path_buf());
+    }
+
+    Ok(paths)
+}
+
+/// Reads `path`, treating missing files as empty.
+fn read_if_exists(path: &Path) -> Result<String> {
+    match fs::read(path) {
+        Ok(contents) => Ok(String::from_utf8_lossy(&contents).to_string()),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(String::new()),
+        Err(err) => Err(err).with_context(|| format!("Failed to read file: {}", path.display())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use insta::assert_snapshot;
+    use std::collections::BTreeMap;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_diff_directories() -> Result<()> {
+        let dir = tempdir()?;
+        let generated_dir = dir.path().join("generated");
+        let dest_dir = dir.path().join("dist");
+
+        fixturify::write(
+            &generated_dir,
+            &BTreeMap::from([
+                ("zshrc".to_string(), "export A=1\nexport B=2\n".to_string()),
+                (
+                    "plugins/new.zsh".to_string(),
+                    "alias ll='ls -l'\n".to_string(),
+                ),
+                ("unchanged.zsh".to_string(), "same\n".to_string()),
+            ]),
+        )?;
+        fixturify::write(
+            &dest_dir,
+            &BTreeMap::from([
+                ("zshrc".to_string(), "export A=1\nexport B=1\n".to_string()),
+                ("stale.zsh".to_string(), "old\n".to_string()),
+                ("unchanged.zsh".to_string(), "same\n".to_string()),
+            ]),
+        )?;
+
+        let stabilize = |diffs: Vec<String>| {
+            diffs
+                .concat()
+                .replace(&dest_dir.display().to_string(), "{dest}")
+        };
+
+        assert_snapshot!(stabilize(diff_directories(&generated_dir, &dest_dir, true)?), @r###"
+        --- {dest}/plugins/new.zsh
+        +++ {dest}/plugins/new.zsh (generated)
+        @@ -0,0 +1 @@
+        +alias ll='ls -l'
+        --- {dest}/stale.zsh
+        +++ {dest}/stale.zsh (generated)
+        @@ -1 +0,0 @@
+        -old
+        --- {dest}/zshrc
+        +++ {dest}/zshrc (generated)
+        @@ -1,2 +1,2 @@
+         export A=1
+        -export B=1
+        +export B=2
+        "###);
+        assert_snapshot!(stabilize(diff_directories(&generated_dir, &dest_dir, false)?), @r###"
+        --- {dest}/plugins/new.zsh
+        +++ {dest}/plugins/new.zsh (generated)
+        @@ -0,0 +1 @@
+        +alias ll='ls -l'
+        --- {dest}/zshrc
+        +++ {dest}/zshrc (generated)
+        @@ -1,2 +1,2 @@
+         export A=1
+        -export B=1
+        +export B=2
+        "###);
+
+        Ok(())
+    }
+}
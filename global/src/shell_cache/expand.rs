@@ -0,0 +1,128 @@
+use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use tracing::trace;
+
+use super::command::command_succeeds;
+use super::{CommandOptions, LineAction, parse_line};
+
+/// Reads `file` into `lines`, expanding the directives that are resolved while reading (rather
+/// than executed later): `# INCLUDE:`, `# FILE:` and `# IF:` / `# ENDIF` blocks.
+///
+/// Directives (and the markers around the expanded content) are comments starting with `comment`.
+/// Conditions are run like commands with `command_defaults`. `include_stack` holds the
+/// (canonical) files that are currently being read, to detect cycles.
+pub(super) fn expand_file(
+    file: &Path,
+    comment: &str,
+    command_defaults: &CommandOptions,
+    include_stack: &mut Vec<PathBuf>,
+    lines: &mut Vec<LineAction>,
+) -> Result<()> {
+    let canonical_file = file
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve path: {}", file.display()))?;
+    if include_stack.contains(&canonical_file) {
+        let cycle: Vec<String> = include_stack
+            .iter()
+            .chain([&canonical_file])
+            .map(|path| path.display().to_string())
+            .collect();
+        anyhow::bail!("Include cycle detected: {}", cycle.join(" -> "));
+    }
+    include_stack.push(canonical_file);
+
+    let reader = BufReader::new(File::open(file).context("Failed to open file for reading")?);
+
+    // whether the condition of each enclosing `# IF:` block holds
+    let mut conditions: Vec<bool> = Vec::new();
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.context("Failed to read line")?;
+        let action = parse_line(&line, comment)?;
+        let active = conditions.iter().all(|holds| *holds);
+
+        match action {
+            LineAction::If(condition) => {
+                conditions.push(active && condition_holds(&condition, command_defaults)?);
+                lines.push(LineAction::Other(line));
+            }
+            LineAction::EndIf => {
+                conditions.pop().with_context(|| {
+                    format!(
+                        "`{comment} ENDIF` without a matching `{comment} IF:` (line {} of {})",
+                        index + 1,
+                        file.display()
+                    )
+                })?;
+                lines.push(LineAction::Other(line));
+            }
+            _ if !active => trace!("Skipping line within a false `# IF:` block: {}", line),
+            LineAction::Include(path) => {
+                let included_file = resolve_path(file, &path);
+
+                lines.push(LineAction::Other(line));
+                lines.push(LineAction::Other(format!(
+                    "{} INCLUDE START: {}",
+                    comment, path
+                )));
+                expand_file(
+                    &included_file,
+                    comment,
+                    command_defaults,
+                    include_stack,
+                    lines,
+                )
+                .with_context(|| format!("Failed to include {}", included_file.display()))?;
+                lines.push(LineAction::Other(format!(
+                    "{} INCLUDE END: {}",
+                    comment, path
+                )));
+            }
+            LineAction::File(path) => {
+                let inlined_file = resolve_path(file, &path);
+                let content = fs::read_to_string(&inlined_file)
+                    .with_context(|| format!("Failed to read file: {}", inlined_file.display()))?;
+
+                lines.push(LineAction::Other(line));
+                lines.push(LineAction::Other(format!(
+                    "{comment} FILE CONTENT START: {path}\n{content}\n{comment} FILE CONTENT END: {path}"
+                )));
+            }
+            action => lines.push(action),
+        }
+    }
+
+    if !conditions.is_empty() {
+        anyhow::bail!(
+            "{} `{comment} IF:` block(s) without a matching `{comment} ENDIF` in {}",
+            conditions.len(),
+            file.display()
+        );
+    }
+
+    include_stack.pop();
+
+    Ok(())
+}
+
+/// Resolves a path given in a directive of `file`, which is relative to the directory containing
+/// `file` (unless it is absolute or starts with `~`).
+fn resolve_path(file: &Path, path: &str) -> PathBuf {
+    let path = PathBuf::from(shellexpand::tilde(path).to_string());
+
+    match file.parent() {
+        Some(parent) if path.is_relative() => parent.join(path),
+        _ => path,
+    }
+}
+
+/// Runs the condition of an `# IF:` directive (e.g. `command -v brew`), which holds when it exits
+/// successfully.
+fn condition_holds(condition: &str, command_defaults: &CommandOptions) -> Result<bool> {
+    trace!("Evaluating condition: {}", condition);
+
+    command_succeeds(condition, command_defaults)
+        .with_context(|| format!("Failed to evaluate condition (`{}`)", condition))
+}
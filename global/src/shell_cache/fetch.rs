@@ -0,0 +1,204 @@
+use anyhow::{Context, Result};
+use lua_config_utils::cache::write_json;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use tracing::{debug, trace, warn};
+
+use super::manifest::sha256_hex;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedContent {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    content: String,
+}
+
+/// A persistent cache of the content fetched by `# FETCH:` directives, which is revalidated (using
+/// `ETag` and `Last-Modified`) instead of downloaded again.
+#[derive(Debug, Clone)]
+pub struct FetchCache {
+    dir: PathBuf,
+    offline: bool,
+}
+
+impl FetchCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            offline: false,
+        }
+    }
+
+    /// Never makes requests, i.e. only uses previously cached content.
+    pub fn offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    fn cache_file(&self, url: &str) -> PathBuf {
+        let key = sha256_hex(url.as_bytes());
+
+        self.dir.join(format!("{}.json", &key[..16]))
+    }
+
+    fn read(&self, url: &str) -> Option<CachedContent> {
+        let cache_file = self.cache_file(url);
+        let contents = fs::read_to_string(&cache_file).ok()?;
+
+        match serde_json::from_str::<CachedContent>(&contents) {
+            Ok(entry) if entry.url == url => Some(entry),
+            Ok(_) => None,
+            Err(err) => {
+                debug!(
+                    "Ignoring unreadable cached content {}: {}",
+                    cache_file.display(),
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    fn write(&self, entry: &CachedContent) {
+        let cache_file = self.cache_file(&entry.url);
+
+        if let Err(err) = write_json(&cache_file, entry) {
+            warn!(
+                "Failed to write the cached content of {} to {}: {:#}",
+                entry.url,
+                cache_file.display(),
+                err
+            );
+        }
+    }
+}
+
+/// Fetches `url`, going through the cache (when given) and verifying the content against the
+/// `sha256` checksum (when pinned).
+pub(crate) fn fetch(url: &str, sha256: Option<&str>, cache: Option<&FetchCache>) -> Result<String> {
+    let content = match cache {
+        Some(cache) => fetch_cached(url, sha256, cache)?,
+        None => {
+            request(url, None)?
+                .expect("only cached content is revalidated")
+                .content
+        }
+    };
+
+    verify_sha256(url, &content, sha256)?;
+
+    Ok(content)
+}
+
+fn fetch_cached(url: &str, sha256: Option<&str>, cache: &FetchCache) -> Result<String> {
+    let cached = cache.read(url);
+
+    if cache.offline {
+        return match cached {
+            Some(cached) => {
+                debug!("Using cached content for {} (offline)", url);
+                Ok(cached.content)
+            }
+            None => anyhow::bail!(
+                "Failed to fetch URL: {} (offline mode is enabled, and it hasn't been cached yet)",
+                url
+            ),
+        };
+    }
+
+    match request(url, cached.as_ref())? {
+        Some(fetched) => {
+            // only cache content that can be used, otherwise it would be revalidated forever
+            verify_sha256(url, &fetched.content, sha256)?;
+            cache.write(&fetched);
+
+            Ok(fetched.content)
+        }
+        None => {
+            debug!("Cached content for {} is still fresh", url);
+            Ok(cached
+                .expect("`304 Not Modified` is only possible for cached content")
+                .content)
+        }
+    }
+}
+
+/// Requests `url`, revalidating the `cached` content when given. Returns `None` when the server
+/// responded with `304 Not Modified`.
+fn request(url: &str, cached: Option<&CachedContent>) -> Result<Option<CachedContent>> {
+    trace!("Fetching URL: {}", &url);
+
+    let mut request = ureq::get(url);
+    if let Some(cached) = cached {
+        if let Some(etag) = &cached.etag {
+            request = request.header("If-None-Match", etag);
+        }
+        if let Some(last_modified) = &cached.last_modified {
+            request = request.header("If-Modified-Since", last_modified);
+        }
+    }
+
+    let response = request
+        .call()
+        .context(format!("Failed to fetch URL: {}", &url))?;
+
+    if response.status() == 304 && cached.is_some() {
+        return Ok(None);
+    }
+
+    if response.status() == 200 {
+        let header = |name: &str| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(ToString::to_string)
+        };
+        let etag = header("etag");
+        let last_modified = header("last-modified");
+
+        let content = response
+            .into_body()
+            .read_to_string()
+            .context("Failed to read response content")?;
+
+        Ok(Some(CachedContent {
+            url: url.to_string(),
+            etag,
+            last_modified,
+            content,
+        }))
+    } else {
+        let status = response.status();
+        let error_body = response
+            .into_body()
+            .read_to_string()
+            .unwrap_or_else(|_| "Failed to read error response body".to_string());
+        anyhow::bail!(
+            "Failed to fetch URL '{}' (Status Code: {}):\nError Body: {}",
+            &url,
+            status,
+            error_body
+        );
+    }
+}
+
+fn verify_sha256(url: &str, content: &str, expected: Option<&str>) -> Result<()> {
+    let Some(expected) = expected else {
+        return Ok(());
+    };
+
+    let actual = sha256_hex(content.as_bytes());
+    if !actual.eq_ignore_ascii_case(expected) {
+        anyhow::bail!(
+            "Checksum mismatch for {}: expected sha256 {}, but got {}",
+            url,
+            expected,
+            actual
+        );
+    }
+
+    Ok(())
+}
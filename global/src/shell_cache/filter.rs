@@ -0,0 +1,72 @@
+use anyhow::{Context, Result};
+use glob::Pattern;
+use std::path::Path;
+
+/// Decides which files (by their path relative to the source directory) get their directives
+/// processed. The other files are copied as is.
+#[derive(Debug, Clone, Default)]
+pub struct FileFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl FileFilter {
+    /// Files are processed when they match any of the `include` patterns (or when there are none),
+    /// unless they match any of the `exclude` patterns.
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self> {
+        Ok(Self {
+            include: parse_patterns(include)?,
+            exclude: parse_patterns(exclude)?,
+        })
+    }
+
+    pub(crate) fn should_process(&self, relative_path: &Path) -> bool {
+        let included = self.include.is_empty()
+            || self
+                .include
+                .iter()
+                .any(|pattern| pattern.matches_path(relative_path));
+
+        included
+            && !self
+                .exclude
+                .iter()
+                .any(|pattern| pattern.matches_path(relative_path))
+    }
+}
+
+fn parse_patterns(patterns: &[String]) -> Result<Vec<Pattern>> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            Pattern::new(pattern).with_context(|| format!("Invalid glob pattern: {}", pattern))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use insta::assert_snapshot;
+
+    #[test]
+    fn test_should_process() -> Result<()> {
+        let everything = FileFilter::default();
+        assert!(everything.should_process(Path::new("zshrc")));
+        assert!(everything.should_process(Path::new("bin/helper")));
+
+        let filter = FileFilter::new(
+            &["zshrc".to_string(), "plugins/*.zsh".to_string()],
+            &["plugins/skip-*".to_string()],
+        )?;
+        assert!(filter.should_process(Path::new("zshrc")));
+        assert!(filter.should_process(Path::new("plugins/thing.zsh")));
+        assert!(!filter.should_process(Path::new("plugins/skip-me.zsh")));
+        assert!(!filter.should_process(Path::new("bin/helper")));
+
+        let err = FileFilter::new(&["[".to_string()], &[]).unwrap_err();
+        assert_snapshot!(format!("{:#}", err), @"Invalid glob pattern: [: Pattern syntax error near position 0: invalid range pattern");
+
+        Ok(())
+    }
+}
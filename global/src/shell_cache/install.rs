@@ -0,0 +1,283 @@
+use anyhow::{Context, Result};
+use std::ffi::{CString, OsString};
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+
+use super::copy_recursively;
+use super::manifest::Manifest;
+
+/// The sibling of `dest_dir` that holds the previous generation of the output.
+pub fn backup_dir(dest_dir: &Path) -> PathBuf {
+    sibling_dir(dest_dir, "backup")
+}
+
+/// The sibling of `dest_dir` that the next generation is assembled in before it is swapped in.
+fn staging_dir(dest_dir: &Path) -> PathBuf {
+    sibling_dir(dest_dir, "staging")
+}
+
+/// The sibling of `dest_dir` that holds one of the directories while they are exchanged, on
+/// filesystems that can't exchange them atomically.
+fn swap_dir(dest_dir: &Path) -> PathBuf {
+    sibling_dir(dest_dir, "swap")
+}
+
+fn sibling_dir(dest_dir: &Path, suffix: &str) -> PathBuf {
+    let mut name = dest_dir
+        .file_name()
+        .map(OsString::from)
+        .unwrap_or_else(|| OsString::from("dist"));
+    name.push(format!(".{}", suffix));
+
+    dest_dir.with_file_name(name)
+}
+
+/// Whether `path` is (or is within) `dest_dir` or one of the siblings that are managed along with
+/// it, i.e. output rather than a source.
+pub(crate) fn is_output_path(path: &Path, dest_dir: &Path) -> bool {
+    path.starts_with(dest_dir)
+        || path.starts_with(backup_dir(dest_dir))
+        || path.starts_with(staging_dir(dest_dir))
+        || path.starts_with(swap_dir(dest_dir))
+}
+
+/// Installs the generated output into `dest_dir`, keeping the current contents as a backup.
+///
+/// The output is staged in a sibling directory and then exchanged with `dest_dir` in a single
+/// step, so `dest_dir` is never left partially written (or missing). When `merge` is set the staged output starts as a copy of the
+/// current contents of `dest_dir` (so files that weren't generated are kept), except for the files
+/// that were generated by an earlier run but aren't anymore (i.e. their source was removed).
+pub fn install(generated_dir: &Path, dest_dir: &Path, merge: bool) -> Result<()> {
+    let staging_dir = staging_dir(dest_dir);
+    remove_dir_if_exists(&staging_dir)?;
+
+    if merge && dest_dir.exists() {
+        copy_recursively(dest_dir, &staging_dir).with_context(|| {
+            format!(
+                "Failed to copy {} to {}",
+                dest_dir.display(),
+                staging_dir.display()
+            )
+        })?;
+        remove_stale_outputs(generated_dir, &staging_dir)?;
+    }
+    copy_recursively(generated_dir, &staging_dir).with_context(|| {
+        format!(
+            "Failed to copy the generated output to {}",
+            staging_dir.display()
+        )
+    })?;
+
+    if dest_dir.exists() {
+        let backup_dir = backup_dir(dest_dir);
+        remove_dir_if_exists(&backup_dir)?;
+        exchange(&staging_dir, dest_dir)?;
+        // the staging directory now holds the previous generation
+        rename(&staging_dir, &backup_dir)?;
+    } else {
+        rename(&staging_dir, dest_dir)?;
+    }
+
+    info!("Installed the generated output into {}", dest_dir.display());
+
+    Ok(())
+}
+
+/// Restores the previous generation of `dest_dir` from its backup. The replaced generation
+/// becomes the backup, so rolling back again undoes the rollback.
+pub fn rollback(dest_dir: &Path) -> Result<()> {
+    let backup_dir = backup_dir(dest_dir);
+    if !backup_dir.exists() {
+        anyhow::bail!(
+            "There is no backup of {} to roll back to (expected it at {})",
+            dest_dir.display(),
+            backup_dir.display()
+        );
+    }
+
+    if dest_dir.exists() {
+        exchange(&backup_dir, dest_dir)?;
+    } else {
+        rename(&backup_dir, dest_dir)?;
+    }
+
+    info!("Rolled {} back to its backup", dest_dir.display());
+
+    Ok(())
+}
+
+/// Removes the files from `staging_dir` that the previous manifest lists, but the manifest of
+/// `generated_dir` doesn't.
+fn remove_stale_outputs(generated_dir: &Path, staging_dir: &Path) -> Result<()> {
+    let previous_manifest = Manifest::read(staging_dir);
+    let manifest = Manifest::read(generated_dir);
+    let outputs: Vec<&str> = manifest.outputs().collect();
+
+    for output in previous_manifest.outputs() {
+        if outputs.contains(&output) {
+            continue;
+        }
+
+        let stale_file = staging_dir.join(output);
+        if fs::symlink_metadata(&stale_file).is_ok() {
+            info!("Removing {} (its source was removed)", output);
+            fs::remove_file(&stale_file)
+                .with_context(|| format!("Failed to remove file: {}", stale_file.display()))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Exchanges the directories `a` and `b` (which must both exist). This is atomic where the
+/// platform and filesystem support it, otherwise `b` is moved aside while `a` is renamed into its
+/// place, and moved back when that fails.
+fn exchange(a: &Path, b: &Path) -> Result<()> {
+    match exchange_atomically(a, b) {
+        Ok(()) => Ok(()),
+        Err(err) if is_unsupported(&err) => {
+            debug!("Exchanging directories atomically isn't supported: {}", err);
+            exchange_with_renames(a, b)
+        }
+        Err(err) => Err(err)
+            .with_context(|| format!("Failed to exchange {} and {}", a.display(), b.display())),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn exchange_atomically(a: &Path, b: &Path) -> io::Result<()> {
+    let (a, b) = (c_path(a)?, c_path(b)?);
+    // SAFETY: both paths are valid NUL-terminated strings for the duration of the call
+    let result = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            a.as_ptr(),
+            libc::AT_FDCWD,
+            b.as_ptr(),
+            libc::RENAME_EXCHANGE,
+        )
+    };
+
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn exchange_atomically(a: &Path, b: &Path) -> io::Result<()> {
+    let (a, b) = (c_path(a)?, c_path(b)?);
+    // SAFETY: both paths are valid NUL-terminated strings for the duration of the call
+    let result = unsafe { libc::renamex_np(a.as_ptr(), b.as_ptr(), libc::RENAME_SWAP) };
+
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn exchange_atomically(_a: &Path, _b: &Path) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn c_path(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes()).map_err(io::Error::from)
+}
+
+/// Whether exchanging failed because the platform (or filesystem) doesn't support it.
+fn is_unsupported(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::Unsupported
+        || matches!(
+            err.raw_os_error(),
+            Some(libc::EINVAL | libc::ENOSYS | libc::ENOTSUP)
+        )
+}
+
+fn exchange_with_renames(a: &Path, b: &Path) -> Result<()> {
+    let swap_dir = swap_dir(b);
+    remove_dir_if_exists(&swap_dir)?;
+
+    rename(b, &swap_dir)?;
+    if let Err(err) = rename(a, b) {
+        // put `b` back, so it is never left missing
+        rename(&swap_dir, b).context(format!("{:#}", err))?;
+        return Err(err);
+    }
+    rename(&swap_dir, a)
+}
+
+fn rename(from: &Path, to: &Path) -> Result<()> {
+    fs::rename(from, to)
+        .with_context(|| format!("Failed to move {} to {}", from.display(), to.display()))
+}
+
+fn remove_dir_if_exists(dir: &Path) -> Result<()> {
+    if dir.exists() {
+        fs::remove_dir_all(dir)
+            .with_context(|| format!("Failed to remove directory: {}", dir.display()))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use insta::assert_debug_snapshot;
+    use tempfile::tempdir;
+
+    fn write_dirs(base_dir: &Path) -> (PathBuf, PathBuf) {
+        let (a, b) = (base_dir.join("a"), base_dir.join("b"));
+        fs::create_dir(&a).unwrap();
+        fs::create_dir(&b).unwrap();
+        fs::write(a.join("file"), "a").unwrap();
+        fs::write(b.join("file"), "b").unwrap();
+
+        (a, b)
+    }
+
+    #[test]
+    fn test_exchange() {
+        let dir = tempdir().unwrap();
+        let (a, b) = write_dirs(dir.path());
+
+        exchange(&a, &b).unwrap();
+        assert_debug_snapshot!(fixturify::read(dir.path()).unwrap(), @r###"
+        {
+            "a/file": "b",
+            "b/file": "a",
+        }
+        "###);
+
+        exchange_with_renames(&a, &b).unwrap();
+        assert_debug_snapshot!(fixturify::read(dir.path()).unwrap(), @r###"
+        {
+            "a/file": "a",
+            "b/file": "b",
+        }
+        "###);
+    }
+
+    #[test]
+    fn test_exchange_with_renames_restores_the_destination_on_failure() {
+        let dir = tempdir().unwrap();
+        let (_, b) = write_dirs(dir.path());
+
+        let err = exchange_with_renames(&dir.path().join("missing"), &b).unwrap_err();
+
+        assert!(format!("{:#}", err).starts_with("Failed to move"));
+        assert_debug_snapshot!(fixturify::read(dir.path()).unwrap(), @r###"
+        {
+            "a/file": "a",
+            "b/file": "b",
+        }
+        "###);
+    }
+}
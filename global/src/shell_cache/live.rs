@@ -0,0 +1,251 @@
+use anyhow::{Context, Result};
+use std::env;
+use std::path::Path;
+use tracing::info;
+
+use super::{Collector, CommandOptions, LineAction, ProcessOptions, TargetShell};
+
+/// Expands every file within `source_dir` into `dest_dir` like
+/// [`process_directory`](super::process_directory), except that directives are rendered as the
+/// shell code that computes their output whenever the file is sourced (e.g.
+/// `eval "$(brew shellenv)"`) instead of being executed. That is, it renders the startup files
+/// as they would be without caching (e.g. to measure how much caching saves).
+///
+/// `# INCLUDE:`, `# FILE:` and `# IF:` directives are still expanded. Fish files get the fish
+/// equivalents (e.g. `brew shellenv | source`). References to `final_dest_dir` (e.g. sourcing a
+/// sibling by its absolute path) are pointed at `dest_dir`, so that they don't source the cached
+/// files.
+pub fn render_live_directory(
+    source_dir: &Path,
+    dest_dir: &Path,
+    final_dest_dir: &Path,
+    options: &ProcessOptions,
+) -> Result<()> {
+    let mut collector = Collector {
+        source_root: source_dir,
+        final_dest_dir,
+        filter: &options.filter,
+        syntax: &options.syntax,
+        command_defaults: &options.command_defaults,
+        pending_files: Vec::new(),
+        copied_files: Vec::new(),
+    };
+    collector.collect_directory(source_dir, dest_dir, &[])?;
+
+    info!(
+        "Rendering {} file(s) without caching",
+        collector.pending_files.len()
+    );
+
+    let retarget = dest_dir_references(final_dest_dir, dest_dir);
+    for file in &collector.pending_files {
+        let content: Vec<String> = file
+            .lines
+            .iter()
+            .map(|action| {
+                let line =
+                    render_live_directive(action, &options.command_defaults, file.syntax.shell);
+                retarget
+                    .iter()
+                    .fold(line, |line, (from, to)| line.replace(from, to))
+            })
+            .collect();
+
+        file.write_content(&content)
+            .with_context(|| format!("Failed to process file {:?}", file.source_file))?;
+    }
+
+    Ok(())
+}
+
+/// The ways files refer to `final_dest_dir` (by its absolute path, or relative to `~`), each with
+/// the corresponding reference to `dest_dir`.
+fn dest_dir_references(final_dest_dir: &Path, dest_dir: &Path) -> Vec<(String, String)> {
+    if final_dest_dir == dest_dir {
+        return Vec::new();
+    }
+
+    let mut references = vec![(
+        final_dest_dir.display().to_string(),
+        dest_dir.display().to_string(),
+    )];
+    if let Some(relative) = env::var_os("HOME").and_then(|home| {
+        final_dest_dir
+            .strip_prefix(home)
+            .ok()
+            .map(Path::to_path_buf)
+    }) {
+        references.push((
+            format!("~/{}", relative.display()),
+            dest_dir.display().to_string(),
+        ));
+    }
+
+    references
+}
+
+/// Renders the shell code that computes the output of a directive when it is sourced.
+fn render_live_directive(
+    action: &LineAction,
+    command_defaults: &CommandOptions,
+    shell: TargetShell,
+) -> String {
+    let (command, variable) = match action {
+        LineAction::Command {
+            command, options, ..
+        }
+        | LineAction::EvalOnce { command, options } => (
+            live_command(command, &command_defaults.merge(options), shell),
+            None,
+        ),
+        LineAction::Fetch { url, .. } => (format!("curl -fsSL {}", shell.quote(url)), None),
+        LineAction::Env { name, command } => {
+            (live_command(command, command_defaults, shell), Some(name))
+        }
+        LineAction::Include(_) | LineAction::File(_) | LineAction::If(_) | LineAction::EndIf => {
+            unreachable!("expanded while reading the file")
+        }
+        LineAction::Other(line) => return line.clone(),
+    };
+
+    match (shell, variable) {
+        (TargetShell::Fish, Some(name)) => {
+            format!("set -gx {} ({} | string collect)", name, command)
+        }
+        (TargetShell::Fish, None) => format!("{} | source", command),
+        (_, Some(name)) => format!("export {}=\"$({})\"", name, command),
+        (_, None) => format!("eval \"$({})\"", command),
+    }
+}
+
+/// Renders a command (for `shell` to run) that runs `command` the way [`CommandOptions`] describe
+/// (apart from the timeout, and whether it may fail).
+fn live_command(command: &str, options: &CommandOptions, shell: TargetShell) -> String {
+    let mut parts = Vec::new();
+
+    // the directory is changed by the command's own shell, so it never affects the sourcing shell
+    let command = match &options.cwd {
+        Some(cwd) => format!(
+            "cd {} && {}",
+            TargetShell::Sh.quote(&shellexpand::tilde(cwd)),
+            command
+        ),
+        None => command.to_string(),
+    };
+    if !options.env.is_empty() {
+        parts.push("env".to_string());
+        parts.extend(
+            options
+                .env
+                .iter()
+                .map(|(name, value)| shell.quote(&format!("{}={}", name, value))),
+        );
+    }
+    parts.push(options.shell.unwrap_or_default().program().to_string());
+    parts.push("-c".to_string());
+    parts.push(shell.quote(&command));
+
+    parts.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use insta::assert_snapshot;
+    use std::collections::BTreeMap;
+    use std::fs;
+    use std::process::Command;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_render_live_directory() -> Result<()> {
+        let dir = tempdir()?;
+        let source_dir = dir.path().join("zsh");
+        let dest_dir = dir.path().join("live");
+
+        fixturify::write(
+            &source_dir,
+            &BTreeMap::from([
+                (
+                    "zshrc".to_string(),
+                    "# CMD: brew shellenv\n\
+                     # CMD_SILENT[shell=bash, env=A=1, cwd=/tmp]: echo \"it's $A\"\n\
+                     # ENV: EDITOR=command -v nvim\n\
+                     # FETCH: https://example.com/completions.zsh\n\
+                     alias ll='ls -l'\n"
+                        .to_string(),
+                ),
+                (
+                    "config.fish".to_string(),
+                    "# CMD: brew shellenv fish\n\
+                     # CMD[cwd=/tmp]: echo \"it's here\"\n\
+                     # ENV: EDITOR=command -v nvim\n\
+                     # FETCH: https://example.com/completions.fish\n"
+                        .to_string(),
+                ),
+            ]),
+        )?;
+
+        render_live_directory(
+            &source_dir,
+            &dest_dir,
+            &dest_dir,
+            &ProcessOptions::default(),
+        )?;
+
+        assert_snapshot!(fs::read_to_string(dest_dir.join("zshrc"))?, @r###"
+        eval "$(sh -c 'brew shellenv')"
+        eval "$(env 'A=1' bash -c 'cd '\''/tmp'\'' && echo "it'\''s $A"')"
+        export EDITOR="$(sh -c 'command -v nvim')"
+        eval "$(curl -fsSL 'https://example.com/completions.zsh')"
+        alias ll='ls -l'
+        "###);
+        assert_snapshot!(fs::read_to_string(dest_dir.join("config.fish"))?, @r###"
+        sh -c 'brew shellenv fish' | source
+        sh -c 'cd \'/tmp\' && echo "it\'s here"' | source
+        set -gx EDITOR (sh -c 'command -v nvim' | string collect)
+        curl -fsSL 'https://example.com/completions.fish' | source
+        "###);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_live_directory_sources_live_siblings() -> Result<()> {
+        let dir = tempdir()?;
+        let source_dir = dir.path().join("zsh");
+        let final_dest_dir = dir.path().join("dist");
+        let dest_dir = dir.path().join("live");
+
+        fixturify::write(
+            &source_dir,
+            &BTreeMap::from([
+                (
+                    "zshrc".to_string(),
+                    format!(". {}/aliases.zsh\n", final_dest_dir.display()),
+                ),
+                (
+                    "aliases.zsh".to_string(),
+                    "# CMD: echo echo live\n".to_string(),
+                ),
+            ]),
+        )?;
+        // the cached files, which the uncached ones must not source
+        fixturify::write(
+            &final_dest_dir,
+            &BTreeMap::from([("aliases.zsh".to_string(), "echo cached\n".to_string())]),
+        )?;
+
+        render_live_directory(
+            &source_dir,
+            &dest_dir,
+            &final_dest_dir,
+            &ProcessOptions::default(),
+        )?;
+
+        let output = Command::new("sh").arg(dest_dir.join("zshrc")).output()?;
+        assert_snapshot!(String::from_utf8_lossy(&output.stdout), @"live");
+
+        Ok(())
+    }
+}
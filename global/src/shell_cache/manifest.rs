@@ -0,0 +1,98 @@
+use anyhow::{Context, Result};
+use lua_config_utils::cache::write_json;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use tracing::debug;
+
+/// The name of the manifest within the destination directory.
+pub const MANIFEST_FILE: &str = ".shell-cache-manifest.json";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ManifestEntry {
+    /// The hash of the (expanded) source file, including its directives but not their results.
+    pub(crate) source_hash: String,
+    /// The hash of the generated file, i.e. the source file along with the results of its
+    /// directives.
+    pub(crate) result_hash: String,
+}
+
+/// Records how every file within a destination directory was generated, so that the files whose
+/// source hasn't changed can be reused by the next run.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct Manifest {
+    /// Keyed by the path of the generated file, relative to the destination directory.
+    files: BTreeMap<String, ManifestEntry>,
+}
+
+impl Manifest {
+    /// Reads the manifest of `dest_dir`, which is empty when it is missing or unreadable (e.g.
+    /// because the destination was generated by an older version).
+    pub(crate) fn read(dest_dir: &Path) -> Self {
+        let manifest_file = dest_dir.join(MANIFEST_FILE);
+        let Ok(contents) = fs::read_to_string(&manifest_file) else {
+            return Self::default();
+        };
+
+        serde_json::from_str(&contents).unwrap_or_else(|err| {
+            debug!(
+                "Ignoring unreadable manifest {}: {}",
+                manifest_file.display(),
+                err
+            );
+            Self::default()
+        })
+    }
+
+    pub(crate) fn write(&self, dest_dir: &Path) -> Result<()> {
+        write_json(&dest_dir.join(MANIFEST_FILE), self)
+    }
+
+    pub(crate) fn insert(&mut self, output: String, entry: ManifestEntry) {
+        self.files.insert(output, entry);
+    }
+
+    pub(crate) fn outputs(&self) -> impl Iterator<Item = &str> {
+        self.files.keys().map(String::as_str)
+    }
+
+    /// Returns the entry of `output` when it was generated from the same source and the
+    /// generated file within `dest_dir` hasn't been modified since.
+    pub(crate) fn reusable(
+        &self,
+        dest_dir: &Path,
+        output: &str,
+        source_hash: &str,
+    ) -> Option<&ManifestEntry> {
+        let entry = self.files.get(output)?;
+        if entry.source_hash != source_hash {
+            return None;
+        }
+
+        let contents = fs::read(dest_dir.join(output)).ok()?;
+        (sha256_hex(&contents) == entry.result_hash).then_some(entry)
+    }
+}
+
+/// Hashes the generated file at `path`, for [`ManifestEntry::result_hash`]. Symlinks are hashed
+/// by their target.
+pub(crate) fn hash_file(path: &Path) -> Result<String> {
+    let metadata = fs::symlink_metadata(path)
+        .with_context(|| format!("Failed to read metadata: {}", path.display()))?;
+    if metadata.is_symlink() {
+        let target = fs::read_link(path)
+            .with_context(|| format!("Failed to read symlink: {}", path.display()))?;
+        return Ok(sha256_hex(target.as_os_str().as_encoded_bytes()));
+    }
+
+    let contents =
+        fs::read(path).with_context(|| format!("Failed to read file: {}", path.display()))?;
+
+    Ok(sha256_hex(&contents))
+}
+
+pub(crate) fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
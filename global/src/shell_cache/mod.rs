@@ -0,0 +1,2173 @@
+//! Expands the directives (e.g. `# CMD: brew shellenv`) within shell startup files, so that their
+//! output is cached in the generated files instead of being recomputed on every shell startup.
+//!
+//! Files can be meant for sh, bash, zsh or fish (see [`TargetShell`]), and directives are
+//! comments in whatever syntax the file uses (see [`SyntaxOptions`]).
+
+mod command;
+mod directive;
+mod drift;
+mod expand;
+mod fetch;
+mod filter;
+mod install;
+mod live;
+mod manifest;
+mod output_cache;
+mod syntax;
+
+use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::Write;
+use std::num::NonZeroUsize;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, info, trace, warn};
+
+use command::run_command;
+pub use command::{CommandOptions, Shell};
+use directive::{format_duration, parse_directive, parse_duration};
+pub use drift::diff_directories;
+pub use fetch::FetchCache;
+pub use filter::FileFilter;
+pub use install::{backup_dir, install, rollback};
+pub use live::render_live_directory;
+pub use manifest::MANIFEST_FILE;
+use manifest::{Manifest, ManifestEntry};
+pub use output_cache::OutputCache;
+use syntax::FileSyntax;
+pub use syntax::{SyntaxOptions, TargetShell};
+
+/// Options that apply to every file that is processed.
+#[derive(Debug, Clone)]
+pub struct ProcessOptions {
+    /// Where the outputs of commands with a `ttl` (and of `# EVAL_ONCE:` directives) are cached
+    /// between runs. When unset, every command is run.
+    pub output_cache: Option<OutputCache>,
+    /// Where the content of `# FETCH:` directives is cached between runs. When unset, every URL
+    /// is downloaded.
+    pub fetch_cache: Option<FetchCache>,
+    /// How many directives are executed concurrently. Defaults to the available parallelism.
+    pub jobs: usize,
+    /// Reuse the files of the final destination whose source hasn't changed since they were
+    /// generated (according to its manifest), instead of executing their directives again. Only
+    /// files without directives, or whose directives are all commands with a `ttl` (or
+    /// `# EVAL_ONCE:` directives) that are still in [`ProcessOptions::output_cache`], are reused.
+    pub incremental: bool,
+    /// Which files get their directives processed (when processing a directory), the others are
+    /// copied as is. Defaults to every file.
+    pub filter: FileFilter,
+    /// The defaults for running the commands of directives, which `# CMD:` directives can
+    /// override.
+    pub command_defaults: CommandOptions,
+    /// How the syntax (i.e. the comment prefix and target shell) of each file is determined.
+    pub syntax: SyntaxOptions,
+}
+
+impl Default for ProcessOptions {
+    fn default() -> Self {
+        Self {
+            output_cache: None,
+            fetch_cache: None,
+            jobs: thread::available_parallelism()
+                .map(NonZeroUsize::get)
+                .unwrap_or(1),
+            incremental: false,
+            filter: FileFilter::default(),
+            command_defaults: CommandOptions::default(),
+            syntax: SyntaxOptions::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum LineAction {
+    Command {
+        command: String,
+        silent: bool,
+        ttl: Option<Duration>,
+        /// The options that override [`ProcessOptions::command_defaults`].
+        options: CommandOptions,
+    },
+    /// A command whose output is cached until it is refreshed, rather than for a `ttl`.
+    EvalOnce {
+        command: String,
+        /// The options that override [`ProcessOptions::command_defaults`].
+        options: CommandOptions,
+    },
+    Fetch {
+        url: String,
+        sha256: Option<String>,
+    },
+    Env {
+        name: String,
+        command: String,
+    },
+    Include(String),
+    File(String),
+    If(String),
+    EndIf,
+    Other(String),
+}
+
+/// Parses a line of a file whose comments start with `comment` (e.g. `#`).
+fn parse_line(line: &str, comment: &str) -> Result<LineAction> {
+    if line.trim() == format!("{} ENDIF", comment) {
+        return Ok(LineAction::EndIf);
+    }
+
+    let Some(directive) = parse_directive(line, comment)? else {
+        return Ok(LineAction::Other(line.to_string()));
+    };
+
+    if matches!(directive.name, "ENV" | "INCLUDE" | "FILE" | "IF") && !directive.options.is_empty()
+    {
+        anyhow::bail!(
+            "`{} {}:` doesn't support any options",
+            comment,
+            directive.name
+        );
+    }
+
+    match directive.name {
+        "CMD" | "CMD_SILENT" => {
+            let mut ttl = None;
+            let mut options = CommandOptions::default();
+            for (key, value) in directive.options {
+                match key {
+                    "ttl" => ttl = Some(parse_duration(value)?),
+                    _ if CommandOptions::KEYS.contains(&key) => options.set(key, value)?,
+                    _ => anyhow::bail!(
+                        "Unknown option `{}` for `{} {}:` (supported options: `ttl`, `{}`)",
+                        key,
+                        comment,
+                        directive.name,
+                        CommandOptions::KEYS.join("`, `")
+                    ),
+                }
+            }
+
+            Ok(LineAction::Command {
+                command: directive.argument.to_string(),
+                silent: directive.name == "CMD_SILENT",
+                ttl,
+                options,
+            })
+        }
+        "EVAL_ONCE" => {
+            let mut options = CommandOptions::default();
+            for (key, value) in directive.options {
+                if !CommandOptions::KEYS.contains(&key) {
+                    anyhow::bail!(
+                        "Unknown option `{}` for `{} EVAL_ONCE:` (supported options: `{}`)",
+                        key,
+                        comment,
+                        CommandOptions::KEYS.join("`, `")
+                    );
+                }
+                options.set(key, value)?;
+            }
+
+            Ok(LineAction::EvalOnce {
+                command: directive.argument.to_string(),
+                options,
+            })
+        }
+        "FETCH" => {
+            let mut sha256 = None;
+            for (key, value) in directive.options {
+                match key {
+                    "sha256" => {
+                        if value.len() != 64 || !value.chars().all(|ch| ch.is_ascii_hexdigit()) {
+                            anyhow::bail!(
+                                "Invalid sha256 checksum `{}`, expected 64 hexadecimal characters",
+                                value
+                            );
+                        }
+                        sha256 = Some(value.to_ascii_lowercase());
+                    }
+                    _ => anyhow::bail!(
+                        "Unknown option `{}` for `{} FETCH:` (supported options: `sha256`)",
+                        key,
+                        comment
+                    ),
+                }
+            }
+
+            Ok(LineAction::Fetch {
+                url: directive.argument.to_string(),
+                sha256,
+            })
+        }
+        "ENV" => {
+            let (name, command) = directive
+                .argument
+                .split_once('=')
+                .map(|(name, command)| (name.trim(), command.trim()))
+                .filter(|(name, _)| is_env_name(name))
+                .with_context(|| {
+                    format!(
+                        "Invalid `{comment} ENV: {}`, expected `{comment} ENV: NAME=command`",
+                        directive.argument
+                    )
+                })?;
+
+            Ok(LineAction::Env {
+                name: name.to_string(),
+                command: command.to_string(),
+            })
+        }
+        "INCLUDE" => Ok(LineAction::Include(directive.argument.to_string())),
+        "FILE" => Ok(LineAction::File(directive.argument.to_string())),
+        "IF" => Ok(LineAction::If(directive.argument.to_string())),
+        _ => Ok(LineAction::Other(line.to_string())),
+    }
+}
+
+fn is_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    chars
+        .next()
+        .is_some_and(|ch| ch.is_ascii_alphabetic() || ch == '_')
+        && chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+}
+
+/// The result of executing a directive.
+#[derive(Debug)]
+enum DirectiveOutput {
+    Success(String),
+    /// The error of a command that is allowed to fail.
+    Failure(String),
+}
+
+/// Turns the failure of a command that is allowed to fail into a [`DirectiveOutput::Failure`].
+fn tolerate_failure(output: Result<String>, options: &CommandOptions) -> Result<DirectiveOutput> {
+    match output {
+        Ok(output) => Ok(DirectiveOutput::Success(output)),
+        Err(err) if options.allows_failure() => {
+            warn!("Ignoring failure (allow_failure is set): {:#}", err);
+            Ok(DirectiveOutput::Failure(format!("{:#}", err)))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Runs the command (or fetches the URL) of a directive, returning its output.
+///
+/// Only directives that remain after [`expand::expand_file`] are executed.
+fn execute_directive(action: &LineAction, options: &ProcessOptions) -> Result<DirectiveOutput> {
+    match action {
+        LineAction::Command {
+            command,
+            ttl,
+            options: overrides,
+            ..
+        } => {
+            let command_options = options.command_defaults.merge(overrides);
+            let output = match (ttl, &options.output_cache) {
+                (Some(ttl), Some(output_cache)) => {
+                    output_cache.get_or_run(command, *ttl, &command_options, || {
+                        run_command(command, &command_options)
+                    })
+                }
+                _ => run_command(command, &command_options),
+            };
+
+            tolerate_failure(output, &command_options)
+        }
+        LineAction::EvalOnce {
+            command,
+            options: overrides,
+        } => {
+            let command_options = options.command_defaults.merge(overrides);
+            let output = match &options.output_cache {
+                Some(output_cache) => {
+                    output_cache.get_or_run(command, Duration::MAX, &command_options, || {
+                        run_command(command, &command_options)
+                    })
+                }
+                None => run_command(command, &command_options),
+            };
+
+            tolerate_failure(output, &command_options)
+        }
+        LineAction::Fetch { url, sha256 } => {
+            fetch::fetch(url, sha256.as_deref(), options.fetch_cache.as_ref())
+                .map(DirectiveOutput::Success)
+        }
+        LineAction::Env { command, .. } => tolerate_failure(
+            run_command(command, &options.command_defaults),
+            &options.command_defaults,
+        ),
+        LineAction::Include(_)
+        | LineAction::File(_)
+        | LineAction::If(_)
+        | LineAction::EndIf
+        | LineAction::Other(_) => unreachable!("only directives are executed"),
+    }
+}
+
+/// Renders the `# CMD:` (or `# EVAL_ONCE:`) line of a command, along with its options.
+fn command_header(
+    name: &str,
+    command: &str,
+    ttl: Option<Duration>,
+    options: &CommandOptions,
+    comment: &str,
+) -> String {
+    let mut directive_options: Vec<String> = ttl
+        .map(|ttl| format!("ttl={}", format_duration(ttl)))
+        .into_iter()
+        .collect();
+    directive_options.extend(options.to_directive_options());
+
+    if directive_options.is_empty() {
+        format!("{} {}: {}", comment, name, command)
+    } else {
+        format!(
+            "{} {}[{}]: {}",
+            comment,
+            name,
+            directive_options.join(","),
+            command
+        )
+    }
+}
+
+/// Appends the lines that replace a directive (given its output) to `content`.
+fn render_directive(
+    action: &LineAction,
+    output: DirectiveOutput,
+    syntax: &FileSyntax,
+    content: &mut Vec<String>,
+) {
+    let output = match output {
+        DirectiveOutput::Success(output) => output,
+        DirectiveOutput::Failure(error) => {
+            return render_failure(action, &error, &syntax.comment, content);
+        }
+    };
+    let comment = &syntax.comment;
+
+    match action {
+        LineAction::Command {
+            command,
+            silent: true,
+            ..
+        } => {
+            trace!("Rendering silent command: {}", command);
+            content.push(output);
+        }
+        LineAction::Command {
+            command,
+            ttl,
+            options,
+            ..
+        } => {
+            content.push(command_header("CMD", command, *ttl, options, comment));
+            content.push(format!(
+                "{comment} OUTPUT START: {command}\n{output}\n{comment} OUTPUT END: {command}"
+            ));
+        }
+        LineAction::EvalOnce { command, options } => {
+            content.push(command_header("EVAL_ONCE", command, None, options, comment));
+            content.push(format!(
+                "{comment} OUTPUT START: {command}\n{output}\n{comment} OUTPUT END: {command}"
+            ));
+        }
+        LineAction::Fetch { url, sha256 } => {
+            match sha256 {
+                Some(sha256) => {
+                    content.push(format!("{} FETCH[sha256={}]: {}", comment, sha256, url))
+                }
+                None => content.push(format!("{} FETCH: {}", comment, url)),
+            }
+            content.push(format!(
+                "{comment} FETCHED CONTENT START: {url}\n{output}\n{comment} FETCHED CONTENT END: {url}"
+            ));
+        }
+        LineAction::Env { name, command } => {
+            content.push(format!("{} ENV: {}={}", comment, name, command));
+            content.push(syntax.shell.export(name, output.trim_end_matches('\n')));
+        }
+        LineAction::Include(_) | LineAction::File(_) | LineAction::If(_) | LineAction::EndIf => {
+            unreachable!("expanded while reading the file")
+        }
+        LineAction::Other(line) => content.push(line.clone()),
+    }
+}
+
+/// Appends the lines that replace a command that failed (but is allowed to) to `content`: the
+/// directive (unless it is silent), followed by the error as a comment.
+fn render_failure(action: &LineAction, error: &str, comment: &str, content: &mut Vec<String>) {
+    let command = match action {
+        LineAction::Command {
+            command,
+            silent,
+            ttl,
+            options,
+        } => {
+            if !silent {
+                content.push(command_header("CMD", command, *ttl, options, comment));
+            }
+            command
+        }
+        LineAction::EvalOnce { command, options } => {
+            content.push(command_header("EVAL_ONCE", command, None, options, comment));
+            command
+        }
+        LineAction::Env { name, command } => {
+            content.push(format!("{} ENV: {}={}", comment, name, command));
+            command
+        }
+        _ => unreachable!("only commands are allowed to fail"),
+    };
+
+    content.push(format!("{} ERROR START: {}", comment, command));
+    for line in error.trim_end().lines() {
+        content.push(format!("{} {}", comment, line).trim_end().to_string());
+    }
+    content.push(format!("{} ERROR END: {}", comment, command));
+}
+
+/// Runs every directive, at most `options.jobs` at a time, returning their outputs in the same
+/// order as `directives`.
+///
+/// Once a directive fails no new ones are started, so the outputs of directives after the first
+/// failure may be missing.
+fn execute_directives(
+    directives: &[&LineAction],
+    options: &ProcessOptions,
+) -> Vec<Option<Result<DirectiveOutput>>> {
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let jobs = options.jobs.clamp(1, directives.len().max(1));
+
+    let finished: Vec<(usize, Result<DirectiveOutput>)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut finished = Vec::new();
+
+                    while !failed.load(Ordering::Relaxed) {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(directive) = directives.get(index) else {
+                            break;
+                        };
+
+                        let output = execute_directive(directive, options);
+                        if output.is_err() {
+                            failed.store(true, Ordering::Relaxed);
+                        }
+                        finished.push((index, output));
+                    }
+
+                    finished
+                })
+            })
+            .collect();
+
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("Directive worker panicked"))
+            .collect()
+    });
+
+    let mut outputs: Vec<Option<Result<DirectiveOutput>>> =
+        directives.iter().map(|_| None).collect();
+    for (index, output) in finished {
+        outputs[index] = Some(output);
+    }
+
+    outputs
+}
+
+/// A source file whose directives have been parsed, but not executed yet.
+#[derive(Debug)]
+struct PendingFile {
+    source_file: PathBuf,
+    dest_file: PathBuf,
+    /// The directories that were traversed to find the file (outermost first), which are
+    /// mentioned when processing the file fails.
+    parent_dirs: Vec<PathBuf>,
+    syntax: FileSyntax,
+    lines: Vec<LineAction>,
+}
+
+impl PendingFile {
+    fn read(
+        source_file: &Path,
+        dest_file: &Path,
+        parent_dirs: Vec<PathBuf>,
+        syntax: FileSyntax,
+        command_defaults: &CommandOptions,
+    ) -> Result<Self> {
+        debug!("Processing file: {}", source_file.display());
+
+        let mut lines = Vec::new();
+        expand::expand_file(
+            source_file,
+            &syntax.comment,
+            command_defaults,
+            &mut Vec::new(),
+            &mut lines,
+        )?;
+
+        Ok(Self {
+            source_file: source_file.to_path_buf(),
+            dest_file: dest_file.to_path_buf(),
+            parent_dirs,
+            syntax,
+            lines,
+        })
+    }
+
+    fn directives(&self) -> impl Iterator<Item = &LineAction> {
+        self.lines
+            .iter()
+            .filter(|action| !matches!(action, LineAction::Other(_)))
+    }
+
+    /// Hashes the expanded lines, so included files (and the outcome of `# IF:` conditions) are
+    /// taken into account too, along with the syntax they are rendered in.
+    fn source_hash(&self) -> String {
+        manifest::sha256_hex(format!("{:?}{:?}", self.syntax, self.lines).as_bytes())
+    }
+
+    /// Whether the output of any of the directives may have changed since the previous run, so the
+    /// file has to be processed again even when its source hasn't changed. Only the outputs of
+    /// commands with a `ttl` (or of `# EVAL_ONCE:` directives) that are still cached are known to
+    /// be current, anything else (e.g. `# CMD: brew shellenv`, `# FETCH:` or `# ENV:`) has to run
+    /// again.
+    fn has_expiring_directives(&self, options: &ProcessOptions) -> bool {
+        self.directives().any(|action| match action {
+            LineAction::Command {
+                command,
+                ttl: Some(ttl),
+                options: overrides,
+                ..
+            } => !options.output_cache.as_ref().is_some_and(|output_cache| {
+                output_cache.holds(command, *ttl, &options.command_defaults.merge(overrides))
+            }),
+            LineAction::EvalOnce {
+                command,
+                options: overrides,
+            } => !options.output_cache.as_ref().is_some_and(|output_cache| {
+                output_cache.holds(
+                    command,
+                    Duration::MAX,
+                    &options.command_defaults.merge(overrides),
+                )
+            }),
+            _ => true,
+        })
+    }
+
+    /// Writes the file to its destination, taking the outputs of its directives from `outputs`.
+    /// Returns whether all of them succeeded (i.e. none of them failed, but were allowed to).
+    fn write(
+        &self,
+        outputs: &mut impl Iterator<Item = Option<Result<DirectiveOutput>>>,
+    ) -> Result<bool> {
+        let mut new_content = Vec::new();
+        let mut succeeded = true;
+
+        for action in &self.lines {
+            if let LineAction::Other(line) = action {
+                new_content.push(line.clone());
+                continue;
+            }
+
+            let output = outputs
+                .next()
+                .flatten()
+                .context("Directive was skipped after an earlier failure")??;
+            if matches!(output, DirectiveOutput::Failure(_)) {
+                succeeded = false;
+            }
+            render_directive(action, output, &self.syntax, &mut new_content);
+        }
+
+        self.write_content(&new_content)?;
+
+        Ok(succeeded)
+    }
+
+    /// Writes `content` (the rendered lines) to the destination, with the permissions of the
+    /// source.
+    fn write_content(&self, content: &[String]) -> Result<()> {
+        let dest_file = &self.dest_file;
+        if let Some(parent) = dest_file.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directories for path: {:?}", parent))?;
+        }
+
+        debug!("Writing to file: {}", dest_file.display());
+        trace!("New content:\n{}", content.join("\n"));
+
+        let mut output_file = File::create(dest_file)
+            .with_context(|| format!("Failed to open file for writing: {}", dest_file.display()))?;
+
+        for line in content {
+            writeln!(output_file, "{}", line).context("Failed to write line")?;
+        }
+
+        output_file.flush().context("Failed to flush file")?;
+
+        copy_permissions(&self.source_file, dest_file)
+    }
+}
+
+/// Executes the directives of every file concurrently, then writes the files (in order). Returns
+/// whether all the directives of each file succeeded (see [`PendingFile::write`]).
+fn write_pending_files(files: &[PendingFile], options: &ProcessOptions) -> Result<Vec<bool>> {
+    let directives: Vec<&LineAction> = files.iter().flat_map(PendingFile::directives).collect();
+    info!(
+        "Running {} directive(s) from {} file(s)",
+        directives.len(),
+        files.len()
+    );
+
+    let mut outputs = execute_directives(&directives, options).into_iter();
+    let mut succeeded = Vec::new();
+
+    for file in files {
+        let mut result = file
+            .write(&mut outputs)
+            .with_context(|| format!("Failed to process file {:?}", file.source_file));
+        for dir in file.parent_dirs.iter().rev() {
+            result = result.with_context(|| format!("Failed to process directory {:?}", dir));
+        }
+        succeeded.push(result?);
+    }
+
+    Ok(succeeded)
+}
+
+/// Expands a single file into `dest_file`. Files that aren't text are copied as is.
+pub fn process_file<S: AsRef<Path>>(
+    source_file: S,
+    dest_file: S,
+    options: &ProcessOptions,
+) -> Result<()> {
+    if !is_text_file(source_file.as_ref())? {
+        fs::copy(source_file.as_ref(), dest_file.as_ref())?;
+        return Ok(());
+    }
+
+    let source_file = source_file.as_ref();
+    let syntax = options
+        .syntax
+        .for_file(Path::new(source_file.file_name().unwrap_or_default()));
+    let file = PendingFile::read(
+        source_file,
+        dest_file.as_ref(),
+        vec![],
+        syntax,
+        &options.command_defaults,
+    )?;
+
+    let directives: Vec<&LineAction> = file.directives().collect();
+    let mut outputs = execute_directives(&directives, options).into_iter();
+
+    file.write(&mut outputs)?;
+
+    Ok(())
+}
+
+/// Whether directives can be processed within `file`, i.e. it is UTF-8 text (without NUL bytes).
+fn is_text_file(file: &Path) -> Result<bool> {
+    let contents =
+        fs::read(file).with_context(|| format!("Failed to read file: {}", file.display()))?;
+
+    Ok(!contents.contains(&0) && std::str::from_utf8(&contents).is_ok())
+}
+
+/// Walks a source directory, reading the files whose directives will be processed and copying
+/// everything else (i.e. symlinks, files that aren't text and files excluded by the
+/// [`FileFilter`]) into the destination directly.
+struct Collector<'a> {
+    source_root: &'a Path,
+    final_dest_dir: &'a Path,
+    filter: &'a FileFilter,
+    syntax: &'a SyntaxOptions,
+    /// How the conditions of `# IF:` directives are run.
+    command_defaults: &'a CommandOptions,
+    pending_files: Vec<PendingFile>,
+    /// The destination of every file that was copied as is.
+    copied_files: Vec<PathBuf>,
+}
+
+impl Collector<'_> {
+    fn collect_directory(
+        &mut self,
+        source_dir: &Path,
+        dest_dir: &Path,
+        parent_dirs: &[PathBuf],
+    ) -> Result<()> {
+        info!("Scanning directory: {}", source_dir.display());
+
+        let mut entries = fs::read_dir(source_dir)
+            .with_context(|| format!("Failed to read directory: {}", source_dir.display()))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .context("Failed to process directory entry")?;
+        entries.sort();
+
+        for path in entries {
+            if install::is_output_path(&path, self.final_dest_dir) {
+                continue;
+            }
+
+            let relative_path = path
+                .strip_prefix(source_dir)
+                .context("Failed to get relative path")?;
+            let file_type = fs::symlink_metadata(&path)
+                .with_context(|| format!("Failed to read metadata: {}", path.display()))?
+                .file_type();
+
+            if file_type.is_dir() {
+                let new_dest_dir = dest_dir.join(relative_path);
+                let mut nested_parent_dirs = parent_dirs.to_vec();
+                nested_parent_dirs.push(path.clone());
+
+                fs::create_dir_all(&new_dest_dir)
+                    .context("Failed to create destination directory")?;
+                self.collect_directory(&path, &new_dest_dir, &nested_parent_dirs)
+                    .context(format!("Failed to process directory {:?}", path))?;
+            } else if file_type.is_symlink() {
+                let dest_file = dest_dir.join(relative_path);
+                copy_symlink(&path, &dest_file)?;
+                self.copied_files.push(dest_file);
+            } else if !self.should_process(&path)? {
+                debug!("Copying file as is: {}", path.display());
+                let dest_file = dest_dir.join(relative_path);
+                fs::copy(&path, &dest_file).with_context(|| {
+                    format!(
+                        "Failed to copy {} to {}",
+                        path.display(),
+                        dest_file.display()
+                    )
+                })?;
+                self.copied_files.push(dest_file);
+            } else {
+                let dest_file = dest_dir.join(relative_path);
+                let syntax = self.syntax.for_file(self.relative_to_root(&path)?);
+                let file = PendingFile::read(
+                    &path,
+                    &dest_file,
+                    parent_dirs.to_vec(),
+                    syntax,
+                    self.command_defaults,
+                )
+                .context(format!("Failed to process file {:?}", path))?;
+                self.pending_files.push(file);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn should_process(&self, file: &Path) -> Result<bool> {
+        Ok(self.filter.should_process(self.relative_to_root(file)?) && is_text_file(file)?)
+    }
+
+    fn relative_to_root<'p>(&self, file: &'p Path) -> Result<&'p Path> {
+        file.strip_prefix(self.source_root)
+            .context("Failed to get relative path")
+    }
+}
+
+/// Expands every file within `source_dir` into `dest_dir`, skipping `final_dest_dir` (where the
+/// output will eventually live, along with its backup) when it is nested within `source_dir`.
+///
+/// The directives of all files are executed concurrently (see [`ProcessOptions::jobs`]), but the
+/// output is identical to processing them one by one. A manifest of the generated files is
+/// written into `dest_dir`, which lets later runs reuse them (see
+/// [`ProcessOptions::incremental`]).
+///
+/// Generated files get the permissions of their source, while symlinks, files that aren't text and
+/// files that aren't selected by [`ProcessOptions::filter`] are copied as is.
+pub fn process_directory(
+    source_dir: &Path,
+    dest_dir: &Path,
+    final_dest_dir: &Path,
+    options: &ProcessOptions,
+) -> Result<()> {
+    let mut collector = Collector {
+        source_root: source_dir,
+        final_dest_dir,
+        filter: &options.filter,
+        syntax: &options.syntax,
+        command_defaults: &options.command_defaults,
+        pending_files: Vec::new(),
+        copied_files: Vec::new(),
+    };
+    collector.collect_directory(source_dir, dest_dir, &[])?;
+
+    let previous_manifest = if options.incremental {
+        Manifest::read(final_dest_dir)
+    } else {
+        Manifest::default()
+    };
+    let mut manifest = Manifest::default();
+    let mut pending_files = Vec::new();
+    // the output path and source hash of every pending file, to record them in the manifest
+    let mut pending_entries = Vec::new();
+
+    for file in collector.pending_files {
+        let output = relative_output(&file.dest_file, dest_dir)?;
+        let source_hash = file.source_hash();
+
+        let reusable = if file.has_expiring_directives(options) {
+            None
+        } else {
+            previous_manifest.reusable(final_dest_dir, &output, &source_hash)
+        };
+
+        match reusable {
+            Some(entry) => {
+                debug!("Reusing unchanged file: {}", file.source_file.display());
+                fs::copy(final_dest_dir.join(&output), &file.dest_file).with_context(|| {
+                    format!("Failed to copy {} to {}", output, file.dest_file.display())
+                })?;
+                copy_permissions(&file.source_file, &file.dest_file)?;
+                manifest.insert(output, entry.clone());
+            }
+            None => {
+                pending_files.push(file);
+                pending_entries.push((output, source_hash));
+            }
+        }
+    }
+
+    let succeeded = write_pending_files(&pending_files, options)?;
+
+    for ((file, (output, source_hash)), succeeded) in
+        pending_files.iter().zip(pending_entries).zip(succeeded)
+    {
+        let result_hash = manifest::hash_file(&file.dest_file)?;
+        manifest.insert(
+            output,
+            ManifestEntry {
+                // a source hash that never matches, so that failed commands are retried next time
+                source_hash: if succeeded {
+                    source_hash
+                } else {
+                    String::new()
+                },
+                result_hash,
+            },
+        );
+    }
+
+    // copied files are always copied again, so they are only recorded for removing them once
+    // their source is removed
+    for dest_file in collector.copied_files {
+        let hash = manifest::hash_file(&dest_file)?;
+        manifest.insert(
+            relative_output(&dest_file, dest_dir)?,
+            ManifestEntry {
+                source_hash: hash.clone(),
+                result_hash: hash,
+            },
+        );
+    }
+
+    manifest.write(dest_dir)
+}
+
+fn relative_output(dest_file: &Path, dest_dir: &Path) -> Result<String> {
+    Ok(dest_file
+        .strip_prefix(dest_dir)
+        .context("Failed to get relative path")?
+        .to_string_lossy()
+        .to_string())
+}
+
+fn copy_permissions(source_file: &Path, dest_file: &Path) -> Result<()> {
+    let permissions = fs::metadata(source_file)
+        .with_context(|| format!("Failed to read metadata: {}", source_file.display()))?
+        .permissions();
+
+    fs::set_permissions(dest_file, permissions)
+        .with_context(|| format!("Failed to set permissions: {}", dest_file.display()))
+}
+
+/// Creates a symlink at `dest` that points to the same target as the symlink at `src`.
+fn copy_symlink(src: &Path, dest: &Path) -> Result<()> {
+    let target =
+        fs::read_link(src).with_context(|| format!("Failed to read symlink: {}", src.display()))?;
+
+    symlink(&target, dest).with_context(|| {
+        format!(
+            "Failed to create symlink {} -> {}",
+            dest.display(),
+            target.display()
+        )
+    })
+}
+
+/// Copies `src` into `dest` (merging it with any existing contents), preserving permissions and
+/// symlinks.
+pub fn copy_recursively(src: &Path, dest: &Path) -> Result<()> {
+    fs::create_dir_all(dest)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let dest_path = dest.join(entry.file_name());
+        if file_type.is_dir() {
+            copy_recursively(&entry.path(), &dest_path)?;
+            continue;
+        }
+
+        // replace existing symlinks rather than writing through them
+        if fs::symlink_metadata(&dest_path).is_ok_and(|metadata| metadata.is_symlink()) {
+            fs::remove_file(&dest_path)?;
+        }
+        if file_type.is_symlink() {
+            copy_symlink(&entry.path(), &dest_path)?;
+        } else {
+            fs::copy(entry.path(), dest_path)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use insta::{assert_debug_snapshot, assert_snapshot};
+    use std::collections::BTreeMap;
+    use std::fs::{Permissions, write};
+    use std::os::unix::fs::PermissionsExt;
+    use std::thread;
+    use tempfile::tempdir;
+
+    /// Reads every file within `dir`, except for manifests (whose hashes would only add noise to
+    /// the snapshots).
+    fn read_files(dir: &Path) -> BTreeMap<String, String> {
+        let mut files = fixturify::read(dir).unwrap();
+        files.retain(|path, _| !path.ends_with(MANIFEST_FILE));
+        files
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_debug_snapshot!(parse_line("# CMD: echo hello", "#").unwrap(), @r###"
+        Command {
+            command: "echo hello",
+            silent: false,
+            ttl: None,
+            options: CommandOptions {
+                timeout: None,
+                shell: None,
+                cwd: None,
+                env: {},
+                allow_failure: None,
+            },
+        }
+        "###);
+
+        assert_debug_snapshot!(parse_line("   # CMD: echo hello", "#").unwrap(), @r###"
+        Command {
+            command: "echo hello",
+            silent: false,
+            ttl: None,
+            options: CommandOptions {
+                timeout: None,
+                shell: None,
+                cwd: None,
+                env: {},
+                allow_failure: None,
+            },
+        }
+        "###);
+    }
+
+    #[test]
+    fn test_parse_command_with_options() {
+        assert_debug_snapshot!(parse_line("# CMD[timeout=30s, shell=zsh, cwd=~/src, env=A=1, env=B=x=y, allow_failure=true]: echo hello", "#").unwrap(), @r###"
+        Command {
+            command: "echo hello",
+            silent: false,
+            ttl: None,
+            options: CommandOptions {
+                timeout: Some(
+                    30s,
+                ),
+                shell: Some(
+                    Zsh,
+                ),
+                cwd: Some(
+                    "~/src",
+                ),
+                env: {
+                    "A": "1",
+                    "B": "x=y",
+                },
+                allow_failure: Some(
+                    true,
+                ),
+            },
+        }
+        "###);
+
+        assert_snapshot!(parse_line("# CMD[shell=fish]: echo hello", "#").unwrap_err(), @"Unknown shell `fish`, expected one of `sh`, `bash` or `zsh`");
+        assert_snapshot!(parse_line("# CMD[env=A]: echo hello", "#").unwrap_err(), @"Invalid env `A`, expected `env=NAME=value`");
+    }
+
+    #[test]
+    fn test_parse_command_silent() {
+        assert_debug_snapshot!(parse_line("# CMD_SILENT: echo hello", "#").unwrap(), @r###"
+        Command {
+            command: "echo hello",
+            silent: true,
+            ttl: None,
+            options: CommandOptions {
+                timeout: None,
+                shell: None,
+                cwd: None,
+                env: {},
+                allow_failure: None,
+            },
+        }
+        "###);
+
+        assert_debug_snapshot!(parse_line("     # CMD_SILENT: echo hello", "#").unwrap(), @r###"
+        Command {
+            command: "echo hello",
+            silent: true,
+            ttl: None,
+            options: CommandOptions {
+                timeout: None,
+                shell: None,
+                cwd: None,
+                env: {},
+                allow_failure: None,
+            },
+        }
+        "###);
+    }
+
+    #[test]
+    fn test_parse_command_with_ttl() {
+        assert_debug_snapshot!(parse_line("# CMD[ttl=7d]: brew shellenv", "#").unwrap(), @r###"
+        Command {
+            command: "brew shellenv",
+            silent: false,
+            ttl: Some(
+                604800s,
+            ),
+            options: CommandOptions {
+                timeout: None,
+                shell: None,
+                cwd: None,
+                env: {},
+                allow_failure: None,
+            },
+        }
+        "###);
+
+        assert_snapshot!(parse_line("# CMD[retries=2]: brew shellenv", "#").unwrap_err(), @r###"
+        Unknown option `retries` for `# CMD:` (supported options: `ttl`, `timeout`, `shell`, `cwd`, `env`, `allow_failure`)
+        "###);
+    }
+
+    #[test]
+    fn test_parse_eval_once() {
+        assert_debug_snapshot!(parse_line("# EVAL_ONCE[shell=zsh]: brew shellenv", "#").unwrap(), @r###"
+        EvalOnce {
+            command: "brew shellenv",
+            options: CommandOptions {
+                timeout: None,
+                shell: Some(
+                    Zsh,
+                ),
+                cwd: None,
+                env: {},
+                allow_failure: None,
+            },
+        }
+        "###);
+
+        assert_snapshot!(parse_line("# EVAL_ONCE[ttl=7d]: brew shellenv", "#").unwrap_err(), @r###"
+        Unknown option `ttl` for `# EVAL_ONCE:` (supported options: `timeout`, `shell`, `cwd`, `env`, `allow_failure`)
+        "###);
+    }
+
+    #[test]
+    fn test_parse_fetch() {
+        assert_debug_snapshot!(parse_line("# FETCH: http://example.com", "#").unwrap(), @r###"
+        Fetch {
+            url: "http://example.com",
+            sha256: None,
+        }
+        "###);
+
+        assert_debug_snapshot!(parse_line("    # FETCH: http://example.com", "#").unwrap(), @r###"
+        Fetch {
+            url: "http://example.com",
+            sha256: None,
+        }
+        "###);
+    }
+
+    #[test]
+    fn test_parse_expansion_directives() {
+        let actions: Vec<LineAction> = [
+            "# ENV: HOMEBREW_PREFIX = brew --prefix",
+            "# INCLUDE: ../shared/aliases.zsh",
+            "# FILE: ~/.config/snippet.zsh",
+            "# IF: command -v brew",
+            "  # ENDIF  ",
+        ]
+        .iter()
+        .map(|line| parse_line(line, "#").unwrap())
+        .collect();
+
+        assert_debug_snapshot!(actions, @r###"
+        [
+            Env {
+                name: "HOMEBREW_PREFIX",
+                command: "brew --prefix",
+            },
+            Include(
+                "../shared/aliases.zsh",
+            ),
+            File(
+                "~/.config/snippet.zsh",
+            ),
+            If(
+                "command -v brew",
+            ),
+            EndIf,
+        ]
+        "###);
+
+        assert_snapshot!(parse_line("# ENV: 1PASSWORD=op whoami", "#").unwrap_err(), @"Invalid `# ENV: 1PASSWORD=op whoami`, expected `# ENV: NAME=command`");
+        assert_snapshot!(parse_line("# INCLUDE[once=true]: aliases.zsh", "#").unwrap_err(), @"`# INCLUDE:` doesn't support any options");
+    }
+
+    #[test]
+    fn test_parse_other() {
+        assert_debug_snapshot!(parse_line("This is a regular line", "#").unwrap(), @r###"
+        Other(
+            "This is a regular line",
+        )
+        "###);
+
+        assert_debug_snapshot!(parse_line("      This is a regular line", "#").unwrap(), @r###"
+        Other(
+            "      This is a regular line",
+        )
+        "###);
+    }
+
+    #[test]
+    fn test_process_file_with_valid_command() -> Result<()> {
+        let dir = tempdir()?;
+        let source_file = dir.path().join("test.zsh");
+        let dest_file = dir.path().join("output.zsh");
+
+        let content = "# CMD: echo 'hello world'\n";
+        write(&source_file, content)?;
+
+        process_file(&source_file, &dest_file, &ProcessOptions::default())?;
+
+        let source_contents = fs::read_to_string(&source_file)?;
+
+        assert_snapshot!(source_contents, @"# CMD: echo 'hello world'");
+
+        let processed_content = fs::read_to_string(&dest_file)?;
+        assert_snapshot!(processed_content, @r###"
+        # CMD: echo 'hello world'
+        # OUTPUT START: echo 'hello world'
+        hello world
+
+        # OUTPUT END: echo 'hello world'
+        "###);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_with_silent_command() -> Result<()> {
+        let dir = tempdir()?;
+        let source_file = dir.path().join("test.zsh");
+        let dest_file = dir.path().join("output.zsh");
+
+        let content = "# CMD_SILENT: echo 'hello world'\n";
+        write(&source_file, content)?;
+
+        process_file(&source_file, &dest_file, &ProcessOptions::default())?;
+
+        let source_contents = fs::read_to_string(&source_file)?;
+
+        assert_snapshot!(source_contents, @"# CMD_SILENT: echo 'hello world'");
+
+        let processed_content = fs::read_to_string(&dest_file)?;
+        assert_snapshot!(processed_content, @"hello world");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_with_existing_output() -> Result<()> {
+        let dir = tempdir()?;
+        let source_file = dir.path().join("test.zsh");
+        let dest_file = dir.path().join("output.zsh");
+
+        write(&source_file, "# CMD: echo 'hello world'\n")?;
+        write(
+            &dest_file,
+            "# CMD: echo 'hello world'\n# OUTPUT START: echo 'hello world'\nold output\n# OUTPUT END: echo 'hello world'\n",
+        )?;
+
+        process_file(&source_file, &dest_file, &ProcessOptions::default())?;
+
+        let source_contents = fs::read_to_string(&source_file)?;
+
+        assert_snapshot!(source_contents, @"# CMD: echo 'hello world'");
+
+        let processed_content = fs::read_to_string(&dest_file)?;
+        assert_snapshot!(processed_content, @r###"
+        # CMD: echo 'hello world'
+        # OUTPUT START: echo 'hello world'
+        hello world
+
+        # OUTPUT END: echo 'hello world'
+        "###);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_with_invalid_command() -> Result<()> {
+        let dir = tempdir()?;
+        let source_file = dir.path().join("test.zsh");
+        let dest_file = dir.path().join("output.zsh");
+
+        let content = "# CMD: invalidcommand\n";
+        write(&source_file, content)?;
+
+        // Process the file (should not panic, just print error)
+        let result = process_file(&source_file, &dest_file, &ProcessOptions::default());
+
+        let err = result.unwrap_err();
+
+        let error_alternate_output = format!("{:#}", err);
+
+        // the alternate output is not stable between local and CI, so we can't use snapshots here
+        assert!(
+            error_alternate_output.contains("Failed to run command (`invalidcommand`)"),
+            "Error output: {}",
+            error_alternate_output
+        );
+        assert!(
+            error_alternate_output.contains("not found"),
+            "Error output: {}",
+            error_alternate_output
+        );
+
+        Ok(())
+    }
+
+    fn process_counting_command(dir: &Path, directive: &str, options: &ProcessOptions) -> String {
+        let source_file = dir.join("test.zsh");
+        let dest_file = dir.join("output.zsh");
+        let counter_file = dir.join("count");
+
+        write(
+            &source_file,
+            format!(
+                "# {}: echo run >> {} && wc -l < {} | tr -d ' '\n",
+                directive,
+                counter_file.display(),
+                counter_file.display()
+            ),
+        )
+        .unwrap();
+
+        process_file(&source_file, &dest_file, options).unwrap();
+
+        fs::read_to_string(&dest_file)
+            .unwrap()
+            .replace(&dir.display().to_string(), "{dir}")
+    }
+
+    #[test]
+    fn test_process_file_reuses_outputs_within_ttl() -> Result<()> {
+        let dir = tempdir()?;
+        let options = ProcessOptions {
+            output_cache: Some(OutputCache::new(dir.path().join("cache"))),
+            ..Default::default()
+        };
+
+        process_counting_command(dir.path(), "CMD[ttl=7d]", &options);
+        let processed_content = process_counting_command(dir.path(), "CMD[ttl=7d]", &options);
+
+        assert_snapshot!(processed_content, @r###"
+        # CMD[ttl=7d]: echo run >> {dir}/count && wc -l < {dir}/count | tr -d ' '
+        # OUTPUT START: echo run >> {dir}/count && wc -l < {dir}/count | tr -d ' '
+        1
+
+        # OUTPUT END: echo run >> {dir}/count && wc -l < {dir}/count | tr -d ' '
+        "###);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_with_refresh_reruns_commands() -> Result<()> {
+        let dir = tempdir()?;
+        let output_cache = OutputCache::new(dir.path().join("cache"));
+
+        process_counting_command(
+            dir.path(),
+            "CMD_SILENT[ttl=1h]",
+            &ProcessOptions {
+                output_cache: Some(output_cache.clone()),
+                ..Default::default()
+            },
+        );
+        let refreshed = process_counting_command(
+            dir.path(),
+            "CMD_SILENT[ttl=1h]",
+            &ProcessOptions {
+                output_cache: Some(output_cache.clone().refresh(true)),
+                ..Default::default()
+            },
+        );
+        let reused = process_counting_command(
+            dir.path(),
+            "CMD_SILENT[ttl=1h]",
+            &ProcessOptions {
+                output_cache: Some(output_cache),
+                ..Default::default()
+            },
+        );
+
+        assert_debug_snapshot!((refreshed, reused), @r###"
+        (
+            "2\n\n",
+            "2\n\n",
+        )
+        "###);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_reruns_expired_and_uncached_commands() -> Result<()> {
+        let dir = tempdir()?;
+        let options = ProcessOptions {
+            output_cache: Some(OutputCache::new(dir.path().join("cache"))),
+            ..Default::default()
+        };
+
+        process_counting_command(dir.path(), "CMD_SILENT[ttl=0s]", &options);
+        let expired = process_counting_command(dir.path(), "CMD_SILENT[ttl=0s]", &options);
+        let without_ttl = process_counting_command(dir.path(), "CMD_SILENT", &options);
+
+        assert_debug_snapshot!((expired, without_ttl), @r###"
+        (
+            "2\n\n",
+            "3\n\n",
+        )
+        "###);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_evaluates_eval_once_commands_until_refreshed() -> Result<()> {
+        let dir = tempdir()?;
+        let output_cache = OutputCache::new(dir.path().join("cache"));
+        let options = ProcessOptions {
+            output_cache: Some(output_cache.clone()),
+            ..Default::default()
+        };
+
+        process_counting_command(dir.path(), "EVAL_ONCE", &options);
+        let reused = process_counting_command(dir.path(), "EVAL_ONCE", &options);
+        let refreshed = process_counting_command(
+            dir.path(),
+            "EVAL_ONCE",
+            &ProcessOptions {
+                output_cache: Some(output_cache.refresh(true)),
+                ..Default::default()
+            },
+        );
+
+        assert_snapshot!(format!("{reused}---\n{refreshed}"), @r###"
+        # EVAL_ONCE: echo run >> {dir}/count && wc -l < {dir}/count | tr -d ' '
+        # OUTPUT START: echo run >> {dir}/count && wc -l < {dir}/count | tr -d ' '
+        1
+
+        # OUTPUT END: echo run >> {dir}/count && wc -l < {dir}/count | tr -d ' '
+        ---
+        # EVAL_ONCE: echo run >> {dir}/count && wc -l < {dir}/count | tr -d ' '
+        # OUTPUT START: echo run >> {dir}/count && wc -l < {dir}/count | tr -d ' '
+        2
+
+        # OUTPUT END: echo run >> {dir}/count && wc -l < {dir}/count | tr -d ' '
+        "###);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_directory() {
+        let temp_dir = tempdir().unwrap();
+        let base_dir = temp_dir.path();
+
+        let source_files: BTreeMap<String, String> = BTreeMap::from([
+            (
+                "zsh/zshrc".to_string(),
+                "# CMD: echo 'hello world'\n".to_string(),
+            ),
+            (
+                "zsh/plugins/thing.zsh".to_string(),
+                "# CMD: echo 'goodbye world'\n".to_string(),
+            ),
+        ]);
+
+        fixturify::write(base_dir, &source_files).unwrap();
+
+        let source_dir = base_dir.join("zsh");
+        let dest_dir = base_dir.join("zsh/dist");
+
+        process_directory(
+            &source_dir,
+            &dest_dir,
+            &dest_dir,
+            &ProcessOptions::default(),
+        )
+        .unwrap();
+
+        let file_map = read_files(base_dir);
+
+        assert_debug_snapshot!(file_map, @r###"
+        {
+            "zsh/dist/plugins/thing.zsh": "# CMD: echo 'goodbye world'\n# OUTPUT START: echo 'goodbye world'\ngoodbye world\n\n# OUTPUT END: echo 'goodbye world'\n",
+            "zsh/dist/zshrc": "# CMD: echo 'hello world'\n# OUTPUT START: echo 'hello world'\nhello world\n\n# OUTPUT END: echo 'hello world'\n",
+            "zsh/plugins/thing.zsh": "# CMD: echo 'goodbye world'\n",
+            "zsh/zshrc": "# CMD: echo 'hello world'\n",
+        }
+        "###)
+    }
+
+    #[test]
+    fn test_process_directory_preserves_permissions_symlinks_and_binary_files() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let source_dir = temp_dir.path().join("zsh");
+        let dest_dir = temp_dir.path().join("dist");
+
+        fixturify::write(
+            &source_dir,
+            &BTreeMap::from([
+                (
+                    "zshrc".to_string(),
+                    "# CMD: echo 'hello world'\n".to_string(),
+                ),
+                (
+                    "bin/helper".to_string(),
+                    "#!/bin/sh\n# CMD: echo 'excluded'\n".to_string(),
+                ),
+                (
+                    "plugins/thing.zsh".to_string(),
+                    "# CMD: echo 'goodbye world'\n".to_string(),
+                ),
+            ]),
+        )?;
+        fs::set_permissions(source_dir.join("zshrc"), Permissions::from_mode(0o750))?;
+        fs::set_permissions(source_dir.join("bin/helper"), Permissions::from_mode(0o755))?;
+        fs::write(
+            source_dir.join("plugins/data.bin"),
+            b"\xff\xfe\0# CMD: echo",
+        )?;
+        symlink("thing.zsh", source_dir.join("plugins/link.zsh"))?;
+
+        let options = ProcessOptions {
+            filter: FileFilter::new(&[], &["bin/*".to_string()])?,
+            ..Default::default()
+        };
+        process_directory(&source_dir, &dest_dir, &dest_dir, &options)?;
+
+        let mode = |path: &str| -> Result<u32> {
+            Ok(fs::metadata(dest_dir.join(path))?.permissions().mode() & 0o777)
+        };
+        assert_eq!(mode("zshrc")?, 0o750);
+        assert_eq!(mode("bin/helper")?, 0o755);
+        assert_eq!(
+            fs::read(dest_dir.join("plugins/data.bin"))?,
+            b"\xff\xfe\0# CMD: echo"
+        );
+        assert_eq!(
+            fs::read_link(dest_dir.join("plugins/link.zsh"))?,
+            Path::new("thing.zsh")
+        );
+
+        let contents = ["zshrc", "bin/helper", "plugins/link.zsh"]
+            .iter()
+            .map(|path| {
+                Ok(format!(
+                    "{}:\n{}",
+                    path,
+                    fs::read_to_string(dest_dir.join(path))?
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        assert_snapshot!(contents.join("---\n"), @r###"
+        zshrc:
+        # CMD: echo 'hello world'
+        # OUTPUT START: echo 'hello world'
+        hello world
+
+        # OUTPUT END: echo 'hello world'
+        ---
+        bin/helper:
+        #!/bin/sh
+        # CMD: echo 'excluded'
+        ---
+        plugins/link.zsh:
+        # CMD: echo 'goodbye world'
+        # OUTPUT START: echo 'goodbye world'
+        goodbye world
+
+        # OUTPUT END: echo 'goodbye world'
+        "###);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_directory_in_parallel_matches_sequential_output() {
+        let temp_dir = tempdir().unwrap();
+        let base_dir = temp_dir.path();
+
+        // later directives finish first, so their outputs arrive out of order
+        let zshrc = (0..6)
+            .map(|index| format!("# CMD: sleep 0.0{}; echo 'zshrc {}'\n", 6 - index, index))
+            .collect::<String>();
+        let source_files: BTreeMap<String, String> = BTreeMap::from([
+            (
+                "zsh/zshrc".to_string(),
+                format!("# leading line\n{}", zshrc),
+            ),
+            (
+                "zsh/plugins/thing.zsh".to_string(),
+                "# CMD_SILENT: sleep 0.03; echo 'silent'\nexport THING=1\n# CMD: echo 'fast'\n"
+                    .to_string(),
+            ),
+        ]);
+        fixturify::write(base_dir, &source_files).unwrap();
+
+        let source_dir = base_dir.join("zsh");
+        let process_with_jobs = |jobs: usize| {
+            let dest_dir = base_dir.join(format!("dist-{}", jobs));
+            let options = ProcessOptions {
+                jobs,
+                ..Default::default()
+            };
+            process_directory(&source_dir, &dest_dir, &dest_dir, &options).unwrap();
+
+            read_files(&dest_dir)
+        };
+
+        let sequential = process_with_jobs(1);
+        let parallel = process_with_jobs(8);
+
+        assert_eq!(sequential, parallel);
+        assert_debug_snapshot!(parallel, @r###"
+        {
+            "plugins/thing.zsh": "silent\n\nexport THING=1\n# CMD: echo 'fast'\n# OUTPUT START: echo 'fast'\nfast\n\n# OUTPUT END: echo 'fast'\n",
+            "zshrc": "# leading line\n# CMD: sleep 0.06; echo 'zshrc 0'\n# OUTPUT START: sleep 0.06; echo 'zshrc 0'\nzshrc 0\n\n# OUTPUT END: sleep 0.06; echo 'zshrc 0'\n# CMD: sleep 0.05; echo 'zshrc 1'\n# OUTPUT START: sleep 0.05; echo 'zshrc 1'\nzshrc 1\n\n# OUTPUT END: sleep 0.05; echo 'zshrc 1'\n# CMD: sleep 0.04; echo 'zshrc 2'\n# OUTPUT START: sleep 0.04; echo 'zshrc 2'\nzshrc 2\n\n# OUTPUT END: sleep 0.04; echo 'zshrc 2'\n# CMD: sleep 0.03; echo 'zshrc 3'\n# OUTPUT START: sleep 0.03; echo 'zshrc 3'\nzshrc 3\n\n# OUTPUT END: sleep 0.03; echo 'zshrc 3'\n# CMD: sleep 0.02; echo 'zshrc 4'\n# OUTPUT START: sleep 0.02; echo 'zshrc 4'\nzshrc 4\n\n# OUTPUT END: sleep 0.02; echo 'zshrc 4'\n# CMD: sleep 0.01; echo 'zshrc 5'\n# OUTPUT START: sleep 0.01; echo 'zshrc 5'\nzshrc 5\n\n# OUTPUT END: sleep 0.01; echo 'zshrc 5'\n",
+        }
+        "###);
+    }
+
+    #[test]
+    fn test_process_file_with_fetch() -> Result<()> {
+        let mut server = mockito::Server::new();
+
+        let server_url = server.url();
+
+        let mock = server
+            .mock("GET", "/test")
+            .with_status(200)
+            .with_header("content-type", "text/plain")
+            .with_body("# some content returned here!!")
+            .create();
+
+        let dir = tempdir()?;
+        let source_file = dir.path().join("test.zsh");
+        let dest_file = dir.path().join("output.zsh");
+
+        let content = format!("# FETCH: {}/test", server_url);
+        write(&source_file, content)?;
+
+        process_file(&source_file, &dest_file, &ProcessOptions::default())?;
+
+        mock.assert();
+
+        let replace_server_addr = |content: &str, server_url: &str| -> String {
+            content.replace(server_url, "{server_url}")
+        };
+
+        let source_contents = fs::read_to_string(&source_file)?;
+        assert_snapshot!(replace_server_addr(&source_contents, &server_url), @"# FETCH: {server_url}/test");
+
+        let processed_content = fs::read_to_string(&dest_file)?;
+        assert_snapshot!(replace_server_addr(&processed_content, &server_url), @r###"
+        # FETCH: {server_url}/test
+        # FETCHED CONTENT START: {server_url}/test
+        # some content returned here!!
+        # FETCHED CONTENT END: {server_url}/test
+        "###);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_with_invalid_fetch() -> Result<()> {
+        let mut server = mockito::Server::new();
+
+        let server_url = server.url();
+
+        let mock = server
+            .mock("GET", "/test")
+            .with_status(500)
+            .with_header("content-type", "text/plain")
+            .with_body("ZOMG ERROR")
+            .create();
+
+        let dir = tempdir()?;
+        let source_file = dir.path().join("test.zsh");
+        let dest_file = dir.path().join("output.zsh");
+
+        let content = format!("# FETCH: {}/test", server_url);
+        write(&source_file, content)?;
+
+        let replace_server_addr = |content: String, server_url: &str| -> String {
+            content.replace(server_url, "{server_url}")
+        };
+
+        let result = process_file(&source_file, &dest_file, &ProcessOptions::default());
+
+        let err = result.unwrap_err();
+        assert_snapshot!(replace_server_addr(format!("{:#}", err), &server_url), @"Failed to fetch URL: {server_url}/test: http status: 500");
+
+        mock.assert();
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_fetch_with_sha256() {
+        assert_debug_snapshot!(parse_line("# FETCH[sha256=B94D27B9934D3E08A52E52D7DA7DABFAC484EFE37A5380EE9088F7ACE2EFCDE9]: http://example.com", "#").unwrap(), @r###"
+        Fetch {
+            url: "http://example.com",
+            sha256: Some(
+                "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9",
+            ),
+        }
+        "###);
+
+        assert_snapshot!(parse_line("# FETCH[sha256=abc123]: http://example.com", "#").unwrap_err(), @"Invalid sha256 checksum `abc123`, expected 64 hexadecimal characters");
+    }
+
+    fn process_fetch(
+        server_url: &str,
+        directive: &str,
+        options: &ProcessOptions,
+    ) -> Result<String> {
+        let dir = tempdir()?;
+        let source_file = dir.path().join("test.zsh");
+        let dest_file = dir.path().join("output.zsh");
+
+        write(
+            &source_file,
+            format!("# {}: {}/test\n", directive, server_url),
+        )?;
+        process_file(&source_file, &dest_file, options)?;
+
+        Ok(fs::read_to_string(&dest_file)?.replace(server_url, "{server_url}"))
+    }
+
+    #[test]
+    fn test_process_file_with_pinned_fetch() -> Result<()> {
+        let mut server = mockito::Server::new();
+        let server_url = server.url();
+
+        let mock = server
+            .mock("GET", "/test")
+            .with_status(200)
+            .with_body("# some content returned here!!")
+            .expect(2)
+            .create();
+
+        let processed_content = process_fetch(
+            &server_url,
+            "FETCH[sha256=55e09df173e45a24641874bd857b70dc1010ee93924e02432e3ba9dee8cc64fc]",
+            &ProcessOptions::default(),
+        )?;
+        assert_snapshot!(processed_content, @r###"
+        # FETCH[sha256=55e09df173e45a24641874bd857b70dc1010ee93924e02432e3ba9dee8cc64fc]: {server_url}/test
+        # FETCHED CONTENT START: {server_url}/test
+        # some content returned here!!
+        # FETCHED CONTENT END: {server_url}/test
+        "###);
+
+        let err = process_fetch(
+            &server_url,
+            "FETCH[sha256=0000000000000000000000000000000000000000000000000000000000000000]",
+            &ProcessOptions::default(),
+        )
+        .unwrap_err();
+        assert_snapshot!(format!("{:#}", err).replace(&server_url, "{server_url}"), @"Checksum mismatch for {server_url}/test: expected sha256 0000000000000000000000000000000000000000000000000000000000000000, but got 55e09df173e45a24641874bd857b70dc1010ee93924e02432e3ba9dee8cc64fc");
+
+        mock.assert();
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_revalidates_cached_fetch() -> Result<()> {
+        let mut server = mockito::Server::new();
+        let server_url = server.url();
+        let cache_dir = tempdir()?;
+        let options = ProcessOptions {
+            fetch_cache: Some(FetchCache::new(cache_dir.path())),
+            ..Default::default()
+        };
+
+        let initial = server
+            .mock("GET", "/test")
+            .match_header("if-none-match", mockito::Matcher::Missing)
+            .with_status(200)
+            .with_header("etag", "\"v1\"")
+            .with_header("last-modified", "Wed, 21 Oct 2015 07:28:00 GMT")
+            .with_body("# first version")
+            .expect(1)
+            .create();
+        let revalidated = server
+            .mock("GET", "/test")
+            .match_header("if-none-match", "\"v1\"")
+            .match_header("if-modified-since", "Wed, 21 Oct 2015 07:28:00 GMT")
+            .with_status(304)
+            .expect(1)
+            .create();
+
+        let first = process_fetch(&server_url, "FETCH", &options)?;
+        let second = process_fetch(&server_url, "FETCH", &options)?;
+
+        initial.assert();
+        revalidated.assert();
+        assert_eq!(first, second);
+        assert_snapshot!(second, @r###"
+        # FETCH: {server_url}/test
+        # FETCHED CONTENT START: {server_url}/test
+        # first version
+        # FETCHED CONTENT END: {server_url}/test
+        "###);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_file_with_offline_fetch() -> Result<()> {
+        let mut server = mockito::Server::new();
+        let server_url = server.url();
+        let cache_dir = tempdir()?;
+        let fetch_cache = FetchCache::new(cache_dir.path());
+
+        let mock = server
+            .mock("GET", "/test")
+            .with_status(200)
+            .with_body("# cached content")
+            .expect(1)
+            .create();
+
+        process_fetch(
+            &server_url,
+            "FETCH",
+            &ProcessOptions {
+                fetch_cache: Some(fetch_cache.clone()),
+                ..Default::default()
+            },
+        )?;
+
+        let offline_options = ProcessOptions {
+            fetch_cache: Some(fetch_cache.offline(true)),
+            ..Default::default()
+        };
+        let offline = process_fetch(&server_url, "FETCH", &offline_options)?;
+        assert_snapshot!(offline, @r###"
+        # FETCH: {server_url}/test
+        # FETCHED CONTENT START: {server_url}/test
+        # cached content
+        # FETCHED CONTENT END: {server_url}/test
+        "###);
+
+        mock.assert();
+
+        let err = process_fetch("http://127.0.0.1:1", "FETCH", &offline_options).unwrap_err();
+        assert_snapshot!(format!("{:#}", err), @"Failed to fetch URL: http://127.0.0.1:1/test (offline mode is enabled, and it hasn't been cached yet)");
+
+        Ok(())
+    }
+
+    /// Processes `zsh/zshrc` (along with the other given files) and returns its output (or error),
+    /// with the temporary directory replaced by `{dir}`.
+    fn process_zshrc(files: &[(&str, &str)]) -> std::result::Result<String, String> {
+        let dir = tempdir().unwrap();
+        let base_dir = dir.path().canonicalize().unwrap();
+        let stabilize = |content: String| content.replace(&base_dir.display().to_string(), "{dir}");
+
+        let source_files: BTreeMap<String, String> = files
+            .iter()
+            .map(|(path, contents)| (path.to_string(), contents.to_string()))
+            .collect();
+        fixturify::write(&base_dir, &source_files).unwrap();
+
+        let dest_file = base_dir.join("zshrc.out");
+        match process_file(
+            &base_dir.join("zsh/zshrc"),
+            &dest_file,
+            &ProcessOptions::default(),
+        ) {
+            Ok(()) => Ok(stabilize(fs::read_to_string(&dest_file).unwrap())),
+            Err(err) => Err(stabilize(format!("{:#}", err))),
+        }
+    }
+
+    /// Processes `zshrc` with the given command defaults, replacing the temporary directory (which
+    /// is available as `$DIR` within `zshrc`) with `{dir}`.
+    fn process_with_command_defaults(
+        zshrc: &str,
+        command_defaults: CommandOptions,
+    ) -> std::result::Result<String, String> {
+        let dir = tempdir().unwrap();
+        let base_dir = dir.path().canonicalize().unwrap();
+        let stabilize = |content: String| content.replace(&base_dir.display().to_string(), "{dir}");
+
+        fs::create_dir(base_dir.join("nested")).unwrap();
+        let source_file = base_dir.join("zshrc");
+        write(
+            &source_file,
+            zshrc.replace("$DIR", &base_dir.display().to_string()),
+        )
+        .unwrap();
+
+        let dest_file = base_dir.join("zshrc.out");
+        let options = ProcessOptions {
+            command_defaults,
+            ..Default::default()
+        };
+        match process_file(&source_file, &dest_file, &options) {
+            Ok(()) => Ok(stabilize(fs::read_to_string(&dest_file).unwrap())),
+            Err(err) => Err(stabilize(format!("{:#}", err))),
+        }
+    }
+
+    #[test]
+    fn test_process_file_with_command_options() {
+        let mut command_defaults = CommandOptions::default();
+        command_defaults.set("env", "NAME=default").unwrap();
+        command_defaults.set("env", "GREETING=hello").unwrap();
+
+        let processed_content = process_with_command_defaults(
+            "# CMD: echo \"$GREETING $NAME\"\n\
+             # CMD[env=NAME=override, cwd=$DIR/nested]: echo \"$GREETING $NAME from $PWD\"\n\
+             # CMD[shell=bash]: echo \"bash: ${BASH_VERSION:+yes}\"\n",
+            command_defaults,
+        )
+        .unwrap();
+
+        assert_snapshot!(processed_content, @r###"
+        # CMD: echo "$GREETING $NAME"
+        # OUTPUT START: echo "$GREETING $NAME"
+        hello default
+
+        # OUTPUT END: echo "$GREETING $NAME"
+        # CMD[cwd={dir}/nested,env=NAME=override]: echo "$GREETING $NAME from $PWD"
+        # OUTPUT START: echo "$GREETING $NAME from $PWD"
+        hello override from {dir}/nested
+
+        # OUTPUT END: echo "$GREETING $NAME from $PWD"
+        # CMD[shell=bash]: echo "bash: ${BASH_VERSION:+yes}"
+        # OUTPUT START: echo "bash: ${BASH_VERSION:+yes}"
+        bash: yes
+
+        # OUTPUT END: echo "bash: ${BASH_VERSION:+yes}"
+        "###);
+    }
+
+    #[test]
+    fn test_process_file_with_timeouts_and_allowed_failures() {
+        let processed_content = process_with_command_defaults(
+            "# CMD[timeout=1s, allow_failure=true]: sleep 5\n\
+             # CMD[allow_failure=true]: echo 'oops' >&2; exit 3\n\
+             # CMD_SILENT: exit 1\n\
+             # CMD: echo 'still runs'\n",
+            CommandOptions {
+                allow_failure: Some(true),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_snapshot!(processed_content, @r###"
+        # CMD[timeout=1s,allow_failure=true]: sleep 5
+        # ERROR START: sleep 5
+        # Failed to execute command (`sleep 5`): Timed out after 1s
+        # ERROR END: sleep 5
+        # CMD[allow_failure=true]: echo 'oops' >&2; exit 3
+        # ERROR START: echo 'oops' >&2; exit 3
+        # Failed to run command (`echo 'oops' >&2; exit 3`):
+        # oops
+        # ERROR END: echo 'oops' >&2; exit 3
+        # ERROR START: exit 1
+        # Failed to run command (`exit 1`):
+        # ERROR END: exit 1
+        # CMD: echo 'still runs'
+        # OUTPUT START: echo 'still runs'
+        still runs
+
+        # OUTPUT END: echo 'still runs'
+        "###);
+
+        let err = process_with_command_defaults(
+            "# CMD: sleep 5\n",
+            CommandOptions {
+                timeout: Some(Duration::from_secs(1)),
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+
+        assert_snapshot!(err, @"Failed to execute command (`sleep 5`): Timed out after 1s");
+    }
+
+    #[test]
+    fn test_process_file_with_timeout_kills_background_processes() {
+        let marker_dir = tempdir().unwrap();
+        let marker = marker_dir.path().join("late");
+
+        // the background process keeps stdout open after the command itself exited
+        let err = process_with_command_defaults(
+            &format!(
+                "# CMD[timeout=1s]: (sleep 2; touch {}) & echo started\n",
+                marker.display()
+            ),
+            CommandOptions::default(),
+        )
+        .unwrap_err()
+        .replace(&marker.display().to_string(), "{marker}");
+
+        assert_snapshot!(err, @"Failed to execute command (`(sleep 2; touch {marker}) & echo started`): Timed out after 1s");
+
+        thread::sleep(Duration::from_secs(2));
+        assert!(!marker.exists(), "the background process was not killed");
+    }
+
+    #[test]
+    fn test_process_file_with_quoted_option_values() {
+        let processed_content = process_with_command_defaults(
+            "# CMD[env=\"LIST=a,b]\"]: echo \"$LIST\"\n",
+            CommandOptions::default(),
+        )
+        .unwrap();
+
+        assert_snapshot!(processed_content, @r###"
+        # CMD[env="LIST=a,b]"]: echo "$LIST"
+        # OUTPUT START: echo "$LIST"
+        a,b]
+
+        # OUTPUT END: echo "$LIST"
+        "###);
+    }
+
+    #[test]
+    fn test_process_file_with_includes() {
+        let processed_content = process_zshrc(&[
+            (
+                "zsh/zshrc",
+                "# zshrc\n# INCLUDE: plugins/aliases.zsh\n# FILE: ../snippet.txt\n",
+            ),
+            (
+                "zsh/plugins/aliases.zsh",
+                "alias ll='ls -l'\n# INCLUDE: ../shared.zsh\n",
+            ),
+            ("zsh/shared.zsh", "# CMD: echo 'from shared'\n"),
+            ("snippet.txt", "# CMD: echo 'not executed'\n"),
+        ])
+        .unwrap();
+
+        assert_snapshot!(processed_content, @r###"
+        # zshrc
+        # INCLUDE: plugins/aliases.zsh
+        # INCLUDE START: plugins/aliases.zsh
+        alias ll='ls -l'
+        # INCLUDE: ../shared.zsh
+        # INCLUDE START: ../shared.zsh
+        # CMD: echo 'from shared'
+        # OUTPUT START: echo 'from shared'
+        from shared
+
+        # OUTPUT END: echo 'from shared'
+        # INCLUDE END: ../shared.zsh
+        # INCLUDE END: plugins/aliases.zsh
+        # FILE: ../snippet.txt
+        # FILE CONTENT START: ../snippet.txt
+        # CMD: echo 'not executed'
+
+        # FILE CONTENT END: ../snippet.txt
+        "###);
+    }
+
+    #[test]
+    fn test_process_file_with_include_cycle() {
+        let err = process_zshrc(&[
+            ("zsh/zshrc", "# INCLUDE: a.zsh\n"),
+            ("zsh/a.zsh", "# INCLUDE: b.zsh\n"),
+            ("zsh/b.zsh", "# INCLUDE: a.zsh\n"),
+        ])
+        .unwrap_err();
+
+        assert_snapshot!(err, @r###"
+        Failed to include {dir}/zsh/a.zsh: Failed to include {dir}/zsh/b.zsh: Failed to include {dir}/zsh/a.zsh: Include cycle detected: {dir}/zsh/zshrc -> {dir}/zsh/a.zsh -> {dir}/zsh/b.zsh -> {dir}/zsh/a.zsh
+        "###);
+    }
+
+    #[test]
+    fn test_process_file_with_env() {
+        let processed_content = process_zshrc(&[(
+            "zsh/zshrc",
+            "# ENV: GREETING=echo \"it's here\"\n# ENV: EMPTY=true\n",
+        )])
+        .unwrap();
+
+        assert_snapshot!(processed_content, @r###"
+        # ENV: GREETING=echo "it's here"
+        export GREETING='it'\''s here'
+        # ENV: EMPTY=true
+        export EMPTY=''
+        "###);
+    }
+
+    #[test]
+    fn test_process_file_with_conditionals() {
+        let processed_content = process_zshrc(&[(
+            "zsh/zshrc",
+            "# IF: true\n# CMD: echo 'included'\n# IF: test -d /nonexistent\n# CMD: exit 1\nexport SKIPPED=1\n# ENDIF\n# ENDIF\n# IF: false\n# INCLUDE: missing.zsh\n# ENDIF\nexport AFTER=1\n",
+        )]).unwrap();
+
+        assert_snapshot!(processed_content, @r###"
+        # IF: true
+        # CMD: echo 'included'
+        # OUTPUT START: echo 'included'
+        included
+
+        # OUTPUT END: echo 'included'
+        # IF: test -d /nonexistent
+        # ENDIF
+        # ENDIF
+        # IF: false
+        # ENDIF
+        export AFTER=1
+        "###);
+
+        let err = process_zshrc(&[("zsh/zshrc", "# IF: true\n# ENDIF\n# ENDIF\n")]).unwrap_err();
+        assert_snapshot!(err, @"`# ENDIF` without a matching `# IF:` (line 3 of {dir}/zsh/zshrc)");
+
+        let err = process_zshrc(&[("zsh/zshrc", "# IF: true\n")]).unwrap_err();
+        assert_snapshot!(err, @"1 `# IF:` block(s) without a matching `# ENDIF` in {dir}/zsh/zshrc");
+    }
+
+    #[test]
+    fn test_process_file_with_conditionals_uses_command_defaults() {
+        let mut command_defaults = CommandOptions::default();
+        command_defaults.set("env", "FEATURE=on").unwrap();
+
+        let processed_content = process_with_command_defaults(
+            "# IF: test \"$FEATURE\" = on\nexport FEATURE=1\n# ENDIF\n",
+            command_defaults,
+        )
+        .unwrap();
+
+        assert_snapshot!(processed_content, @r###"
+        # IF: test "$FEATURE" = on
+        export FEATURE=1
+        # ENDIF
+        "###);
+
+        let err = process_with_command_defaults(
+            "# IF: sleep 5\n# ENDIF\n",
+            CommandOptions {
+                timeout: Some(Duration::from_secs(1)),
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+
+        assert_snapshot!(err, @"Failed to evaluate condition (`sleep 5`): Failed to execute command (`sleep 5`): Timed out after 1s");
+    }
+
+    #[test]
+    fn test_process_directory_with_bash_fish_and_other_comment_prefixes() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let source_dir = temp_dir.path().join("shells");
+        let dest_dir = temp_dir.path().join("dist");
+
+        fixturify::write(
+            &source_dir,
+            &BTreeMap::from([
+                (
+                    "bashrc".to_string(),
+                    "# ENV: GREETING=echo \"it's bash\"\n".to_string(),
+                ),
+                (
+                    "config.fish".to_string(),
+                    "# ENV: GREETING=echo \"it's fish\"\n# CMD: echo 'set -gx FROM_CMD 1'\n"
+                        .to_string(),
+                ),
+                (
+                    "aliases".to_string(),
+                    "# ENV: GREETING=echo 'default'\n".to_string(),
+                ),
+                (
+                    "init.lua".to_string(),
+                    "-- CMD: echo 'vim.g.loaded = 1'\n# CMD: ignored\n".to_string(),
+                ),
+            ]),
+        )?;
+
+        let options = ProcessOptions {
+            syntax: SyntaxOptions::new(
+                TargetShell::Fish,
+                &BTreeMap::from([("*.lua".to_string(), "--".to_string())]),
+            )?,
+            ..Default::default()
+        };
+        process_directory(&source_dir, &dest_dir, &dest_dir, &options)?;
+
+        assert_debug_snapshot!(read_files(&dest_dir), @r###"
+        {
+            "aliases": "# ENV: GREETING=echo 'default'\nset -gx GREETING 'default'\n",
+            "bashrc": "# ENV: GREETING=echo \"it's bash\"\nexport GREETING='it'\\''s bash'\n",
+            "config.fish": "# ENV: GREETING=echo \"it's fish\"\nset -gx GREETING 'it\\'s fish'\n# CMD: echo 'set -gx FROM_CMD 1'\n# OUTPUT START: echo 'set -gx FROM_CMD 1'\nset -gx FROM_CMD 1\n\n# OUTPUT END: echo 'set -gx FROM_CMD 1'\n",
+            "init.lua": "-- CMD: echo 'vim.g.loaded = 1'\n-- OUTPUT START: echo 'vim.g.loaded = 1'\nvim.g.loaded = 1\n\n-- OUTPUT END: echo 'vim.g.loaded = 1'\n# CMD: ignored\n",
+        }
+        "###);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+use anyhow::{Context, Result};
+use lua_config_utils::cache::write_json;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, trace, warn};
+
+use super::CommandOptions;
+
+/// Environment variables that affect the output of most commands.
+const ALWAYS_RELEVANT_ENV: &[&str] = &["HOME", "PATH", "SHELL"];
+
+static ENV_REFERENCE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\$\{?([A-Za-z_][A-Za-z0-9_]*)").unwrap());
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedOutput {
+    command: String,
+    created_at: u64,
+    output: String,
+}
+
+/// A persistent cache of the outputs of `# CMD[ttl=...]:` and `# EVAL_ONCE:` directives, keyed by the command, the
+/// working directory, the environment variables that the command depends on and the options it
+/// is run with (e.g. its shell).
+#[derive(Debug, Clone)]
+pub struct OutputCache {
+    dir: PathBuf,
+    refresh: bool,
+}
+
+impl OutputCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            refresh: false,
+        }
+    }
+
+    /// Ignores (and replaces) every cached output, i.e. always runs the commands.
+    pub fn refresh(mut self, refresh: bool) -> Self {
+        self.refresh = refresh;
+        self
+    }
+
+    /// Returns the cached output of `command` when it is younger than `ttl`, otherwise runs it and
+    /// caches its output.
+    pub(crate) fn get_or_run(
+        &self,
+        command: &str,
+        ttl: Duration,
+        options: &CommandOptions,
+        run: impl FnOnce() -> Result<String>,
+    ) -> Result<String> {
+        let cache_file = self.cache_file(command, options)?;
+
+        if !self.refresh {
+            if let Some(output) = read_cached_output(&cache_file, ttl) {
+                debug!("Using cached output for `{}`", command);
+                return Ok(output);
+            }
+        }
+
+        let output = run()?;
+
+        let entry = CachedOutput {
+            command: command.to_string(),
+            created_at: now(),
+            output,
+        };
+        if let Err(err) = write_json(&cache_file, &entry) {
+            warn!(
+                "Failed to write the cached output of `{}` to {}: {:#}",
+                command,
+                cache_file.display(),
+                err
+            );
+        }
+
+        Ok(entry.output)
+    }
+
+    /// Whether [`OutputCache::get_or_run`] would return a cached output, without running the
+    /// command.
+    pub(crate) fn holds(&self, command: &str, ttl: Duration, options: &CommandOptions) -> bool {
+        !self.refresh
+            && self
+                .cache_file(command, options)
+                .is_ok_and(|cache_file| read_cached_output(&cache_file, ttl).is_some())
+    }
+
+    fn cache_file(&self, command: &str, options: &CommandOptions) -> Result<PathBuf> {
+        let cwd = env::current_dir().context("Failed to get the current directory")?;
+
+        let mut hasher = Sha256::new();
+        hasher.update(command.as_bytes());
+        hasher.update([0]);
+        hasher.update(cwd.to_string_lossy().as_bytes());
+        for name in relevant_env(command) {
+            hasher.update([0]);
+            hasher.update(name.as_bytes());
+            hasher.update(b"=");
+            hasher.update(env::var(&name).unwrap_or_default().as_bytes());
+        }
+        for part in options.cache_key() {
+            hasher.update([0]);
+            hasher.update(part.as_bytes());
+        }
+        let key = format!("{:x}", hasher.finalize());
+
+        Ok(self.dir.join(format!("{}.json", &key[..16])))
+    }
+}
+
+/// The environment variables that the output of `command` depends on, i.e. the ones it references
+/// (e.g. `$HOMEBREW_PREFIX`) along with [`ALWAYS_RELEVANT_ENV`].
+fn relevant_env(command: &str) -> Vec<String> {
+    let mut names: Vec<String> = ALWAYS_RELEVANT_ENV
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+
+    for captures in ENV_REFERENCE.captures_iter(command) {
+        let name = captures[1].to_string();
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+
+    names
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+fn read_cached_output(cache_file: &Path, ttl: Duration) -> Option<String> {
+    let contents = fs::read_to_string(cache_file).ok()?;
+
+    let entry: CachedOutput = match serde_json::from_str(&contents) {
+        Ok(entry) => entry,
+        Err(err) => {
+            debug!(
+                "Ignoring unreadable cached output {}: {}",
+                cache_file.display(),
+                err
+            );
+            return None;
+        }
+    };
+
+    if now().saturating_sub(entry.created_at) >= ttl.as_secs() {
+        trace!("Cached output has expired: {}", cache_file.display());
+        return None;
+    }
+
+    Some(entry.output)
+}
@@ -0,0 +1,202 @@
+use anyhow::{Context, Result};
+use glob::Pattern;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The shells that source the processed files, which determines the syntax of the lines that are
+/// generated for them (e.g. `export` vs `set -gx` for `# ENV:` directives).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TargetShell {
+    Sh,
+    Bash,
+    #[default]
+    Zsh,
+    Fish,
+}
+
+impl TargetShell {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "sh" => Ok(Self::Sh),
+            "bash" => Ok(Self::Bash),
+            "zsh" => Ok(Self::Zsh),
+            "fish" => Ok(Self::Fish),
+            _ => anyhow::bail!(
+                "Unknown target shell `{}`, expected one of `sh`, `bash`, `zsh` or `fish`",
+                value
+            ),
+        }
+    }
+
+    /// The shell that a file is meant for according to its name (e.g. `config.fish`, `bashrc` or
+    /// `aliases.zsh`), if any.
+    fn detect(file: &Path) -> Option<Self> {
+        let file_name = file.file_name()?.to_str()?.trim_start_matches('.');
+
+        match file.extension().and_then(|extension| extension.to_str()) {
+            Some("sh") => Some(Self::Sh),
+            Some("bash") => Some(Self::Bash),
+            Some("zsh") => Some(Self::Zsh),
+            Some("fish") => Some(Self::Fish),
+            _ => match file_name {
+                "profile" => Some(Self::Sh),
+                "bashrc" | "bash_profile" | "bash_login" | "bash_logout" => Some(Self::Bash),
+                "zshrc" | "zshenv" | "zprofile" | "zlogin" | "zlogout" => Some(Self::Zsh),
+                _ => None,
+            },
+        }
+    }
+
+    /// Quotes `value` as a single word.
+    pub(crate) fn quote(self, value: &str) -> String {
+        match self {
+            Self::Sh | Self::Bash | Self::Zsh => format!("'{}'", value.replace('\'', r"'\''")),
+            // within single quotes, fish only treats `\\` and `\'` specially
+            Self::Fish => format!("'{}'", value.replace('\\', r"\\").replace('\'', r"\'")),
+        }
+    }
+
+    /// Renders the line that exports an environment variable.
+    pub(crate) fn export(self, name: &str, value: &str) -> String {
+        match self {
+            Self::Sh | Self::Bash | Self::Zsh => format!("export {}={}", name, self.quote(value)),
+            Self::Fish => format!("set -gx {} {}", name, self.quote(value)),
+        }
+    }
+}
+
+/// The syntax of a file whose directives are processed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FileSyntax {
+    pub(crate) shell: TargetShell,
+    /// The prefix of the comments that directives (and the markers around their output) are
+    /// written in, e.g. `#` for `# CMD: ...`.
+    pub(crate) comment: String,
+}
+
+/// Decides the syntax of each processed file (by its path relative to the source directory).
+#[derive(Debug, Clone, Default)]
+pub struct SyntaxOptions {
+    /// The shell of files whose name doesn't identify one (see [`TargetShell::detect`]).
+    default_shell: TargetShell,
+    comment_prefixes: Vec<(Pattern, String)>,
+}
+
+impl SyntaxOptions {
+    /// Files use `#` comments, unless they match any of the glob patterns in `comment_prefixes`
+    /// (e.g. `*.lua` → `--`).
+    pub fn new(
+        default_shell: TargetShell,
+        comment_prefixes: &BTreeMap<String, String>,
+    ) -> Result<Self> {
+        let comment_prefixes = comment_prefixes
+            .iter()
+            .map(|(pattern, prefix)| {
+                if prefix.trim().is_empty() {
+                    anyhow::bail!("Empty comment prefix for `{}`", pattern);
+                }
+                let pattern = Pattern::new(pattern)
+                    .with_context(|| format!("Invalid glob pattern: {}", pattern))?;
+
+                Ok((pattern, prefix.trim().to_string()))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            default_shell,
+            comment_prefixes,
+        })
+    }
+
+    /// The syntax options that a `shell_caching` entry of the config sets.
+    pub fn from_config(shell_caching: &config::ShellCache) -> Result<Self> {
+        let default_shell = match &shell_caching.target_shell {
+            Some(target_shell) => TargetShell::parse(target_shell)
+                .context("Invalid `shell_caching.target_shell` in the config")?,
+            None => TargetShell::default(),
+        };
+
+        Self::new(
+            default_shell,
+            &shell_caching.comment_prefixes.clone().unwrap_or_default(),
+        )
+        .context("Invalid `shell_caching.comment_prefixes` in the config")
+    }
+
+    pub(crate) fn for_file(&self, relative_path: &Path) -> FileSyntax {
+        let comment = self
+            .comment_prefixes
+            .iter()
+            .find(|(pattern, _)| pattern.matches_path(relative_path))
+            .map_or_else(|| "#".to_string(), |(_, prefix)| prefix.clone());
+
+        FileSyntax {
+            shell: TargetShell::detect(relative_path).unwrap_or(self.default_shell),
+            comment,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use insta::assert_snapshot;
+
+    #[test]
+    fn test_for_file() -> Result<()> {
+        let options = SyntaxOptions::new(
+            TargetShell::Bash,
+            &BTreeMap::from([
+                ("*.lua".to_string(), "--".to_string()),
+                ("vim/*".to_string(), "\"".to_string()),
+            ]),
+        )?;
+
+        let describe = |path: &str| {
+            let syntax = options.for_file(Path::new(path));
+            format!("{}: {:?} {}", path, syntax.shell, syntax.comment)
+        };
+        let described: Vec<String> = [
+            "zshrc",
+            ".bashrc",
+            "conf.d/abbreviations.fish",
+            "plugins/thing.zsh",
+            "profile",
+            "aliases",
+            "init.lua",
+            "vim/settings",
+        ]
+        .into_iter()
+        .map(describe)
+        .collect();
+
+        assert_snapshot!(described.join("\n"), @r###"
+        zshrc: Zsh #
+        .bashrc: Bash #
+        conf.d/abbreviations.fish: Fish #
+        plugins/thing.zsh: Zsh #
+        profile: Sh #
+        aliases: Bash #
+        init.lua: Bash --
+        vim/settings: Bash "
+        "###);
+
+        let err = SyntaxOptions::new(
+            TargetShell::Zsh,
+            &BTreeMap::from([("*.lua".to_string(), " ".to_string())]),
+        )
+        .unwrap_err();
+        assert_snapshot!(err, @"Empty comment prefix for `*.lua`");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_export() {
+        let value = "it's a \\path";
+
+        assert_snapshot!(TargetShell::Zsh.export("NAME", value), @r"export NAME='it'\''s a \path'");
+        assert_snapshot!(TargetShell::Fish.export("NAME", value), @r"set -gx NAME 'it\'s a \\path'");
+        assert_snapshot!(TargetShell::parse("nu").unwrap_err(), @"Unknown target shell `nu`, expected one of `sh`, `bash`, `zsh` or `fish`");
+    }
+}
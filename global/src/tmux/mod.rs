@@ -144,9 +144,9 @@ fn determine_commands_for_window(
     }
 
     if let Some(command) = &window.command {
-        match command {
-            config::Command::Single(cmd) => commands.push(cmd.clone()),
-            config::Command::Multiple(cmds) => commands.extend(cmds.clone()),
+        match command.resolve()? {
+            config::Command::Single(cmd) => commands.push(cmd),
+            config::Command::Multiple(cmds) => commands.extend(cmds),
         };
     }
 
@@ -651,6 +651,7 @@ mod tests {
         let config = Config {
             crate_locations: None,
             shell_caching: None,
+            local_dotfiles: None,
             tmux: Some(Tmux {
                 default_session: None,
                 sessions: vec![Session {
@@ -724,6 +725,7 @@ mod tests {
         let config = Config {
             crate_locations: None,
             shell_caching: None,
+            local_dotfiles: None,
             tmux: Some(Tmux {
                 default_session: None,
                 sessions: vec![Session {
@@ -779,6 +781,7 @@ mod tests {
         let config = Config {
             crate_locations: None,
             shell_caching: None,
+            local_dotfiles: None,
             tmux: Some(Tmux {
                 default_session: None,
                 sessions: vec![Session {
@@ -842,6 +845,7 @@ mod tests {
         let config = Config {
             crate_locations: None,
             shell_caching: None,
+            local_dotfiles: None,
             tmux: Some(Tmux {
                 default_session: None,
                 sessions: vec![Session {
@@ -896,6 +900,7 @@ mod tests {
         let config = Config {
             crate_locations: None,
             shell_caching: None,
+            local_dotfiles: None,
             tmux: Some(Tmux {
                 default_session: None,
                 sessions: vec![Session {
@@ -904,10 +909,13 @@ mod tests {
                         linked_crates: None,
                         name: "bar".to_string(),
                         path: None,
-                        command: Some(ConfigCommand::Single(format!(
-                            "echo \"$FOO-$BAZ\" > {}",
-                            temp_path_str
-                        ))),
+                        command: Some(
+                            ConfigCommand::Single(format!(
+                                "echo \"$FOO-$BAZ\" > {}",
+                                temp_path_str
+                            ))
+                            .into(),
+                        ),
                         env: Some(BTreeMap::from([
                             ("FOO".to_string(), "bar".to_string()),
                             ("BAZ".to_string(), "qux".to_string()),
@@ -963,6 +971,7 @@ mod tests {
         let config = Config {
             crate_locations: None,
             shell_caching: None,
+            local_dotfiles: None,
             tmux: Some(Tmux {
                 default_session: None,
                 sessions: vec![Session {
@@ -970,7 +979,9 @@ mod tests {
                     windows: vec![Window {
                         name: "bar".to_string(),
                         path: None,
-                        command: Some(ConfigCommand::Single(format!("touch {}", temp_path_str))),
+                        command: Some(
+                            ConfigCommand::Single(format!("touch {}", temp_path_str)).into(),
+                        ),
                         env: None,
                         linked_crates: None,
                     }],
@@ -1020,6 +1031,7 @@ mod tests {
         let config = Config {
             crate_locations: None,
             shell_caching: None,
+            local_dotfiles: None,
             tmux: Some(Tmux {
                 default_session: Some("foo".to_string()),
                 sessions: vec![Session {
@@ -1058,6 +1070,7 @@ mod tests {
         let config = Config {
             crate_locations: None,
             shell_caching: None,
+            local_dotfiles: None,
             tmux: Some(Tmux {
                 default_session: None,
                 sessions: vec![Session {
@@ -1091,7 +1104,7 @@ mod tests {
             path: None,
             env: None,
             name: "test_window".to_string(),
-            command: Some(config::Command::Single("echo Hello".to_string())),
+            command: Some(config::Command::Single("echo Hello".to_string()).into()),
             linked_crates: None,
         };
         let crates = BTreeMap::new();
@@ -1111,7 +1124,7 @@ mod tests {
             path: None,
             env: None,
             name: "test_window".to_string(),
-            command: Some(config::Command::Single("echo Hello".to_string())),
+            command: Some(config::Command::Single("echo Hello".to_string()).into()),
             linked_crates: Some(vec!["crate1".to_string()]),
         };
         let mut crates = BTreeMap::new();
@@ -1133,10 +1146,10 @@ mod tests {
             path: None,
             env: None,
             name: "test_window".to_string(),
-            command: Some(config::Command::Multiple(vec![
-                "echo Hello".to_string(),
-                "echo World".to_string(),
-            ])),
+            command: Some(
+                config::Command::Multiple(vec!["echo Hello".to_string(), "echo World".to_string()])
+                    .into(),
+            ),
             linked_crates: None,
         };
         let crates = BTreeMap::new();
@@ -1238,6 +1251,7 @@ mod tests {
         let config = Config {
             crate_locations: Some(vec![workspace_dir.to_string_lossy().to_string()]),
             shell_caching: None,
+            local_dotfiles: None,
             tmux: Some(Tmux {
                 default_session: None,
                 sessions: vec![Session {
@@ -1245,7 +1259,7 @@ mod tests {
                     windows: vec![Window {
                         name: "bar".to_string(),
                         path: Some(working_dir.to_path_buf()),
-                        command: Some(ConfigCommand::Single("bar".to_string())),
+                        command: Some(ConfigCommand::Single("bar".to_string()).into()),
                         env: None,
                         linked_crates: Some(vec!["foo".to_string()]),
                     }],
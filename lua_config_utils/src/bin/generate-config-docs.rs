@@ -0,0 +1,28 @@
+use clap::Parser;
+use lua_config_utils::lua_type_gen::{GenerateArgs, OutputFormat, generate};
+
+use anyhow::Result;
+use tracing_subscriber::EnvFilter;
+
+/// Generate a Markdown reference for a given file's desearializable structs
+#[derive(Parser, Debug)]
+#[command()]
+struct Args {
+    #[command(flatten)]
+    generate: GenerateArgs,
+}
+
+fn main() -> Result<()> {
+    // Initialize tracing, use `info` by default
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
+
+    latest_bin::ensure_latest_bin()?;
+
+    let args = Args::parse();
+
+    generate(&args.generate, None, OutputFormat::Markdown)
+}
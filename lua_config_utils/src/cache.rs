@@ -0,0 +1,393 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fmt::Debug;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::UNIX_EPOCH;
+use tracing::{debug, trace, warn};
+
+use anyhow::{Context, Result};
+
+use crate::{ConfigDependencies, LuaConfigLoader};
+
+/// Distinguishes the temporary files of concurrent writes within this process.
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry<T> {
+    dependencies: ConfigDependencies,
+    config: T,
+}
+
+/// Reads the Lua config at `config_path`, reusing a previously evaluated copy from `cache_dir`
+/// when none of the files it loaded (or the environment variables it read) have changed.
+///
+/// `env_vars` lists additional environment variables that affect deserialization (e.g. `HOME`
+/// for paths that expand `~`) and therefore must also match for a cached entry to be reused.
+///
+/// Entries are keyed by the config path and the running executable, so a rebuilt binary (which
+/// may have a different config schema) never deserializes a stale entry.
+pub fn read_config_cached<T>(config_path: &Path, cache_dir: &Path, env_vars: &[&str]) -> Result<T>
+where
+    T: Serialize + DeserializeOwned + Debug,
+{
+    let mut loader = LuaConfigLoader::new(config_path);
+    for name in env_vars {
+        loader = loader.track_env(*name);
+    }
+
+    loader.load_cached(cache_dir)
+}
+
+/// Returns the cached config for `config_path` when it is still fresh, otherwise calls `evaluate`
+/// and caches its result.
+///
+/// `fingerprint` identifies the loader's other inputs (e.g. globals), so that changing them uses
+/// a separate entry.
+pub(crate) fn read_or_evaluate<T>(
+    config_path: &Path,
+    cache_dir: &Path,
+    fingerprint: &str,
+    evaluate: impl FnOnce() -> Result<(T, ConfigDependencies)>,
+) -> Result<T>
+where
+    T: Serialize + DeserializeOwned + Debug,
+{
+    let cache_file = cache_file_path(config_path, cache_dir, fingerprint);
+
+    if let Some(config) = read_cache_entry(&cache_file) {
+        debug!("Using cached config from: {}", cache_file.display());
+        return Ok(config);
+    }
+
+    let (config, dependencies) = evaluate()?;
+    if dependencies.dynamic {
+        debug!("Not caching config that depends on the invocation");
+        return Ok(config);
+    }
+
+    let entry = CacheEntry {
+        dependencies,
+        config,
+    };
+
+    if let Err(err) = write_json(&cache_file, &entry) {
+        warn!(
+            "Failed to write config cache to {}: {:#}",
+            cache_file.display(),
+            err
+        );
+    }
+
+    Ok(entry.config)
+}
+
+fn cache_file_path(config_path: &Path, cache_dir: &Path, fingerprint: &str) -> PathBuf {
+    let mut hasher = Sha256::new();
+    hasher.update(config_path.to_string_lossy().as_bytes());
+    hasher.update(executable_fingerprint().as_bytes());
+    hasher.update(fingerprint.as_bytes());
+    let key = format!("{:x}", hasher.finalize());
+
+    cache_dir.join(format!("config-{}.json", &key[..16]))
+}
+
+fn executable_fingerprint() -> String {
+    let Ok(current_exe) = env::current_exe() else {
+        return String::new();
+    };
+
+    let modified = fs::metadata(&current_exe)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_nanos())
+        .unwrap_or_default();
+
+    format!("{}:{}", current_exe.display(), modified)
+}
+
+fn read_cache_entry<T: DeserializeOwned>(cache_file: &Path) -> Option<T> {
+    let contents = fs::read_to_string(cache_file).ok()?;
+
+    let entry: CacheEntry<T> = match serde_json::from_str(&contents) {
+        Ok(entry) => entry,
+        Err(err) => {
+            debug!(
+                "Ignoring unreadable config cache {}: {}",
+                cache_file.display(),
+                err
+            );
+            return None;
+        }
+    };
+
+    if !entry.dependencies.is_fresh() {
+        trace!("Config cache is stale: {}", cache_file.display());
+        return None;
+    }
+
+    Some(entry.config)
+}
+
+/// Writes `value` as JSON, replacing `file` atomically so concurrent readers never observe a
+/// partial entry.
+pub fn write_json(file: &Path, value: &impl Serialize) -> Result<()> {
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create cache directory: {}", parent.display()))?;
+    }
+
+    let contents = serde_json::to_string(value).context("Failed to serialize cache entry")?;
+
+    let temp_file = file.with_extension(format!(
+        "json.{}.{}.tmp",
+        std::process::id(),
+        TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::write(&temp_file, contents)
+        .with_context(|| format!("Failed to write file: {}", temp_file.display()))?;
+    fs::rename(&temp_file, file)
+        .with_context(|| format!("Failed to move cache into place: {}", file.display()))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use insta::assert_debug_snapshot;
+    use std::collections::BTreeMap;
+    use tempfile::tempdir;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct TestConfig {
+        value: String,
+    }
+
+    fn only_cache_file(cache_dir: &Path) -> PathBuf {
+        let entries: Vec<PathBuf> = fs::read_dir(cache_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(entries.len(), 1, "expected a single cache entry");
+
+        entries[0].clone()
+    }
+
+    #[test]
+    fn test_read_config_cached_reuses_entry_until_config_changes() -> Result<()> {
+        let dir = tempdir()?;
+        let config_file = dir.path().join("config.lua");
+        let cache_dir = dir.path().join("cache");
+
+        fs::write(&config_file, r#"return { value = "original" }"#)?;
+
+        let config: TestConfig = read_config_cached(&config_file, &cache_dir, &[])?;
+        assert_eq!(config.value, "original");
+
+        // tamper with the cached entry to prove that it is used as long as the config is unchanged
+        let cache_file = only_cache_file(&cache_dir);
+        let cached = fs::read_to_string(&cache_file)?;
+        fs::write(&cache_file, cached.replace("original", "from-cache"))?;
+
+        let config: TestConfig = read_config_cached(&config_file, &cache_dir, &[])?;
+        assert_eq!(config.value, "from-cache");
+
+        fs::write(&config_file, r#"return { value = "updated" }"#)?;
+
+        let config: TestConfig = read_config_cached(&config_file, &cache_dir, &[])?;
+        assert_eq!(config.value, "updated");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_config_cached_invalidates_when_required_module_changes() -> Result<()> {
+        let dir = tempdir()?;
+        let config_dir = dir.path().join("config");
+        let cache_dir = dir.path().join("cache");
+
+        fixturify::write(
+            &config_dir,
+            &BTreeMap::from([
+                (
+                    "config.lua".to_string(),
+                    r#"return { value = require("other.value") }"#.to_string(),
+                ),
+                (
+                    "other/value.lua".to_string(),
+                    r#"return "original""#.to_string(),
+                ),
+            ]),
+        )?;
+        let config_file = config_dir.join("config.lua");
+
+        let config: TestConfig = read_config_cached(&config_file, &cache_dir, &[])?;
+        assert_eq!(config.value, "original");
+
+        fs::write(config_dir.join("other/value.lua"), r#"return "updated""#)?;
+
+        let config: TestConfig = read_config_cached(&config_file, &cache_dir, &[])?;
+        assert_eq!(config.value, "updated");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_config_cached_invalidates_when_env_changes() -> Result<()> {
+        let dir = tempdir()?;
+        let config_file = dir.path().join("config.lua");
+        let cache_dir = dir.path().join("cache");
+
+        fs::write(
+            &config_file,
+            r#"return { value = os.getenv("BINUTILS_CACHE_TEST_VALUE") or "unset" }"#,
+        )?;
+
+        let read = || -> Result<String> {
+            let config: TestConfig = read_config_cached(&config_file, &cache_dir, &["HOME"])?;
+            Ok(config.value)
+        };
+
+        let values = temp_env::with_var_unset("BINUTILS_CACHE_TEST_VALUE", || -> Result<_> {
+            Ok(vec![read()?, read()?])
+        })?;
+        let updated = temp_env::with_var("BINUTILS_CACHE_TEST_VALUE", Some("set"), read)?;
+
+        assert_debug_snapshot!((values, updated), @r###"
+        (
+            [
+                "unset",
+                "unset",
+            ],
+            "set",
+        )
+        "###);
+
+        let entry: CacheEntry<TestConfig> =
+            serde_json::from_str(&fs::read_to_string(only_cache_file(&cache_dir))?)?;
+        let tracked_env: Vec<&String> = entry.dependencies.env.keys().collect();
+        assert_debug_snapshot!(tracked_env, @r###"
+        [
+            "BINUTILS_CACHE_TEST_VALUE",
+            "HOME",
+        ]
+        "###);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_config_cached_invalidates_when_read_file_changes() -> Result<()> {
+        let dir = tempdir()?;
+        let config_dir = dir.path().join("config");
+        let cache_dir = dir.path().join("cache");
+
+        fixturify::write(
+            &config_dir,
+            &BTreeMap::from([
+                ("value.txt".to_string(), "original".to_string()),
+                ("suffix.lua".to_string(), r#"return "!""#.to_string()),
+            ]),
+        )?;
+        fs::write(
+            config_dir.join("config.lua"),
+            format!(
+                r#"
+local dir = "{}"
+local file = io.open(dir .. "/value.txt")
+local value = file:read("*a")
+file:close()
+return {{ value = value .. dofile(dir .. "/suffix.lua") }}
+"#,
+                config_dir.display()
+            ),
+        )?;
+        let config_file = config_dir.join("config.lua");
+
+        let config: TestConfig = read_config_cached(&config_file, &cache_dir, &[])?;
+        assert_eq!(config.value, "original!");
+
+        fs::write(config_dir.join("value.txt"), "updated")?;
+        let config: TestConfig = read_config_cached(&config_file, &cache_dir, &[])?;
+        assert_eq!(config.value, "updated!");
+
+        fs::write(config_dir.join("suffix.lua"), r#"return "?""#)?;
+        let config: TestConfig = read_config_cached(&config_file, &cache_dir, &[])?;
+        assert_eq!(config.value, "updated?");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_config_cached_skips_configs_that_read_missing_files() -> Result<()> {
+        let dir = tempdir()?;
+        let config_file = dir.path().join("config.lua");
+        let cache_dir = dir.path().join("cache");
+
+        fs::write(
+            &config_file,
+            format!(
+                r#"return {{ value = io.open("{}") and "present" or "missing" }}"#,
+                dir.path().join("optional.txt").display()
+            ),
+        )?;
+
+        let config: TestConfig = read_config_cached(&config_file, &cache_dir, &[])?;
+        assert_eq!(config.value, "missing");
+        assert!(!cache_dir.exists());
+
+        fs::write(dir.path().join("optional.txt"), "")?;
+        let config: TestConfig = read_config_cached(&config_file, &cache_dir, &[])?;
+        assert_eq!(config.value, "present");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_config_cached_skips_configs_that_require_missing_modules() -> Result<()> {
+        let dir = tempdir()?;
+        let config_file = dir.path().join("config.lua");
+        let cache_dir = dir.path().join("cache");
+
+        fs::write(
+            &config_file,
+            r#"return { value = pcall(require, "optional") and "present" or "missing" }"#,
+        )?;
+
+        let config: TestConfig = read_config_cached(&config_file, &cache_dir, &[])?;
+        assert_eq!(config.value, "missing");
+        assert!(!cache_dir.exists());
+
+        fs::write(dir.path().join("optional.lua"), "return {}")?;
+        let config: TestConfig = read_config_cached(&config_file, &cache_dir, &[])?;
+        assert_eq!(config.value, "present");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_config_cached_skips_configs_that_run_commands_or_read_the_time() -> Result<()> {
+        for value in [
+            r#"io.popen("echo hello"):read("l")"#,
+            r#"tostring(os.execute("true"))"#,
+            "tostring(os.time())",
+            r#"os.date("%Y")"#,
+        ] {
+            let dir = tempdir()?;
+            let config_file = dir.path().join("config.lua");
+            let cache_dir = dir.path().join("cache");
+
+            fs::write(&config_file, format!("return {{ value = {} }}", value))?;
+
+            read_config_cached::<TestConfig>(&config_file, &cache_dir, &[])?;
+            assert!(!cache_dir.exists(), "`{}` was cached", value);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::process::Command;
+
+use crate::LuaType;
+
+/// Describes the invocation that is loading the config. Configs (and lazy values) that are
+/// functions are called with it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, LuaType)]
+pub struct ConfigContext {
+    /// The hostname of the machine.
+    pub hostname: String,
+    /// The current working directory.
+    pub cwd: String,
+    /// The name of the binary that is loading the config (e.g. `startup-tmux`).
+    pub binary: String,
+    /// The arguments that the binary was invoked with (excluding the binary itself).
+    pub args: Vec<String>,
+    /// The environment variables of the process.
+    pub env: BTreeMap<String, String>,
+}
+
+impl ConfigContext {
+    /// The context of the running process.
+    pub fn current() -> Self {
+        let mut args = env::args();
+        let binary = args
+            .next()
+            .map(|arg0| {
+                std::path::Path::new(&arg0)
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or(arg0)
+            })
+            .unwrap_or_default();
+
+        Self {
+            hostname: hostname(),
+            cwd: env::current_dir()
+                .map(|cwd| cwd.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            binary,
+            args: args.collect(),
+            // lossy, since reading any variable that isn't UTF-8 with `env::vars` panics
+            env: env::vars_os()
+                .map(|(name, value)| {
+                    (
+                        name.to_string_lossy().to_string(),
+                        value.to_string_lossy().to_string(),
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+fn hostname() -> String {
+    let output = Command::new("hostname").output();
+
+    match output {
+        Ok(output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).trim().to_string()
+        }
+        _ => env::var("HOSTNAME").unwrap_or_default(),
+    }
+}
@@ -0,0 +1,315 @@
+use anyhow::anyhow;
+use mlua::{Lua, LuaSerdeExt};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt;
+
+use anyhow::Result;
+
+use crate::loader::LazyContext;
+use crate::{ConfigContext, LuaType, LuaTypeRegistry};
+
+/// Lua functions can't be deserialized, so they are swapped for a string with this prefix (and
+/// their index in `PENDING_FUNCTIONS`) before the config is deserialized.
+const PLACEHOLDER_PREFIX: &str = "\0lua_config_utils::function:";
+
+thread_local! {
+    /// The functions of the config that is currently being deserialized, along with whether a
+    /// [`Lazy`] claimed them.
+    static PENDING_FUNCTIONS: RefCell<Vec<(LuaFunction, bool)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A config value that can either be given directly, or as a Lua function that is called (with
+/// the [`ConfigContext`]) when the value is needed, e.g.
+///
+/// ```lua
+/// command = function(ctx) return "cd " .. ctx.cwd end
+/// ```
+#[derive(Clone, PartialEq)]
+pub enum Lazy<T> {
+    Value(T),
+    Function(LuaFunction),
+}
+
+impl<T: DeserializeOwned + Clone> Lazy<T> {
+    /// Returns the value, calling the config's function when it provided one.
+    pub fn resolve(&self) -> Result<T> {
+        match self {
+            Lazy::Value(value) => Ok(value.clone()),
+            Lazy::Function(function) => function.call(),
+        }
+    }
+}
+
+/// Values are formatted as if they weren't lazy.
+impl<T: fmt::Debug> fmt::Debug for Lazy<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Lazy::Value(value) => value.fmt(f),
+            Lazy::Function(function) => function.fmt(f),
+        }
+    }
+}
+
+impl<T> From<T> for Lazy<T> {
+    fn from(value: T) -> Self {
+        Lazy::Value(value)
+    }
+}
+
+impl<T: Serialize> Serialize for Lazy<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Lazy::Value(value) => value.serialize(serializer),
+            Lazy::Function(_) => Err(serde::ser::Error::custom(
+                "Lua functions can't be serialized",
+            )),
+        }
+    }
+}
+
+impl<'de, T: DeserializeOwned> Deserialize<'de> for Lazy<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+
+        if let Some(index) = value
+            .as_str()
+            .and_then(|value| value.strip_prefix(PLACEHOLDER_PREFIX))
+        {
+            return claim_function(index)
+                .map(Lazy::Function)
+                .map_err(serde::de::Error::custom);
+        }
+
+        T::deserialize(value)
+            .map(Lazy::Value)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl<T: LuaType> LuaType for Lazy<T> {
+    fn register(registry: &mut LuaTypeRegistry) {
+        T::register(registry);
+        ConfigContext::register(registry);
+    }
+}
+
+/// A function from the config, which keeps the Lua state it belongs to alive.
+#[derive(Clone)]
+pub struct LuaFunction {
+    lua: Lua,
+    function: mlua::Function,
+    context: mlua::Value,
+}
+
+impl LuaFunction {
+    fn call<T: DeserializeOwned>(&self) -> Result<T> {
+        let value: mlua::Value = self.function.call(self.context.clone())?;
+
+        Ok(self.lua.from_value(value)?)
+    }
+}
+
+impl fmt::Debug for LuaFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "LuaFunction({:p})", self.function.to_pointer())
+    }
+}
+
+impl PartialEq for LuaFunction {
+    fn eq(&self, other: &Self) -> bool {
+        self.function == other.function
+    }
+}
+
+fn claim_function(index: &str) -> Result<LuaFunction> {
+    let index: usize = index.parse()?;
+
+    PENDING_FUNCTIONS.with(|functions| {
+        let mut functions = functions.borrow_mut();
+        let (function, claimed) = functions.get_mut(index).ok_or_else(|| {
+            anyhow!("Lua functions can only be deserialized while loading a config")
+        })?;
+        *claimed = true;
+
+        Ok(function.clone())
+    })
+}
+
+/// Replaces every function within `value` with a placeholder, and deserializes it with `T`
+/// (where [`Lazy`] fields swap the placeholders back).
+///
+/// Returns whether the config contained any functions.
+pub(crate) fn deserialize_with_functions<T: DeserializeOwned>(
+    lua: &Lua,
+    value: mlua::Value,
+    context: &mut LazyContext,
+) -> Result<(T, bool)> {
+    let mut functions = vec![];
+    let value = replace_functions(lua, value, context, &mut functions, &mut HashSet::new())?;
+    let has_functions = !functions.is_empty();
+
+    PENDING_FUNCTIONS.with(|pending| {
+        *pending.borrow_mut() = functions
+            .into_iter()
+            .map(|function| (function, false))
+            .collect();
+    });
+
+    let result = lua.from_value::<T>(value);
+    let unclaimed = PENDING_FUNCTIONS.with(|pending| {
+        pending
+            .take()
+            .iter()
+            .filter(|(_, claimed)| !claimed)
+            .count()
+    });
+
+    let config = result?;
+    if unclaimed > 0 {
+        anyhow::bail!(
+            "The config contains {} function(s) in fields that don't support them (only lazy fields can be functions)",
+            unclaimed
+        );
+    }
+
+    Ok((config, has_functions))
+}
+
+fn replace_functions(
+    lua: &Lua,
+    value: mlua::Value,
+    context: &mut LazyContext,
+    functions: &mut Vec<LuaFunction>,
+    visited: &mut HashSet<*const std::ffi::c_void>,
+) -> Result<mlua::Value> {
+    match value {
+        mlua::Value::Function(function) => {
+            let placeholder = format!("{}{}", PLACEHOLDER_PREFIX, functions.len());
+            functions.push(LuaFunction {
+                lua: lua.clone(),
+                function,
+                context: context.value()?,
+            });
+
+            Ok(mlua::Value::String(lua.create_string(placeholder)?))
+        }
+        mlua::Value::Table(table) => {
+            if !visited.insert(table.to_pointer()) {
+                return Ok(mlua::Value::Table(table));
+            }
+
+            let mut replacements = vec![];
+            for pair in table.pairs::<mlua::Value, mlua::Value>() {
+                let (key, value) = pair?;
+                if matches!(value, mlua::Value::Function(_) | mlua::Value::Table(_)) {
+                    replacements.push((key, value));
+                }
+            }
+            for (key, value) in replacements {
+                let value = replace_functions(lua, value, context, functions, visited)?;
+                table.raw_set(key, value)?;
+            }
+
+            Ok(mlua::Value::Table(table))
+        }
+        value => Ok(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LuaConfigLoader;
+    use insta::{assert_debug_snapshot, assert_snapshot};
+    use std::collections::BTreeMap;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[derive(Debug, Deserialize)]
+    struct TestConfig {
+        greeting: Lazy<String>,
+        #[serde(default)]
+        farewell: Option<Lazy<String>>,
+    }
+
+    fn test_context() -> ConfigContext {
+        ConfigContext {
+            hostname: "laptop".to_string(),
+            cwd: "/home/user/src".to_string(),
+            binary: "startup-tmux".to_string(),
+            args: vec!["--attach".to_string()],
+            env: BTreeMap::from([("USER".to_string(), "user".to_string())]),
+        }
+    }
+
+    #[test]
+    fn test_lazy_values_are_resolved_with_the_context() -> Result<()> {
+        let dir = tempdir()?;
+        let config_file = dir.path().join("config.lua");
+        fs::write(
+            &config_file,
+            r#"
+return {
+    greeting = function(ctx)
+        return "hello " .. ctx.env.USER .. " from " .. ctx.binary .. " " .. ctx.args[1]
+    end,
+    farewell = "bye",
+}
+            "#,
+        )?;
+
+        let (config, dependencies) = LuaConfigLoader::<TestConfig>::new(&config_file)
+            .context(test_context())
+            .evaluate()?;
+
+        assert!(matches!(config.greeting, Lazy::Function(_)));
+        assert_debug_snapshot!(
+            (
+                config.greeting.resolve()?,
+                config.farewell.map(|farewell| farewell.resolve()).transpose()?,
+                dependencies.dynamic,
+            ),
+            @r###"
+        (
+            "hello user from startup-tmux --attach",
+            Some(
+                "bye",
+            ),
+            true,
+        )
+        "###
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_functions_are_only_allowed_in_lazy_fields() -> Result<()> {
+        let dir = tempdir()?;
+        let config_file = dir.path().join("config.lua");
+        fs::write(
+            &config_file,
+            r#"return { greeting = "hello", farewell = "bye", other = { function() end } }"#,
+        )?;
+
+        #[derive(Debug, Deserialize)]
+        struct StrictConfig {
+            #[allow(dead_code)]
+            greeting: String,
+            #[allow(dead_code)]
+            other: Vec<String>,
+        }
+
+        let err = LuaConfigLoader::<StrictConfig>::new(&config_file)
+            .context(test_context())
+            .load()
+            .unwrap_err();
+
+        assert_snapshot!(err, @"The config contains 1 function(s) in fields that don't support them (only lazy fields can be functions)");
+
+        Ok(())
+    }
+}
@@ -1,65 +1,100 @@
-use anyhow::anyhow;
-use mlua::{Lua, LuaSerdeExt};
 use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::env;
 use std::fmt::Debug;
 use std::fs;
-use std::path::Path;
-use tracing::{debug, error, trace};
+use std::path::{Path, PathBuf};
+use tracing::debug;
 
 use anyhow::{Context, Result};
 
+pub mod cache;
+mod context;
+mod lazy;
+mod loader;
 pub mod lua_type_gen;
 
-pub fn read_config<T: DeserializeOwned + Debug>(config_path: &Path) -> Result<T> {
-    if !config_path.is_file() {
-        error!(
-            "The specified config path is not a file: {}",
-            config_path.display()
-        );
-
-        anyhow::bail!(
-            "The specified config path is not a file: {}",
-            config_path.display()
-        );
+pub use context::ConfigContext;
+pub use lazy::{Lazy, LuaFunction};
+pub use loader::LuaConfigLoader;
+
+pub use lua_type_derive::LuaType;
+pub use lua_type_gen::registry::{LuaType, LuaTypeRegistry};
+
+// lets `#[derive(LuaType)]` (which refers to `::lua_config_utils`) be used within this crate
+extern crate self as lua_config_utils;
+
+/// The files and environment variables that were read while evaluating a config.
+///
+/// Files are recorded with the sha256 of their contents, environment variables with the value
+/// they had at evaluation time (`None` when unset).
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConfigDependencies {
+    pub files: BTreeMap<PathBuf, String>,
+    pub env: BTreeMap<String, Option<String>>,
+    /// Whether the config depends on the invocation (i.e. it was a function or contained
+    /// [`Lazy`] functions) or on files that can't be tracked (e.g. probing for a missing file with
+    /// `io.open`), in which case it is never fresh.
+    #[serde(default)]
+    pub dynamic: bool,
+}
+
+impl ConfigDependencies {
+    /// Records the current value of the given environment variable.
+    pub fn track_env(&mut self, name: &str) {
+        self.env.insert(name.to_string(), env::var(name).ok());
     }
 
-    debug!("Reading config from: {}", config_path.display());
+    /// Returns `true` when the config isn't dynamic, and none of the recorded files or environment
+    /// variables have changed.
+    pub fn is_fresh(&self) -> bool {
+        if self.dynamic {
+            return false;
+        }
 
-    let lua = Lua::new();
-    let globals = lua.globals();
-    let config_dir = config_path.parent().ok_or_else(|| {
-        anyhow!(
-            "Could not get parent directory of config_path: {}",
-            config_path.display()
-        )
-    })?;
-
-    let package: mlua::Table = globals.get("package")?;
-    let package_path: String = package.get("path")?;
-
-    let new_package_path = format!(
-        "{}/?.lua;{}/?/init.lua;{}",
-        config_dir.display(),
-        config_dir.display(),
-        package_path
-    );
-    package.set("path", new_package_path)?;
-    let config_str = fs::read_to_string(config_path).with_context(|| {
-        format!(
-            "Could not read config file from: {}",
-            &config_path.display()
-        )
-    })?;
-    let result = lua
-        .load(&config_str)
-        .set_name(config_path.to_string_lossy())
-        .eval()?;
+        for (path, hash) in &self.files {
+            match hash_file(path) {
+                Ok(current) if &current == hash => {}
+                _ => {
+                    debug!("Config dependency changed: {}", path.display());
+                    return false;
+                }
+            }
+        }
+
+        for (name, value) in &self.env {
+            if &env::var(name).ok() != value {
+                debug!("Config environment variable changed: {}", name);
+                return false;
+            }
+        }
+
+        true
+    }
+}
 
-    let config: T = lua.from_value(result)?;
+pub(crate) fn hash_file(path: &Path) -> Result<String> {
+    let contents =
+        fs::read(path).with_context(|| format!("Could not read file: {}", path.display()))?;
 
-    trace!("Config: {:?}", config);
+    Ok(format!("{:x}", Sha256::digest(contents)))
+}
+
+/// Reads the Lua config at `config_path`, see [`LuaConfigLoader`] for defaults, validation, etc.
+pub fn read_config<T: DeserializeOwned + Debug>(config_path: &Path) -> Result<T> {
+    LuaConfigLoader::new(config_path).load()
+}
 
-    Ok(config)
+/// Evaluates the Lua config at `config_path`, returning the deserialized config along with every
+/// file (the config itself and anything it `require`s or reads via `dofile`, `loadfile`,
+/// `io.open` or `io.lines`) and environment variable (via
+/// `os.getenv`) that was read while doing so.
+pub fn evaluate_config<T: DeserializeOwned + Debug>(
+    config_path: &Path,
+) -> Result<(T, ConfigDependencies)> {
+    LuaConfigLoader::new(config_path).evaluate()
 }
 
 #[cfg(test)]
@@ -91,6 +126,56 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_evaluate_config_tracks_dependencies() -> Result<()> {
+        let env = setup_test_environment();
+
+        fixturify::write(
+            &env.config_dir,
+            &std::collections::BTreeMap::from([
+                (
+                    "config.lua".to_string(),
+                    r#"return { test = require("nested.value"), home = os.getenv("HOME") }"#
+                        .to_string(),
+                ),
+                ("nested/value.lua".to_string(), "return 42".to_string()),
+            ]),
+        )?;
+
+        #[derive(serde::Deserialize, Debug)]
+        struct TestConfig {
+            #[allow(dead_code)]
+            test: i32,
+        }
+
+        let (_, dependencies) = evaluate_config::<TestConfig>(&env.config_file)?;
+
+        let files: Vec<String> = dependencies
+            .files
+            .keys()
+            .map(|path| stabilize_home_paths(&env, &path.to_string_lossy()))
+            .collect();
+        let env_vars: Vec<&String> = dependencies.env.keys().collect();
+
+        assert_debug_snapshot!((files, env_vars), @r###"
+        (
+            [
+                "~/.config/binutils/config.lua",
+                "~/.config/binutils/nested/value.lua",
+            ],
+            [
+                "HOME",
+            ],
+        )
+        "###);
+        assert!(dependencies.is_fresh());
+
+        fs::write(env.config_dir.join("nested/value.lua"), "return 43")?;
+        assert!(!dependencies.is_fresh());
+
+        Ok(())
+    }
+
     #[test]
     fn test_read_config_invalid_lua() -> Result<()> {
         let env = setup_test_environment();
@@ -0,0 +1,742 @@
+use anyhow::anyhow;
+use mlua::{Lua, LuaSerdeExt, SerializeOptions};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Debug;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use tracing::{debug, error, trace};
+
+use anyhow::{Context, Result};
+
+use crate::{ConfigContext, ConfigDependencies, cache, hash_file, lazy};
+
+type Validator<T> = Box<dyn Fn(&T) -> Result<()>>;
+
+/// Loads a typed config from a Lua file.
+///
+/// ```no_run
+/// # use lua_config_utils::LuaConfigLoader;
+/// # #[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+/// # struct Config { name: String }
+/// let config: Config = LuaConfigLoader::new("/home/me/.config/tool/config.lua")
+///     .search_path("/usr/share/tool/lua")
+///     .global("hostname", "laptop")
+///     .defaults(Config::default())
+///     .validate(|config: &Config| {
+///         anyhow::ensure!(!config.name.is_empty(), "`name` must not be empty");
+///         Ok(())
+///     })
+///     .load()?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct LuaConfigLoader<T> {
+    config_path: PathBuf,
+    search_paths: Vec<PathBuf>,
+    globals: Vec<(String, serde_json::Result<serde_json::Value>)>,
+    defaults: Option<serde_json::Result<serde_json::Value>>,
+    validators: Vec<Validator<T>>,
+    env_vars: Vec<String>,
+    context: Option<ConfigContext>,
+}
+
+impl<T> LuaConfigLoader<T> {
+    pub fn new(config_path: impl Into<PathBuf>) -> Self {
+        Self {
+            config_path: config_path.into(),
+            search_paths: vec![],
+            globals: vec![],
+            defaults: None,
+            validators: vec![],
+            env_vars: vec![],
+            context: None,
+        }
+    }
+
+    /// Adds a directory that `require` searches (after the config's own directory).
+    pub fn search_path(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.search_paths.push(dir.into());
+        self
+    }
+
+    /// Sets a global variable that the config can read while it is evaluated.
+    pub fn global(mut self, name: impl Into<String>, value: impl Serialize) -> Self {
+        self.globals
+            .push((name.into(), serde_json::to_value(value)));
+        self
+    }
+
+    /// Runs `validator` against every loaded config, failing the load when it returns an error.
+    pub fn validate(mut self, validator: impl Fn(&T) -> Result<()> + 'static) -> Self {
+        self.validators.push(Box::new(validator));
+        self
+    }
+
+    /// Sets the context that configs which are functions (and [`Lazy`](crate::Lazy) values) are
+    /// called with, instead of [`ConfigContext::current`].
+    pub fn context(mut self, context: ConfigContext) -> Self {
+        self.context = Some(context);
+        self
+    }
+
+    /// Records an environment variable that affects deserialization (e.g. `HOME` for paths that
+    /// expand `~`), so that a cached config is only reused while it is unchanged.
+    pub fn track_env(mut self, name: impl Into<String>) -> Self {
+        self.env_vars.push(name.into());
+        self
+    }
+}
+
+impl<T: Serialize> LuaConfigLoader<T> {
+    /// Sets the defaults that the config is deep merged onto, i.e. tables the config returns only
+    /// override the fields they set (lists are replaced wholesale).
+    pub fn defaults(mut self, defaults: T) -> Self {
+        self.defaults = Some(serde_json::to_value(defaults));
+        self
+    }
+}
+
+impl<T: DeserializeOwned + Debug> LuaConfigLoader<T> {
+    /// Evaluates and validates the config.
+    pub fn load(&self) -> Result<T> {
+        let (config, _) = self.evaluate()?;
+        self.run_validators(&config)?;
+
+        Ok(config)
+    }
+
+    /// Evaluates the config, returning it (without validating it) along with every file (the
+    /// config itself and anything it `require`s) and environment variable (via `os.getenv`) that
+    /// was read while doing so.
+    pub fn evaluate(&self) -> Result<(T, ConfigDependencies)> {
+        let config_path = &self.config_path;
+        if !config_path.is_file() {
+            error!(
+                "The specified config path is not a file: {}",
+                config_path.display()
+            );
+
+            anyhow::bail!(
+                "The specified config path is not a file: {}",
+                config_path.display()
+            );
+        }
+
+        debug!("Reading config from: {}", config_path.display());
+
+        let lua = Lua::new();
+        let globals = lua.globals();
+        let config_dir = config_path.parent().ok_or_else(|| {
+            anyhow!(
+                "Could not get parent directory of config_path: {}",
+                config_path.display()
+            )
+        })?;
+
+        let package: mlua::Table = globals.get("package")?;
+        let package_path: String = package.get("path")?;
+
+        let mut new_package_path = String::new();
+        for dir in std::iter::once(config_dir).chain(self.search_paths.iter().map(PathBuf::as_path))
+        {
+            new_package_path.push_str(&format!(
+                "{}/?.lua;{}/?/init.lua;",
+                dir.display(),
+                dir.display()
+            ));
+        }
+        new_package_path.push_str(&package_path);
+        package.set("path", new_package_path)?;
+
+        let loaded_files = Rc::new(RefCell::new(vec![config_path.to_path_buf()]));
+        let read_env = Rc::new(RefCell::new(BTreeMap::new()));
+        let untracked_reads = Rc::new(Cell::new(false));
+        let reads = FileReads {
+            loaded_files: Rc::clone(&loaded_files),
+            untracked: Rc::clone(&untracked_reads),
+        };
+        track_required_files(&lua, reads.clone())?;
+        track_file_reads(&lua, reads)?;
+        track_untracked_calls(&lua, Rc::clone(&untracked_reads))?;
+        track_env_reads(&lua, Rc::clone(&read_env))?;
+
+        for (name, value) in &self.globals {
+            let value = value
+                .as_ref()
+                .map_err(|err| anyhow!("Unable to serialize the `{}` global: {}", name, err))?;
+            globals.set(
+                name.as_str(),
+                lua.to_value_with(value, serialize_options())?,
+            )?;
+        }
+
+        let config_str = fs::read_to_string(config_path).with_context(|| {
+            format!("Could not read config file from: {}", config_path.display())
+        })?;
+        let mut result = lua
+            .load(&config_str)
+            .set_name(config_path.to_string_lossy())
+            .eval()?;
+
+        let mut context = LazyContext::new(&lua, self.context.as_ref());
+
+        // configs that are functions adapt to the invocation, so they must never be cached
+        let mut dynamic = false;
+        if let mlua::Value::Function(function) = result {
+            dynamic = true;
+            result = function.call(context.value()?)?;
+        }
+
+        if let Some(defaults) = &self.defaults {
+            let defaults = defaults
+                .as_ref()
+                .map_err(|err| anyhow!("Unable to serialize the config defaults: {}", err))?;
+            result = merge_values(
+                &lua,
+                lua.to_value_with(defaults, serialize_options())?,
+                result,
+            )?;
+        }
+
+        let (config, has_functions): (T, bool) =
+            lazy::deserialize_with_functions(&lua, result, &mut context)?;
+
+        trace!("Config: {:?}", config);
+
+        let mut dependencies = ConfigDependencies {
+            env: read_env.take(),
+            dynamic: dynamic || has_functions || untracked_reads.get(),
+            ..Default::default()
+        };
+        for path in loaded_files.take() {
+            let hash = hash_file(&path)?;
+            dependencies.files.insert(path, hash);
+        }
+        for name in &self.env_vars {
+            dependencies.track_env(name);
+        }
+
+        trace!("Config dependencies: {:?}", dependencies);
+
+        Ok((config, dependencies))
+    }
+
+    fn run_validators(&self, config: &T) -> Result<()> {
+        for validator in &self.validators {
+            validator(config)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + Debug> LuaConfigLoader<T> {
+    /// Like [`LuaConfigLoader::load`], but reuses a previously evaluated copy of the config from
+    /// `cache_dir` when none of the files it loaded (or environment variables it read) have
+    /// changed.
+    pub fn load_cached(&self, cache_dir: &Path) -> Result<T> {
+        let config =
+            cache::read_or_evaluate(&self.config_path, cache_dir, &self.fingerprint()?, || {
+                self.evaluate()
+            })?;
+        self.run_validators(&config)?;
+
+        Ok(config)
+    }
+
+    /// Identifies everything (besides the Lua files themselves) that the evaluated config depends
+    /// on, so that changing e.g. a global never reuses a stale cache entry.
+    fn fingerprint(&self) -> Result<String> {
+        let mut hasher = Sha256::new();
+        for dir in &self.search_paths {
+            hasher.update(dir.to_string_lossy().as_bytes());
+        }
+        for (name, value) in &self.globals {
+            let value = value
+                .as_ref()
+                .map_err(|err| anyhow!("Unable to serialize the `{}` global: {}", name, err))?;
+            hasher.update(name.as_bytes());
+            hasher.update(value.to_string().as_bytes());
+        }
+        if let Some(defaults) = &self.defaults {
+            let defaults = defaults
+                .as_ref()
+                .map_err(|err| anyhow!("Unable to serialize the config defaults: {}", err))?;
+            hasher.update(defaults.to_string().as_bytes());
+        }
+
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+}
+
+/// The context that functions within the config are called with, which is only built (e.g. for
+/// [`ConfigContext::current`], which spawns `hostname`) once a function needs it.
+pub(crate) struct LazyContext<'a> {
+    lua: &'a Lua,
+    context: Option<&'a ConfigContext>,
+    value: Option<mlua::Value>,
+}
+
+impl<'a> LazyContext<'a> {
+    fn new(lua: &'a Lua, context: Option<&'a ConfigContext>) -> Self {
+        Self {
+            lua,
+            context,
+            value: None,
+        }
+    }
+
+    pub(crate) fn value(&mut self) -> Result<mlua::Value> {
+        if let Some(value) = &self.value {
+            return Ok(value.clone());
+        }
+
+        let value = match self.context {
+            Some(context) => self.lua.to_value_with(context, serialize_options())?,
+            None => self
+                .lua
+                .to_value_with(&ConfigContext::current(), serialize_options())?,
+        };
+        self.value = Some(value.clone());
+
+        Ok(value)
+    }
+}
+
+/// `None` fields should leave the Lua value unset, rather than setting it to a `null` sentinel.
+fn serialize_options() -> SerializeOptions {
+    SerializeOptions::new()
+        .serialize_none_to_null(false)
+        .serialize_unit_to_null(false)
+}
+
+/// Merges `overrides` onto `defaults`, recursing into tables that both sides use as maps.
+fn merge_values(lua: &Lua, defaults: mlua::Value, overrides: mlua::Value) -> Result<mlua::Value> {
+    match (defaults, overrides) {
+        (mlua::Value::Table(defaults), mlua::Value::Table(overrides))
+            if defaults.raw_len() == 0 && overrides.raw_len() == 0 =>
+        {
+            let merged = lua.create_table()?;
+            for pair in defaults.pairs::<mlua::Value, mlua::Value>() {
+                let (key, value) = pair?;
+                merged.raw_set(key, value)?;
+            }
+            for pair in overrides.pairs::<mlua::Value, mlua::Value>() {
+                let (key, value) = pair?;
+                let default: mlua::Value = merged.raw_get(&key)?;
+                merged.raw_set(key, merge_values(lua, default, value)?)?;
+            }
+
+            Ok(mlua::Value::Table(merged))
+        }
+        (defaults, mlua::Value::Nil) => Ok(defaults),
+        (_, overrides) => Ok(overrides),
+    }
+}
+
+/// Wraps the global `require` so that every Lua file it resolves (via `package.searchpath`) is
+/// recorded in `loaded_files`. Modules that aren't found there (e.g. native modules) can't be
+/// tracked, so they make the config uncacheable.
+fn track_required_files(lua: &Lua, reads: FileReads) -> Result<()> {
+    let globals = lua.globals();
+    let original_require: mlua::Function = globals.get("require")?;
+
+    let require = lua.create_function(move |lua, name: String| {
+        let package: mlua::Table = lua.globals().get("package")?;
+        let loaded: mlua::Table = package.get("loaded")?;
+        // modules that are already loaded (e.g. `string`) don't read anything
+        if loaded.contains_key(name.as_str())? {
+            return original_require.call::<mlua::MultiValue>(name);
+        }
+
+        let search_path: String = package.get("path")?;
+        let searchpath: mlua::Function = package.get("searchpath")?;
+        let (found, _): (Option<String>, Option<String>) =
+            searchpath.call((name.as_str(), search_path))?;
+
+        match found {
+            Some(found) => {
+                trace!("Config required `{}` from {}", name, found);
+                reads.loaded_files.borrow_mut().push(PathBuf::from(found));
+            }
+            None => {
+                debug!(
+                    "Config required `{}`, which isn't a Lua file, so it won't be cached",
+                    name
+                );
+                reads.untracked.set(true);
+            }
+        }
+
+        original_require.call::<mlua::MultiValue>(name)
+    })?;
+    globals.set("require", require)?;
+
+    Ok(())
+}
+
+/// Where the wrappers of [`track_file_reads`] record the files that the config reads.
+#[derive(Clone)]
+struct FileReads {
+    loaded_files: Rc<RefCell<Vec<PathBuf>>>,
+    /// Set when a file that doesn't exist was read, which can't be tracked by its hash.
+    untracked: Rc<Cell<bool>>,
+}
+
+impl FileReads {
+    fn record(&self, path: &str) {
+        let path = env::current_dir()
+            .map(|cwd| cwd.join(path))
+            .unwrap_or_else(|_| PathBuf::from(path));
+
+        if path.is_file() {
+            trace!("Config read {}", path.display());
+            self.loaded_files.borrow_mut().push(path);
+        } else {
+            debug!(
+                "Config read {}, which isn't a file, so it won't be cached",
+                path.display()
+            );
+            self.untracked.set(true);
+        }
+    }
+}
+
+/// Wraps `dofile`, `loadfile`, `io.lines` and `io.open` (when opening a file for reading) so that
+/// the files they read are recorded like the ones loaded via `require`. Reading a file that
+/// doesn't exist (e.g. probing for an optional file with `io.open`) makes the config uncacheable,
+/// since its absence can't be tracked by a hash.
+fn track_file_reads(lua: &Lua, reads: FileReads) -> Result<()> {
+    let globals = lua.globals();
+    let io: mlua::Table = globals.get("io")?;
+
+    fn first_path(args: &mlua::MultiValue) -> Option<String> {
+        match args.front() {
+            Some(mlua::Value::String(path)) => Some(path.to_string_lossy()),
+            _ => None,
+        }
+    }
+    fn opened_for_reading(args: &mlua::MultiValue) -> Option<String> {
+        match args.get(1) {
+            None | Some(mlua::Value::Nil) => first_path(args),
+            Some(mlua::Value::String(mode)) if mode.as_bytes().starts_with(b"r") => {
+                first_path(args)
+            }
+            _ => None,
+        }
+    }
+
+    wrap_file_reader(lua, &globals, "dofile", first_path, reads.clone())?;
+    wrap_file_reader(lua, &globals, "loadfile", first_path, reads.clone())?;
+    wrap_file_reader(lua, &io, "lines", first_path, reads.clone())?;
+    wrap_file_reader(lua, &io, "open", opened_for_reading, reads)?;
+
+    Ok(())
+}
+
+/// Replaces `table[name]` with a function that records the file (if any) that `read_path` finds
+/// in its arguments before calling the original.
+fn wrap_file_reader(
+    lua: &Lua,
+    table: &mlua::Table,
+    name: &str,
+    read_path: impl Fn(&mlua::MultiValue) -> Option<String> + 'static,
+    reads: FileReads,
+) -> Result<()> {
+    let original: mlua::Function = table.get(name)?;
+
+    let wrapper = lua.create_function(move |_, args: mlua::MultiValue| {
+        if let Some(path) = read_path(&args) {
+            reads.record(&path);
+        }
+        original.call::<mlua::MultiValue>(args)
+    })?;
+    table.set(name, wrapper)?;
+
+    Ok(())
+}
+
+/// Wraps `io.popen`, `os.execute`, `os.time` and `os.date`, whose results can change without any
+/// tracked file or environment variable changing, so that calling them makes the config
+/// uncacheable.
+fn track_untracked_calls(lua: &Lua, untracked: Rc<Cell<bool>>) -> Result<()> {
+    let globals = lua.globals();
+
+    for (table_name, name) in [
+        ("io", "popen"),
+        ("os", "execute"),
+        ("os", "time"),
+        ("os", "date"),
+    ] {
+        let table: mlua::Table = globals.get(table_name)?;
+        let original: mlua::Function = table.get(name)?;
+        let untracked = Rc::clone(&untracked);
+
+        let wrapper = lua.create_function(move |_, args: mlua::MultiValue| {
+            debug!(
+                "Config called `{}.{}`, so it won't be cached",
+                table_name, name
+            );
+            untracked.set(true);
+            original.call::<mlua::MultiValue>(args)
+        })?;
+        table.set(name, wrapper)?;
+    }
+
+    Ok(())
+}
+
+/// Replaces `os.getenv` with an implementation that records every variable that was read.
+fn track_env_reads(
+    lua: &Lua,
+    read_env: Rc<RefCell<BTreeMap<String, Option<String>>>>,
+) -> Result<()> {
+    let os: mlua::Table = lua.globals().get("os")?;
+
+    let getenv = lua.create_function(move |_, name: String| {
+        let value = env::var(&name).ok();
+        read_env.borrow_mut().insert(name, value.clone());
+        Ok(value)
+    })?;
+    os.set("getenv", getenv)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use insta::{assert_debug_snapshot, assert_snapshot};
+    use serde::Deserialize;
+    use tempfile::tempdir;
+
+    #[derive(Debug, Default, Serialize, Deserialize)]
+    struct TestConfig {
+        name: String,
+        #[serde(default)]
+        tags: Vec<String>,
+        #[serde(default)]
+        window: TestWindow,
+    }
+
+    #[derive(Debug, Default, Serialize, Deserialize)]
+    struct TestWindow {
+        #[serde(default)]
+        width: u32,
+        #[serde(default)]
+        height: u32,
+        #[serde(default)]
+        title: Option<String>,
+    }
+
+    #[test]
+    fn test_search_path_and_globals() -> Result<()> {
+        let dir = tempdir()?;
+        fixturify::write(
+            dir.path(),
+            &BTreeMap::from([
+                (
+                    "config/config.lua".to_string(),
+                    r#"return { name = require("naming").prefix .. hostname, tags = tags }"#
+                        .to_string(),
+                ),
+                (
+                    "shared/naming.lua".to_string(),
+                    r#"return { prefix = "host-" }"#.to_string(),
+                ),
+            ]),
+        )?;
+
+        let loader = LuaConfigLoader::<TestConfig>::new(dir.path().join("config/config.lua"))
+            .search_path(dir.path().join("shared"))
+            .global("hostname", "laptop")
+            .global("tags", ["work", "personal"]);
+        let (config, dependencies) = loader.evaluate()?;
+
+        assert_debug_snapshot!(config, @r###"
+        TestConfig {
+            name: "host-laptop",
+            tags: [
+                "work",
+                "personal",
+            ],
+            window: TestWindow {
+                width: 0,
+                height: 0,
+                title: None,
+            },
+        }
+        "###);
+        assert!(
+            dependencies
+                .files
+                .contains_key(&dir.path().join("shared/naming.lua"))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_defaults_are_deep_merged() -> Result<()> {
+        let dir = tempdir()?;
+        let config_file = dir.path().join("config.lua");
+        fs::write(
+            &config_file,
+            r#"return { name = "custom", window = { height = 50 } }"#,
+        )?;
+
+        let config = LuaConfigLoader::new(&config_file)
+            .defaults(TestConfig {
+                name: "default".to_string(),
+                tags: vec!["default".to_string()],
+                window: TestWindow {
+                    width: 80,
+                    height: 24,
+                    title: None,
+                },
+            })
+            .load()?;
+
+        assert_debug_snapshot!(config, @r###"
+        TestConfig {
+            name: "custom",
+            tags: [
+                "default",
+            ],
+            window: TestWindow {
+                width: 80,
+                height: 50,
+                title: None,
+            },
+        }
+        "###);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate() -> Result<()> {
+        let dir = tempdir()?;
+        let config_file = dir.path().join("config.lua");
+        fs::write(&config_file, r#"return { name = "" }"#)?;
+
+        let err = LuaConfigLoader::new(&config_file)
+            .validate(|config: &TestConfig| {
+                anyhow::ensure!(!config.name.is_empty(), "`name` must not be empty");
+                Ok(())
+            })
+            .load()
+            .unwrap_err();
+
+        assert_snapshot!(err, @r###"
+        `name` must not be empty
+        "###);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_cached_keys_entries_by_globals() -> Result<()> {
+        let dir = tempdir()?;
+        let config_file = dir.path().join("config.lua");
+        let cache_dir = dir.path().join("cache");
+        fs::write(&config_file, r#"return { name = hostname }"#)?;
+
+        let load = |hostname: &str| -> Result<String> {
+            let config: TestConfig = LuaConfigLoader::new(&config_file)
+                .global("hostname", hostname)
+                .load_cached(&cache_dir)?;
+            Ok(config.name)
+        };
+
+        assert_debug_snapshot!(
+            (load("laptop")?, load("desktop")?, load("laptop")?),
+            @r###"
+            (
+                "laptop",
+                "desktop",
+                "laptop",
+            )
+            "###
+        );
+        assert_eq!(fs::read_dir(&cache_dir)?.count(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_function_is_called_with_the_context() -> Result<()> {
+        let dir = tempdir()?;
+        let config_file = dir.path().join("config.lua");
+        let cache_dir = dir.path().join("cache");
+        fs::write(
+            &config_file,
+            r#"
+return function(ctx)
+    return { name = ctx.hostname, tags = { ctx.binary, ctx.cwd } }
+end
+            "#,
+        )?;
+
+        let config: TestConfig = LuaConfigLoader::new(&config_file)
+            .context(ConfigContext {
+                hostname: "laptop".to_string(),
+                cwd: "/home/user".to_string(),
+                binary: "startup-tmux".to_string(),
+                args: vec![],
+                env: BTreeMap::new(),
+            })
+            .load_cached(&cache_dir)?;
+
+        assert_debug_snapshot!(config, @r###"
+        TestConfig {
+            name: "laptop",
+            tags: [
+                "startup-tmux",
+                "/home/user",
+            ],
+            window: TestWindow {
+                width: 0,
+                height: 0,
+                title: None,
+            },
+        }
+        "###);
+        // the config depends on the invocation, so it isn't cached
+        assert!(!cache_dir.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_current_context_with_non_utf8_env_var() -> Result<()> {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempdir()?;
+        let config_file = dir.path().join("config.lua");
+        fs::write(
+            &config_file,
+            r#"
+return function(ctx)
+    return { name = ctx.env.BINUTILS_LOADER_TEST_VALUE }
+end
+            "#,
+        )?;
+
+        let config: TestConfig = temp_env::with_var(
+            "BINUTILS_LOADER_TEST_VALUE",
+            Some(OsStr::from_bytes(b"caf\xe9")),
+            || LuaConfigLoader::new(&config_file).load(),
+        )?;
+
+        assert_eq!(config.name, "caf\u{fffd}");
+
+        Ok(())
+    }
+}
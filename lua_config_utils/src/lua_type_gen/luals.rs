@@ -0,0 +1,60 @@
+use super::model::{ClassDefinition, FieldKey, TypeDefinition, TypeKind, luals_field_name};
+
+/// Renders the definitions as `---@class`/`---@alias` annotations for the Lua language server.
+pub(crate) fn render(definitions: &[TypeDefinition]) -> String {
+    let mut output = String::new();
+
+    for definition in definitions {
+        output.push_str(&render_definition(definition));
+        output.push('\n');
+    }
+
+    output
+}
+
+fn render_definition(definition: &TypeDefinition) -> String {
+    match &definition.kind {
+        TypeKind::Class(class) => {
+            let mut class_def = String::new();
+            if let Some(doc) = &definition.docs {
+                class_def.push_str(&format!("--- {}\n", doc));
+            }
+            class_def.push_str(&render_class(&definition.name, class));
+            class_def
+        }
+        TypeKind::Alias {
+            variants,
+            variant_classes,
+        } => {
+            let variants: Vec<String> = variants.iter().map(ToString::to_string).collect();
+            let mut alias_def = format!("---@alias {} {}\n", definition.name, variants.join("|"));
+            for variant_class in variant_classes {
+                alias_def.push('\n');
+                alias_def.push_str(&render_definition(variant_class));
+            }
+            alias_def
+        }
+    }
+}
+
+fn render_class(class_name: &str, class: &ClassDefinition) -> String {
+    let mut class_def = if class.parents.is_empty() {
+        format!("---@class {}\n", class_name)
+    } else {
+        format!("---@class {} : {}\n", class_name, class.parents.join(", "))
+    };
+
+    for field in &class.fields {
+        if let Some(doc) = &field.docs {
+            class_def.push_str(&format!("--- {}\n", doc));
+        }
+
+        let key = match &field.key {
+            FieldKey::Name(name) => luals_field_name(name),
+            FieldKey::Index(key_type) => format!("[{}]", key_type),
+        };
+        class_def.push_str(&format!("---@field {} {}\n", key, field.effective_type()));
+    }
+
+    class_def
+}
@@ -0,0 +1,217 @@
+use super::model::{
+    ClassDefinition, FieldKey, LuaType, TypeDefinition, TypeKind, luals_field_name,
+};
+
+/// Renders the definitions as exported Luau type aliases.
+pub(crate) fn render(definitions: &[TypeDefinition]) -> String {
+    let mut output = String::new();
+
+    for definition in definitions {
+        output.push_str(&render_definition(definition));
+        output.push('\n');
+    }
+
+    output
+}
+
+fn render_definition(definition: &TypeDefinition) -> String {
+    let mut type_def = String::new();
+    if let Some(doc) = &definition.docs {
+        type_def.push_str(&format!("-- {}\n", doc.trim()));
+    }
+
+    let name = luau_type_name(&definition.name);
+    match &definition.kind {
+        TypeKind::Class(class) => {
+            type_def.push_str(&format!("export type {} = {}\n", name, render_class(class)));
+        }
+        TypeKind::Alias {
+            variants,
+            variant_classes,
+        } => {
+            let variants: Vec<String> = variants.iter().map(luau_type).collect();
+            type_def.push_str(&format!(
+                "export type {} = {}\n",
+                name,
+                variants.join(" | ")
+            ));
+            for variant_class in variant_classes {
+                type_def.push('\n');
+                type_def.push_str(&render_definition(variant_class));
+            }
+        }
+    }
+
+    type_def
+}
+
+/// Flattened fields are intersected with their parents, e.g. `Common & { ... }`.
+fn render_class(class: &ClassDefinition) -> String {
+    let mut table = String::from("{\n");
+    for field in &class.fields {
+        if let Some(doc) = &field.docs {
+            table.push_str(&format!("\t-- {}\n", doc.trim()));
+        }
+
+        let key = match &field.key {
+            FieldKey::Name(name) => luals_field_name(name),
+            FieldKey::Index(key_type) => format!("[{}]", luau_type(key_type)),
+        };
+        table.push_str(&format!(
+            "\t{}: {},\n",
+            key,
+            luau_type(&field.effective_type())
+        ));
+    }
+    table.push('}');
+
+    let mut types: Vec<String> = class
+        .parents
+        .iter()
+        .map(|parent| luau_type_name(parent))
+        .collect();
+    if !class.fields.is_empty() || types.is_empty() {
+        types.push(table);
+    }
+    types.join(" & ")
+}
+
+/// Luau has no nested type names, so `Enum.Variant` becomes `EnumVariant`.
+fn luau_type_name(name: &str) -> String {
+    name.replace('.', "")
+}
+
+fn luau_type(lua_type: &LuaType) -> String {
+    match lua_type {
+        LuaType::Any => "any".to_string(),
+        LuaType::Unknown => "unknown".to_string(),
+        LuaType::Nil => "nil".to_string(),
+        LuaType::Boolean => "boolean".to_string(),
+        LuaType::Integer | LuaType::Number => "number".to_string(),
+        LuaType::String => "string".to_string(),
+        LuaType::Literal(value) => format!("\"{}\"", value),
+        LuaType::Named(name) => luau_type_name(name),
+        LuaType::Array(inner) => format!("{{{}}}", luau_type(inner)),
+        LuaType::Map(key, value) => format!("{{[{}]: {}}}", luau_type(key), luau_type(value)),
+        // tables with mixed element types can only be described as arrays of a union
+        LuaType::Tuple(elements) => {
+            let mut types: Vec<String> = vec![];
+            for element in elements {
+                let element = luau_type(element);
+                if !types.contains(&element) {
+                    types.push(element);
+                }
+            }
+            format!("{{{}}}", types.join(" | "))
+        }
+        LuaType::Table(fields) => {
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|(name, field_type)| {
+                    format!("{}: {}", luals_field_name(name), luau_type(field_type))
+                })
+                .collect();
+            format!("{{ {} }}", fields.join(", "))
+        }
+        LuaType::Function { params, returns } => {
+            let params: Vec<String> = params
+                .iter()
+                .map(|(name, param_type)| format!("{}: {}", name, luau_type(param_type)))
+                .collect();
+            format!("({}) -> {}", params.join(", "), luau_type(returns))
+        }
+        LuaType::Union(members) => {
+            let members: Vec<String> = members.iter().map(parenthesized_luau_type).collect();
+            members.join(" | ")
+        }
+        LuaType::Optional(inner) => match inner.as_ref() {
+            LuaType::Any | LuaType::Unknown | LuaType::Nil => luau_type(inner),
+            LuaType::Optional(_) => luau_type(inner),
+            _ => format!("{}?", parenthesized_luau_type(inner)),
+        },
+    }
+}
+
+/// Functions and unions need parentheses when they are part of a larger type.
+fn parenthesized_luau_type(lua_type: &LuaType) -> String {
+    match lua_type {
+        LuaType::Function { .. } | LuaType::Union(_) => format!("({})", luau_type(lua_type)),
+        _ => luau_type(lua_type),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua_type_gen::module_tree::ModuleTree;
+    use crate::lua_type_gen::type_definitions;
+    use insta::assert_snapshot;
+    use std::path::Path;
+
+    fn generate_luau_from_str(content: &str) -> String {
+        let items = ModuleTree::from_source(Path::new("lib.rs"), content).unwrap();
+
+        render(&type_definitions(&items, true).unwrap())
+    }
+
+    #[test]
+    fn test_luau_enums_and_flatten() {
+        let luau = generate_luau_from_str(
+            r###"
+#[derive(Deserialize)]
+pub struct Window {
+    #[serde(rename = "window-layout")]
+    pub layout: Option<Layout>,
+    pub panes: Vec<Pane>,
+    pub size: (u32, u32),
+    #[serde(flatten)]
+    pub common: Common,
+}
+
+#[derive(Deserialize)]
+pub struct Common {
+    pub env: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Layout {
+    Tiled,
+    MainVertical,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+pub enum Pane {
+    Empty,
+    Shell { command: String },
+}
+        "###,
+        );
+
+        assert_snapshot!(luau, @r###"
+        export type Window = Common & {
+        	["window-layout"]: Layout?,
+        	panes: {Pane},
+        	size: {number},
+        }
+
+        export type Common = {
+        	env: {[string]: string},
+        }
+
+        export type Layout = "tiled" | "main-vertical"
+
+        export type Pane = PaneEmpty | PaneShell
+
+        export type PaneEmpty = {
+        	type: "Empty",
+        }
+
+        export type PaneShell = {
+        	type: "Shell",
+        	command: string,
+        }
+        "###);
+    }
+}
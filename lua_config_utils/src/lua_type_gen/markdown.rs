@@ -0,0 +1,376 @@
+use super::model::{
+    ClassDefinition, FieldDefault, FieldDefinition, FieldKey, LuaType, TypeDefinition, TypeKind,
+    luals_field_name,
+};
+
+/// How deep examples for nested aliases are expanded before falling back to `{ ... }`.
+const MAX_EXAMPLE_DEPTH: usize = 4;
+
+/// Renders the definitions as a Markdown configuration reference, with a table describing the
+/// fields of each class.
+pub(crate) fn render(definitions: &[TypeDefinition]) -> String {
+    let mut output = String::from("# Configuration reference\n");
+
+    for definition in definitions {
+        output.push('\n');
+        output.push_str(&render_definition(definition, definitions, 2));
+    }
+
+    output
+}
+
+fn render_definition(
+    definition: &TypeDefinition,
+    definitions: &[TypeDefinition],
+    heading_level: usize,
+) -> String {
+    let mut section = format!("{} {}\n", "#".repeat(heading_level), definition.name);
+
+    if let Some(docs) = &definition.docs {
+        section.push_str(&format!("\n{}\n", normalize_docs(docs)));
+    }
+
+    match &definition.kind {
+        TypeKind::Class(class) => section.push_str(&render_class(class, definitions)),
+        TypeKind::Alias {
+            variants,
+            variant_classes,
+        } => {
+            section.push_str("\nOne of:\n\n");
+            for variant in variants {
+                section.push_str(&format!("- {}\n", type_reference(variant, definitions)));
+            }
+            for variant_class in variant_classes {
+                section.push('\n');
+                section.push_str(&render_definition(
+                    variant_class,
+                    definitions,
+                    heading_level + 1,
+                ));
+            }
+        }
+    }
+
+    section
+}
+
+fn render_class(class: &ClassDefinition, definitions: &[TypeDefinition]) -> String {
+    let mut section = String::new();
+
+    if !class.parents.is_empty() {
+        let parents: Vec<String> = class
+            .parents
+            .iter()
+            .map(|parent| format!("[`{}`](#{})", parent, anchor(parent)))
+            .collect();
+        section.push_str(&format!(
+            "\nIncludes all of the fields of {}.\n",
+            parents.join(", ")
+        ));
+    }
+
+    if class.fields.is_empty() {
+        return section;
+    }
+
+    section.push_str("\n| Field | Type | Required | Default | Description | Example |\n");
+    section.push_str("| --- | --- | --- | --- | --- | --- |\n");
+
+    for field in &class.fields {
+        let key = match &field.key {
+            FieldKey::Name(name) => luals_field_name(name),
+            FieldKey::Index(key_type) => format!("[{}]", key_type),
+        };
+        let example_key = match &field.key {
+            FieldKey::Index(_) => "[\"key\"]".to_string(),
+            FieldKey::Name(_) => key.clone(),
+        };
+
+        let cells = [
+            format!("`{}`", key),
+            type_reference(&field.lua_type, definitions),
+            if field.is_required() {
+                "required".to_string()
+            } else {
+                "optional".to_string()
+            },
+            default_value(field),
+            field
+                .docs
+                .as_deref()
+                .map(normalize_docs)
+                .unwrap_or_default(),
+            format!(
+                "`{} = {}`",
+                example_key,
+                example_value(&field.lua_type, definitions, 0)
+            ),
+        ];
+        let cells: Vec<String> = cells.iter().map(|cell| table_cell(cell)).collect();
+
+        section.push_str(&format!("| {} |\n", cells.join(" | ")));
+    }
+
+    section
+}
+
+/// The type, linking to its section when it is one of the generated definitions.
+fn type_reference(lua_type: &LuaType, definitions: &[TypeDefinition]) -> String {
+    match referenced_name(lua_type) {
+        Some(name) if find_definition(name, definitions).is_some() => {
+            format!("[`{}`](#{})", lua_type, anchor(name))
+        }
+        _ => format!("`{}`", lua_type),
+    }
+}
+
+/// The class or alias that a (possibly optional, or list of) type refers to.
+fn referenced_name(lua_type: &LuaType) -> Option<&str> {
+    match lua_type {
+        LuaType::Named(name) => Some(name),
+        LuaType::Optional(inner) | LuaType::Array(inner) => referenced_name(inner),
+        LuaType::Union(members) => members.iter().find_map(referenced_name),
+        _ => None,
+    }
+}
+
+fn default_value(field: &FieldDefinition) -> String {
+    match &field.default {
+        Some(FieldDefault::Function(function)) => format!("`{}()`", function),
+        Some(FieldDefault::Default) => match &field.lua_type {
+            LuaType::Boolean => "`false`".to_string(),
+            LuaType::Integer => "`0`".to_string(),
+            LuaType::Number => "`0.0`".to_string(),
+            LuaType::String => "`\"\"`".to_string(),
+            LuaType::Array(_) | LuaType::Map(_, _) => "`{}`".to_string(),
+            LuaType::Optional(_) | LuaType::Nil | LuaType::Any => "`nil`".to_string(),
+            _ => "*default*".to_string(),
+        },
+        Some(FieldDefault::Container) => "*default*".to_string(),
+        None if field.lua_type.is_optional() => "`nil`".to_string(),
+        None => String::new(),
+    }
+}
+
+fn example_value(lua_type: &LuaType, definitions: &[TypeDefinition], depth: usize) -> String {
+    match lua_type {
+        LuaType::Any | LuaType::Unknown | LuaType::String => "\"...\"".to_string(),
+        LuaType::Nil => "nil".to_string(),
+        LuaType::Boolean => "true".to_string(),
+        LuaType::Integer => "1".to_string(),
+        LuaType::Number => "1.5".to_string(),
+        LuaType::Literal(value) => format!("\"{}\"", value),
+        LuaType::Named(name) => match find_definition(name, definitions) {
+            Some(TypeDefinition {
+                kind: TypeKind::Alias { variants, .. },
+                ..
+            }) if depth < MAX_EXAMPLE_DEPTH => match variants.first() {
+                Some(variant) => example_value(variant, definitions, depth + 1),
+                None => "nil".to_string(),
+            },
+            _ => "{ ... }".to_string(),
+        },
+        LuaType::Array(inner) => format!("{{ {} }}", example_value(inner, definitions, depth + 1)),
+        LuaType::Map(key, value) => {
+            let value = example_value(value, definitions, depth + 1);
+            match key.as_ref() {
+                LuaType::String => format!("{{ key = {} }}", value),
+                key => format!(
+                    "{{ [{}] = {} }}",
+                    example_value(key, definitions, depth + 1),
+                    value
+                ),
+            }
+        }
+        LuaType::Tuple(elements) => {
+            let elements: Vec<String> = elements
+                .iter()
+                .map(|element| example_value(element, definitions, depth + 1))
+                .collect();
+            format!("{{ {} }}", elements.join(", "))
+        }
+        LuaType::Table(_) => "{ ... }".to_string(),
+        LuaType::Function { params, returns } => {
+            let params: Vec<&str> = params.iter().map(|(name, _)| name.as_str()).collect();
+            format!(
+                "function({}) return {} end",
+                params.join(", "),
+                example_value(returns, definitions, depth + 1)
+            )
+        }
+        LuaType::Union(members) => match members.first() {
+            Some(member) => example_value(member, definitions, depth),
+            None => "nil".to_string(),
+        },
+        LuaType::Optional(inner) => example_value(inner, definitions, depth),
+    }
+}
+
+fn find_definition<'a>(
+    name: &str,
+    definitions: &'a [TypeDefinition],
+) -> Option<&'a TypeDefinition> {
+    definitions.iter().find_map(|definition| {
+        if definition.name == name {
+            return Some(definition);
+        }
+
+        match &definition.kind {
+            TypeKind::Alias {
+                variant_classes, ..
+            } => find_definition(name, variant_classes),
+            TypeKind::Class(_) => None,
+        }
+    })
+}
+
+/// Doc comments keep the leading space of every `///` line, and wrap at arbitrary points.
+fn normalize_docs(docs: &str) -> String {
+    docs.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Pipes end a table cell, even inside of code spans.
+fn table_cell(content: &str) -> String {
+    content.replace('|', "\\|")
+}
+
+/// The anchor that GitHub generates for a heading.
+fn anchor(heading: &str) -> String {
+    heading
+        .to_lowercase()
+        .chars()
+        .filter(|ch| ch.is_alphanumeric() || *ch == '-' || *ch == '_' || *ch == ' ')
+        .map(|ch| if ch == ' ' { '-' } else { ch })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua_type_gen::module_tree::ModuleTree;
+    use crate::lua_type_gen::type_definitions;
+    use insta::assert_snapshot;
+    use std::path::Path;
+
+    fn generate_markdown_from_str(content: &str) -> String {
+        let items = ModuleTree::from_source(Path::new("lib.rs"), content).unwrap();
+
+        render(&type_definitions(&items, true).unwrap())
+    }
+
+    #[test]
+    fn test_markdown_reference() {
+        let markdown = generate_markdown_from_str(
+            r###"
+/// Configuration for a tmux window.
+#[derive(Deserialize)]
+pub struct Window {
+    /// Name of the window.
+    pub name: String,
+    /// Whether the window should be
+    /// focused | selected.
+    #[serde(default)]
+    pub focus: bool,
+    #[serde(default = "default_layout", rename = "window-layout")]
+    pub layout: Layout,
+    /// Optional command to run in the window.
+    pub command: Option<Command>,
+    pub panes: Vec<Pane>,
+    #[serde(flatten)]
+    pub common: Common,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Common {
+    pub env: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Layout {
+    Tiled,
+    MainVertical,
+}
+
+/// Command to be executed in a tmux window.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Command {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+pub enum Pane {
+    Empty,
+    /// Runs a shell command
+    Shell { command: Command },
+}
+        "###,
+        );
+
+        assert_snapshot!(markdown, @r###"
+        # Configuration reference
+
+        ## Window
+
+        Configuration for a tmux window.
+
+        Includes all of the fields of [`Common`](#common).
+
+        | Field | Type | Required | Default | Description | Example |
+        | --- | --- | --- | --- | --- | --- |
+        | `name` | `string` | required |  | Name of the window. | `name = "..."` |
+        | `focus` | `boolean` | optional | `false` | Whether the window should be focused \| selected. | `focus = true` |
+        | `["window-layout"]` | [`Layout`](#layout) | optional | `default_layout()` |  | `["window-layout"] = "tiled"` |
+        | `command` | [`Command\|nil`](#command) | optional | `nil` | Optional command to run in the window. | `command = "..."` |
+        | `panes` | [`Pane[]`](#pane) | required |  |  | `panes = { { ... } }` |
+
+        ## Common
+
+        | Field | Type | Required | Default | Description | Example |
+        | --- | --- | --- | --- | --- | --- |
+        | `env` | `table<string, string>` | optional | *default* |  | `env = { key = "..." }` |
+
+        ## Layout
+
+        One of:
+
+        - `"tiled"`
+        - `"main-vertical"`
+
+        ## Command
+
+        Command to be executed in a tmux window.
+
+        One of:
+
+        - `string`
+        - `string[]`
+
+        ## Pane
+
+        One of:
+
+        - [`Pane.Empty`](#paneempty)
+        - [`Pane.Shell`](#paneshell)
+
+        ### Pane.Empty
+
+        | Field | Type | Required | Default | Description | Example |
+        | --- | --- | --- | --- | --- | --- |
+        | `type` | `"Empty"` | required |  |  | `type = "Empty"` |
+
+        ### Pane.Shell
+
+        Runs a shell command
+
+        | Field | Type | Required | Default | Description | Example |
+        | --- | --- | --- | --- | --- | --- |
+        | `type` | `"Shell"` | required |  |  | `type = "Shell"` |
+        | `command` | [`Command`](#command) | required |  |  | `command = "..."` |
+        "###);
+    }
+}
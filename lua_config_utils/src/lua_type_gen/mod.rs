@@ -1,39 +1,349 @@
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::Path;
-use syn::{Attribute, File, Item, Token, Type, TypePath, punctuated::Punctuated};
+use std::path::{Path, PathBuf};
+use syn::visit::Visit;
+use syn::{Attribute, Item, Token, Type, TypePath, punctuated::Punctuated};
 
-pub fn process_file<S: AsRef<Path>>(input_path: S, output_path: S) {
-    let lua_types = generate_lua_types_from_file(input_path);
-    fs::write(output_path, &lua_types).unwrap();
-    println!("{}", lua_types);
+use anyhow::{Context, Result, bail};
+
+mod luals;
+mod luau;
+mod markdown;
+mod model;
+mod module_tree;
+pub mod registry;
+mod serde_attrs;
+mod teal;
+
+use model::{
+    ClassDefinition, FieldDefault, FieldDefinition, FieldKey, LuaType, TypeDefinition, TypeKind,
+};
+use module_tree::{ModuleTree, SourceItem};
+use registry::LuaTypeRegistry;
+use serde_attrs::{ContainerAttrs, EnumTagging, FieldAttrs, VariantAttrs};
+
+/// The formats that the deserializable types can be rendered to.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    /// Annotations for the Lua language server
+    #[value(name = "luals")]
+    LuaLs,
+    /// A Teal declaration file (`.d.tl`)
+    Teal,
+    /// Luau type aliases
+    Luau,
+    /// A Markdown configuration reference
+    Markdown,
+}
+
+/// The arguments shared by the binaries that generate types, e.g. `generate-types`.
+#[derive(clap::Args, Debug)]
+pub struct GenerateArgs {
+    /// Sets the input files (the rust files that contain the structs, usually a crate's `lib.rs`).
+    /// Modules declared with `mod foo;` are followed automatically. When omitted, the types
+    /// registered with `#[derive(LuaType)]` are used instead.
+    #[arg(short, long, value_name = "FILE")]
+    pub input: Vec<PathBuf>,
+
+    /// Sets the output file path
+    #[arg(short, long, value_name = "FILE")]
+    pub output: PathBuf,
+
+    /// Instead of writing the output file, check that it is up to date (printing a diff and
+    /// exiting with an error when it is not)
+    #[arg(long)]
+    pub check: bool,
+}
+
+/// Generates (or checks) the output described by `args`, from either the `--input` files or the
+/// types in `registry`.
+pub fn generate(
+    args: &GenerateArgs,
+    registry: Option<&LuaTypeRegistry>,
+    format: OutputFormat,
+) -> Result<()> {
+    let generated = match (args.input.is_empty(), registry) {
+        (false, _) => generate_from_files(&args.input, format)?,
+        (true, Some(registry)) => registry.generate(format)?,
+        (true, None) => bail!("At least one `--input` file is required"),
+    };
+
+    if args.check {
+        if let Some(diff) = diff_output(&args.output, &generated)? {
+            print!("{}", diff);
+            bail!(
+                "{} is out of date, run without `--check` to regenerate it",
+                args.output.display()
+            );
+        }
+
+        return Ok(());
+    }
+
+    write_output(&args.output, &generated)
+}
+
+/// Generates the types for every deserializable type reachable from `input_paths` (following
+/// `mod` declarations), and writes them as a single file to `output_path`.
+pub fn process_files<P: AsRef<Path>>(
+    input_paths: &[P],
+    output_path: impl AsRef<Path>,
+    format: OutputFormat,
+) -> Result<()> {
+    let generated = generate_from_files(input_paths, format)?;
+
+    write_output(output_path.as_ref(), &generated)
+}
+
+/// Compares the output generated from `input_paths` with the existing contents of
+/// `output_path`, returning a unified diff when the file is stale (or missing).
+pub fn check_files<P: AsRef<Path>>(
+    input_paths: &[P],
+    output_path: impl AsRef<Path>,
+    format: OutputFormat,
+) -> Result<Option<String>> {
+    let generated = generate_from_files(input_paths, format)?;
+
+    diff_output(output_path.as_ref(), &generated)
+}
+
+fn write_output(output_path: &Path, generated: &str) -> Result<()> {
+    fs::write(output_path, generated)
+        .with_context(|| format!("Failed to write file: {}", output_path.display()))?;
+    println!("{}", generated);
+
+    Ok(())
+}
+
+fn diff_output(output_path: &Path, generated: &str) -> Result<Option<String>> {
+    let existing = match fs::read_to_string(output_path) {
+        Ok(existing) => existing,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => {
+            return Err(err)
+                .with_context(|| format!("Failed to read file: {}", output_path.display()));
+        }
+    };
+
+    if existing == generated {
+        return Ok(None);
+    }
+
+    let output_name = output_path.display().to_string();
+    let diff = similar::TextDiff::from_lines(existing.as_str(), generated)
+        .unified_diff()
+        .header(&output_name, &format!("{} (generated)", output_name))
+        .to_string();
+
+    Ok(Some(diff))
+}
+
+fn generate_from_files<P: AsRef<Path>>(file_paths: &[P], format: OutputFormat) -> Result<String> {
+    let items = ModuleTree::from_files(file_paths)?;
+    let definitions = type_definitions(&items, true)?;
+
+    Ok(render(&definitions, format))
+}
+
+fn render(definitions: &[TypeDefinition], format: OutputFormat) -> String {
+    match format {
+        OutputFormat::LuaLs => luals::render(definitions),
+        OutputFormat::Teal => teal::render(definitions),
+        OutputFormat::Luau => luau::render(definitions),
+        OutputFormat::Markdown => markdown::render(definitions),
+    }
 }
 
-fn generate_lua_types_from_file<S: AsRef<Path>>(file_path: S) -> String {
-    let content = fs::read_to_string(file_path).expect("Unable to read file");
-    let syntax: File = syn::parse_file(&content).expect("Unable to parse file");
+/// Builds the definitions for the structs and enums in `items`. When `require_deserialize` is set
+/// only the types that `#[derive(Deserialize)]` (or implement it by hand) are included, and
+/// everything else is assumed to be an implementation detail of the module.
+fn type_definitions(
+    items: &[SourceItem],
+    require_deserialize: bool,
+) -> Result<Vec<TypeDefinition>> {
+    let input_types = find_deserializer_input_types(items);
+    let implements_deserialize = find_deserialize_impls(items);
+    let is_deserializable = |ident: &syn::Ident, attrs: &[Attribute]| {
+        !require_deserialize
+            || has_derive_deserialize(attrs)
+            || implements_deserialize.contains(&ident.to_string())
+    };
+
+    // every Lua class lives in a single namespace, so the same name in two modules would collide
+    let mut defined_in: HashMap<String, &Path> = HashMap::new();
+    let mut definitions = vec![];
+
+    for SourceItem {
+        source,
+        module_path,
+        item,
+    } in items
+    {
+        let deserializers = Deserializers {
+            input_types: &input_types,
+            module_path,
+        };
+        let definition = match item {
+            Item::Struct(item_struct)
+                if is_deserializable(&item_struct.ident, &item_struct.attrs) =>
+            {
+                struct_definition(item_struct, &deserializers)
+            }
+            Item::Enum(item_enum) if is_deserializable(&item_enum.ident, &item_enum.attrs) => {
+                enum_definition(item_enum, &deserializers)
+            }
+            _ => continue,
+        };
+
+        if let Some(previous_source) = defined_in.insert(definition.name.clone(), source) {
+            bail!(
+                "Duplicate type name `{}` (defined in both {} and {})",
+                definition.name,
+                previous_source.display(),
+                source.display()
+            );
+        }
+
+        definitions.push(definition);
+    }
+
+    Ok(definitions)
+}
 
-    let mut output = String::new();
+/// Maps the paths (from the crate root, e.g. `parse::parse_duration`) of functions used with
+/// `#[serde(deserialize_with = "...")]` (that are defined within the scanned sources) to the type
+/// they actually deserialize from, e.g. `Option<String>` for a function whose body calls
+/// `Option::<String>::deserialize(deserializer)`.
+fn find_deserializer_input_types(items: &[SourceItem]) -> HashMap<String, Type> {
+    struct DeserializeCallFinder {
+        found: Option<Type>,
+    }
 
-    for item in syntax.items {
-        match item {
-            Item::Struct(item_struct) => {
-                if has_derive_deserialize(&item_struct.attrs) {
-                    output.push_str(&generate_lua_types_for_struct(&item_struct));
-                    output.push('\n');
+    impl<'ast> Visit<'ast> for DeserializeCallFinder {
+        fn visit_expr_call(&mut self, call: &'ast syn::ExprCall) {
+            if self.found.is_none() {
+                if let syn::Expr::Path(expr_path) = call.func.as_ref() {
+                    self.found = deserialize_call_type(expr_path);
                 }
             }
-            _ => {
-                if let Item::Enum(item_enum) = item {
-                    if has_derive_deserialize(&item_enum.attrs) {
-                        output.push_str(&generate_lua_enum_alias(&item_enum));
-                        output.push('\n');
-                    }
+            syn::visit::visit_expr_call(self, call);
+        }
+    }
+
+    let mut deserializers = HashMap::new();
+    for SourceItem {
+        module_path, item, ..
+    } in items
+    {
+        if let Item::Fn(item_fn) = item {
+            let mut finder = DeserializeCallFinder { found: None };
+            finder.visit_block(&item_fn.block);
+            if let Some(ty) = finder.found {
+                let path = [module_path.as_slice(), &[item_fn.sig.ident.to_string()]].concat();
+                deserializers.insert(path.join("::"), ty);
+            }
+        }
+    }
+
+    deserializers
+}
+
+/// The types that `deserialize_with` functions deserialize from (see
+/// [`find_deserializer_input_types`]), as seen from the module of the type being defined.
+struct Deserializers<'a> {
+    input_types: &'a HashMap<String, Type>,
+    module_path: &'a [String],
+}
+
+impl Deserializers<'_> {
+    /// Resolves a function's path as written in `deserialize_with` (or `with = "module"`'s
+    /// `module::deserialize`) relative to the module, like Rust does, falling back to the crate
+    /// root for paths that were imported.
+    fn input_type(&self, path: &str) -> Option<&Type> {
+        let mut module_path: Vec<&str> = self.module_path.iter().map(String::as_str).collect();
+        let mut segments = path.split("::").peekable();
+
+        match segments.peek() {
+            Some(&"crate") => {
+                segments.next();
+                module_path.clear();
+            }
+            Some(&"self") => {
+                segments.next();
+            }
+            Some(&"super") => {
+                while segments.next_if_eq(&"super").is_some() {
+                    module_path.pop();
                 }
             }
+            _ => {
+                let relative = [module_path, segments.collect()].concat().join("::");
+                return self
+                    .input_types
+                    .get(&relative)
+                    .or_else(|| self.input_types.get(path));
+            }
+        }
+
+        let absolute = [module_path, segments.collect()].concat().join("::");
+        self.input_types.get(&absolute)
+    }
+}
+
+/// Returns `T` for calls like `T::deserialize(..)`, `T::<U>::deserialize(..)` or
+/// `<T>::deserialize(..)`.
+fn deserialize_call_type(expr_path: &syn::ExprPath) -> Option<Type> {
+    let segments = &expr_path.path.segments;
+    if segments.last()?.ident != "deserialize" {
+        return None;
+    }
+
+    if let Some(qself) = &expr_path.qself {
+        return Some((*qself.ty).clone());
+    }
+
+    if segments.len() < 2 {
+        return None;
+    }
+
+    let mut path = expr_path.path.clone();
+    path.segments = segments.iter().take(segments.len() - 1).cloned().collect();
+
+    // turbofish arguments (`Option::<String>`) are equivalent to regular generic arguments
+    for segment in path.segments.iter_mut() {
+        if let syn::PathArguments::AngleBracketed(args) = &mut segment.arguments {
+            args.colon2_token = None;
         }
     }
 
-    output
+    Some(Type::Path(TypePath { qself: None, path }))
+}
+
+/// The names of the types with a hand-written `impl Deserialize for ...`, which are as
+/// deserializable as the ones that derive it.
+fn find_deserialize_impls(items: &[SourceItem]) -> HashSet<String> {
+    items
+        .iter()
+        .filter_map(|SourceItem { item, .. }| match item {
+            Item::Impl(item_impl) => Some(item_impl),
+            _ => None,
+        })
+        .filter(|item_impl| {
+            item_impl.trait_.as_ref().is_some_and(|(_, path, _)| {
+                path.segments
+                    .last()
+                    .is_some_and(|segment| segment.ident == "Deserialize")
+            })
+        })
+        .filter_map(|item_impl| match &*item_impl.self_ty {
+            Type::Path(type_path) => type_path
+                .path
+                .segments
+                .last()
+                .map(|segment| segment.ident.to_string()),
+            _ => None,
+        })
+        .collect()
 }
 
 fn has_derive_deserialize(attrs: &[Attribute]) -> bool {
@@ -56,29 +366,108 @@ fn has_derive_deserialize(attrs: &[Attribute]) -> bool {
     false
 }
 
-fn generate_lua_types_for_struct(item_struct: &syn::ItemStruct) -> String {
-    let struct_name = &item_struct.ident.to_string();
-    let mut lua_type_def = String::new();
+fn struct_definition(
+    item_struct: &syn::ItemStruct,
+    deserializers: &Deserializers,
+) -> TypeDefinition {
+    let container_attrs = ContainerAttrs::from_attrs(&item_struct.attrs);
+
+    let class = match &item_struct.fields {
+        syn::Fields::Named(fields) => class_definition(
+            fields,
+            container_attrs.rename_all,
+            container_attrs.default,
+            deserializers,
+        ),
+        _ => ClassDefinition::default(),
+    };
 
-    if let Some(doc) = extract_docs(&item_struct.attrs) {
-        lua_type_def.push_str(&format!("--- {}\n", doc));
+    TypeDefinition {
+        name: item_struct.ident.to_string(),
+        docs: extract_docs(&item_struct.attrs),
+        kind: TypeKind::Class(class),
     }
+}
 
-    lua_type_def.push_str(&format!("---@class {}\n", struct_name));
+fn class_definition(
+    fields: &syn::FieldsNamed,
+    rename_all: Option<serde_attrs::RenameRule>,
+    container_default: bool,
+    deserializers: &Deserializers,
+) -> ClassDefinition {
+    let mut class = ClassDefinition::default();
 
-    if let syn::Fields::Named(fields) = &item_struct.fields {
-        for field in fields.named.iter() {
-            if let Some(field_doc) = extract_docs(&field.attrs) {
-                lua_type_def.push_str(&format!("--- {}\n", field_doc));
-            }
+    for field in fields.named.iter() {
+        let field_attrs = FieldAttrs::from_attrs(&field.attrs);
+
+        if field_attrs.skip {
+            continue;
+        }
 
-            let field_name = field.ident.as_ref().unwrap().to_string();
-            let lua_type = get_lua_type(&field.ty);
-            lua_type_def.push_str(&format!("---@field {} {}\n", field_name, lua_type));
+        if field_attrs.flatten {
+            match flattened_map_value_type(&field.ty) {
+                // flattened maps collect every key that isn't otherwise a field
+                Some(value_type) => class.fields.push(FieldDefinition {
+                    key: FieldKey::Index(LuaType::String),
+                    lua_type: get_lua_type(&value_type),
+                    docs: extract_docs(&field.attrs),
+                    default: None,
+                }),
+                // flattened structs contribute their fields, which is how inheritance works
+                None => class
+                    .parents
+                    .push(get_lua_type(&field.ty).strip_nil().to_string()),
+            }
+            continue;
         }
+
+        let field_name = field.ident.as_ref().unwrap().to_string();
+        let field_name = match (&field_attrs.rename, rename_all) {
+            (Some(rename), _) => rename.clone(),
+            (None, Some(rule)) => rule.apply_to_field(&field_name),
+            (None, None) => field_name,
+        };
+
+        let lua_as = field_attrs
+            .lua_as
+            .as_deref()
+            .and_then(|lua_as| syn::parse_str::<Type>(lua_as).ok());
+        let lua_type = match (&lua_as, &field_attrs.deserialize_with) {
+            (Some(lua_as), _) => get_lua_type(lua_as),
+            (None, Some(deserialize_with)) => {
+                // fall back to the declared type when the function isn't in the scanned sources
+                let input_type = deserializers
+                    .input_type(deserialize_with)
+                    .unwrap_or(&field.ty);
+                get_lua_type(input_type)
+            }
+            (None, None) => get_lua_type(&field.ty),
+        };
+
+        class.fields.push(FieldDefinition {
+            key: FieldKey::Name(field_name),
+            lua_type,
+            docs: extract_docs(&field.attrs),
+            default: field_attrs
+                .default
+                .or(container_default.then_some(FieldDefault::Container)),
+        });
     }
 
-    lua_type_def
+    class
+}
+
+/// Returns the value type when `ty` is a (possibly optional) `HashMap`/`BTreeMap`.
+fn flattened_map_value_type(ty: &Type) -> Option<Type> {
+    let Type::Path(TypePath { path, .. }) = ty else {
+        return None;
+    };
+
+    match path.segments.last()?.ident.to_string().as_str() {
+        "HashMap" | "BTreeMap" => get_map_type_args(path).map(|(_, value_type)| value_type),
+        "Option" => flattened_map_value_type(&get_generic_type_arg(path)?),
+        _ => None,
+    }
 }
 
 fn extract_docs(attrs: &[Attribute]) -> Option<String> {
@@ -105,71 +494,114 @@ fn extract_docs(attrs: &[Attribute]) -> Option<String> {
     }
 }
 
-fn get_lua_type(ty: &Type) -> String {
-    if let Type::Path(TypePath { path, .. }) = ty {
-        let segment = &path.segments.last().unwrap().ident.to_string();
+fn get_lua_type(ty: &Type) -> LuaType {
+    match ty {
+        Type::Path(TypePath { path, .. }) => get_lua_type_for_path(path),
+        // `&str`, `&Path`, etc are the same as their owned counterparts
+        Type::Reference(reference) => get_lua_type(&reference.elem),
+        Type::Paren(paren) => get_lua_type(&paren.elem),
+        Type::Group(group) => get_lua_type(&group.elem),
+        Type::Array(array) => LuaType::Array(Box::new(get_lua_type(&array.elem))),
+        Type::Slice(slice) => LuaType::Array(Box::new(get_lua_type(&slice.elem))),
+        // `()` deserializes from an empty value
+        Type::Tuple(tuple) if tuple.elems.is_empty() => LuaType::Nil,
+        Type::Tuple(tuple) => LuaType::Tuple(tuple.elems.iter().map(get_lua_type).collect()),
+        _ => LuaType::Unknown, // Fallback type
+    }
+}
 
-        match segment.as_str() {
-            "String" => "string".to_string(),
-            "Option" => {
-                // Handle Option<T>
-                match get_generic_type_arg(path) {
-                    Some(inner_type) => {
-                        format!("{}|nil", get_lua_type(&inner_type))
-                    }
-                    _ => "any|nil".to_string(),
-                }
-            }
-            "Vec" => {
-                // Handle Vec<T>
-                match get_generic_type_arg(path) {
-                    Some(inner_type) => {
-                        format!("{}[]", get_lua_type(&inner_type))
-                    }
-                    _ => "any[]".to_string(),
-                }
+fn get_lua_type_for_path(path: &syn::Path) -> LuaType {
+    let segment = &path.segments.last().unwrap().ident.to_string();
+
+    if is_dynamic_value(path) {
+        return LuaType::Any;
+    }
+
+    match segment.as_str() {
+        "String" | "str" | "char" | "PathBuf" | "Path" | "OsString" | "OsStr" => LuaType::String,
+        "bool" => LuaType::Boolean,
+        "i8" | "i16" | "i32" | "i64" | "i128" | "isize" | "u8" | "u16" | "u32" | "u64" | "u128"
+        | "usize" => LuaType::Integer,
+        _ if segment.starts_with("NonZero") => LuaType::Integer,
+        "f32" | "f64" => LuaType::Number,
+        "Option" => {
+            // Handle Option<T>
+            match get_generic_type_arg(path) {
+                Some(inner_type) => LuaType::Optional(Box::new(get_lua_type(&inner_type))),
+                _ => LuaType::Optional(Box::new(LuaType::Any)),
             }
-            "HashMap" => {
-                // Handle HashMap<K, V>
-                match get_map_type_args(path) {
-                    Some((key_type, value_type)) => {
-                        format!(
-                            "table<{}, {}>",
-                            get_lua_type(&key_type),
-                            get_lua_type(&value_type)
-                        )
-                    }
-                    _ => "table<any, any>".to_string(),
-                }
+        }
+        // Lazy values are either the value itself, or a function that returns it
+        "Lazy" => {
+            let inner = match get_generic_type_arg(path) {
+                Some(inner_type) => get_lua_type(&inner_type),
+                _ => LuaType::Any,
+            };
+            LuaType::Union(vec![
+                inner.clone(),
+                LuaType::Function {
+                    params: vec![(
+                        "ctx".to_string(),
+                        LuaType::Named("ConfigContext".to_string()),
+                    )],
+                    returns: Box::new(inner),
+                },
+            ])
+        }
+        // Smart pointers are transparent to serde
+        "Box" | "Arc" | "Rc" | "Cow" => match get_generic_type_arg(path) {
+            Some(inner_type) => get_lua_type(&inner_type),
+            _ => LuaType::Any,
+        },
+        "Vec" | "VecDeque" | "LinkedList" | "HashSet" | "BTreeSet" | "BinaryHeap" => {
+            // Handle Vec<T> and other sequences
+            match get_generic_type_arg(path) {
+                Some(inner_type) => LuaType::Array(Box::new(get_lua_type(&inner_type))),
+                _ => LuaType::Array(Box::new(LuaType::Any)),
             }
-            "BTreeMap" => {
-                // Handle BTreeMap<K, V>
-                match get_map_type_args(path) {
-                    Some((key_type, value_type)) => {
-                        format!(
-                            "table<{}, {}>",
-                            get_lua_type(&key_type),
-                            get_lua_type(&value_type)
-                        )
-                    }
-                    _ => "table<any, any>".to_string(),
-                }
+        }
+        "HashMap" | "BTreeMap" => {
+            // Handle HashMap<K, V> and BTreeMap<K, V>
+            match get_map_type_args(path) {
+                Some((key_type, value_type)) => LuaType::Map(
+                    Box::new(get_lua_type(&key_type)),
+                    Box::new(get_lua_type(&value_type)),
+                ),
+                _ => LuaType::Map(Box::new(LuaType::Any), Box::new(LuaType::Any)),
             }
-            "PathBuf" => "string".to_string(), // Treat PathBuf as a string
-            _ => segment.clone(),              // Fallback: use the Rust type name directly
         }
-    } else {
-        "unknown".to_string() // Fallback type
+        // Fallback: use the Rust type name directly (e.g. `config::Command`)
+        _ => LuaType::Named(segment.clone()),
+    }
+}
+
+/// Self describing values (e.g. `serde_json::Value`) accept anything. Only paths that name the
+/// crate are matched, since a bare `Value` may as well be a type of the config.
+fn is_dynamic_value(path: &syn::Path) -> bool {
+    let segments: Vec<String> = path
+        .segments
+        .iter()
+        .map(|segment| segment.ident.to_string())
+        .collect();
+
+    match segments.as_slice() {
+        [module, value] => {
+            value == "Value"
+                && ["serde_json", "toml", "serde_yaml", "mlua"].contains(&module.as_str())
+        }
+        _ => false,
     }
 }
 
-// Helper function to extract the generic type argument (for Option<T> and Vec<T>)
+// Helper function to extract the generic type argument (for Option<T> and Vec<T>), skipping any
+// lifetimes (e.g. `Cow<'a, str>`)
 fn get_generic_type_arg(path: &syn::Path) -> Option<Type> {
     if let Some(segment) = path.segments.last() {
         if let syn::PathArguments::AngleBracketed(args) = &segment.arguments {
-            if let Some(syn::GenericArgument::Type(ty)) = args.args.first() {
-                return Some(ty.clone());
-            }
+            return args.args.iter().find_map(|arg| match arg {
+                syn::GenericArgument::Type(ty) => Some(ty.clone()),
+                _ => None,
+            });
         }
     }
     None
@@ -195,82 +627,187 @@ fn get_map_type_args(path: &syn::Path) -> Option<(Type, Type)> {
     None
 }
 
-fn generate_lua_enum_alias(item_enum: &syn::ItemEnum) -> String {
-    let enum_name = &item_enum.ident.to_string();
-    let mut alias_definition = String::new();
+fn enum_definition(item_enum: &syn::ItemEnum, deserializers: &Deserializers) -> TypeDefinition {
+    let container_attrs = ContainerAttrs::from_attrs(&item_enum.attrs);
+    let enum_name = item_enum.ident.to_string();
+
+    let kind = if container_attrs.tagging == EnumTagging::Untagged {
+        untagged_enum_kind(item_enum, container_attrs.rename_all_fields, deserializers)
+    } else {
+        tagged_enum_kind(item_enum, &enum_name, &container_attrs, deserializers)
+    };
+
+    TypeDefinition {
+        name: enum_name,
+        docs: extract_docs(&item_enum.attrs),
+        kind,
+    }
+}
 
-    let mut variant_types = vec![];
+fn tagged_enum_kind(
+    item_enum: &syn::ItemEnum,
+    enum_name: &str,
+    container_attrs: &ContainerAttrs,
+    deserializers: &Deserializers,
+) -> TypeKind {
+    let mut variants = vec![];
+    let mut variant_classes = vec![];
 
     for variant in &item_enum.variants {
-        match &variant.fields {
-            syn::Fields::Unit => {
-                // Unit variant, detect underlying type if possible
-                todo!("Unit variants are not supported yet")
+        let variant_attrs = VariantAttrs::from_attrs(&variant.attrs);
+        if variant_attrs.skip {
+            continue;
+        }
+
+        let variant_ident = variant.ident.to_string();
+        let tag_value = match (&variant_attrs.rename, container_attrs.rename_all) {
+            (Some(rename), _) => rename.clone(),
+            (None, Some(rule)) => rule.apply_to_variant(&variant_ident),
+            (None, None) => variant_ident.clone(),
+        };
+
+        // externally tagged unit variants are plain strings, everything else is a table
+        if container_attrs.tagging == EnumTagging::External && variant.fields.is_empty() {
+            variants.push(LuaType::Literal(tag_value));
+            continue;
+        }
+
+        let class_name = format!("{}.{}", enum_name, variant_ident);
+        let field_rename_all = variant_attrs
+            .rename_all
+            .or(container_attrs.rename_all_fields);
+
+        let mut class = ClassDefinition::default();
+        match &container_attrs.tagging {
+            EnumTagging::External => {
+                let content_type =
+                    variant_content_type(&variant.fields, field_rename_all, deserializers);
+                class.fields.push(tag_field(&tag_value, content_type));
             }
-            syn::Fields::Unnamed(fields) => {
-                // Tuple variant (e.g., Variant2(String, i32))
-                if fields.unnamed.len() == 1 {
-                    // Single-field tuple variant
-                    let field_type = get_lua_type(&fields.unnamed[0].ty);
-                    variant_types.push(field_type);
-                } else {
-                    // Multi-field tuple variant, treat as a tuple
-                    let tuple_type = fields
-                        .unnamed
-                        .iter()
-                        .map(|field| get_lua_type(&field.ty))
-                        .collect::<Vec<_>>()
-                        .join(", ");
-                    variant_types.push(format!("({})", tuple_type));
+            EnumTagging::Internal { tag } => {
+                class
+                    .fields
+                    .push(tag_field(tag, LuaType::Literal(tag_value.clone())));
+                match &variant.fields {
+                    syn::Fields::Named(fields) => {
+                        let variant_class =
+                            class_definition(fields, field_rename_all, false, deserializers);
+                        class.parents = variant_class.parents;
+                        class.fields.extend(variant_class.fields);
+                    }
+                    // newtype variants contain a struct (or map) whose fields sit alongside the tag
+                    syn::Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+                        class
+                            .parents
+                            .push(get_lua_type(&fields.unnamed[0].ty).strip_nil().to_string());
+                    }
+                    // tuple variants can't be internally tagged, serde rejects them at compile time
+                    _ => {}
                 }
             }
-            syn::Fields::Named(fields) => {
-                // Struct variant (e.g., Variant3 { field1: String, field2: i32 })
-                let struct_fields = fields
-                    .named
-                    .iter()
-                    .map(|field| {
-                        let field_name = field.ident.as_ref().unwrap().to_string();
-                        let field_type = get_lua_type(&field.ty);
-                        format!("{}: {}", field_name, field_type)
-                    })
-                    .collect::<Vec<_>>()
-                    .join(", ");
-                variant_types.push(format!("{{ {} }}", struct_fields)); // Represent as a Lua table
+            EnumTagging::Adjacent { tag, content } => {
+                class
+                    .fields
+                    .push(tag_field(tag, LuaType::Literal(tag_value.clone())));
+                if !variant.fields.is_empty() {
+                    let content_type =
+                        variant_content_type(&variant.fields, field_rename_all, deserializers);
+                    class.fields.push(tag_field(content, content_type));
+                }
             }
+            EnumTagging::Untagged => unreachable!("untagged enums are handled separately"),
         }
+
+        variants.push(LuaType::Named(class_name.clone()));
+        variant_classes.push(TypeDefinition {
+            name: class_name,
+            docs: extract_docs(&variant.attrs),
+            kind: TypeKind::Class(class),
+        });
     }
 
-    // Deduplicate and sort the types
-    variant_types.sort();
-    variant_types.dedup();
+    TypeKind::Alias {
+        variants,
+        variant_classes,
+    }
+}
+
+fn tag_field(name: &str, lua_type: LuaType) -> FieldDefinition {
+    FieldDefinition {
+        key: FieldKey::Name(name.to_string()),
+        lua_type,
+        docs: None,
+        default: None,
+    }
+}
+
+/// The Lua type of a variant's content when it is nested under a key (externally and adjacently
+/// tagged enums).
+fn variant_content_type(
+    fields: &syn::Fields,
+    rename_all: Option<serde_attrs::RenameRule>,
+    deserializers: &Deserializers,
+) -> LuaType {
+    match fields {
+        syn::Fields::Unit => LuaType::Nil,
+        syn::Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+            get_lua_type(&fields.unnamed[0].ty)
+        }
+        syn::Fields::Unnamed(fields) => LuaType::Tuple(
+            fields
+                .unnamed
+                .iter()
+                .map(|field| get_lua_type(&field.ty))
+                .collect(),
+        ),
+        syn::Fields::Named(fields) => {
+            class_definition(fields, rename_all, false, deserializers).to_inline_table()
+        }
+    }
+}
+
+fn untagged_enum_kind(
+    item_enum: &syn::ItemEnum,
+    rename_all_fields: Option<serde_attrs::RenameRule>,
+    deserializers: &Deserializers,
+) -> TypeKind {
+    let mut variants = vec![];
 
-    // Join all the types into a single alias
-    alias_definition.push_str(&format!(
-        "---@alias {} {}\n",
-        enum_name,
-        variant_types.join("|")
-    ));
+    for variant in &item_enum.variants {
+        // untagged variants deserialize from their content alone (unit variants from an empty
+        // value), so they have the same shape as externally tagged content
+        variants.push(variant_content_type(
+            &variant.fields,
+            rename_all_fields,
+            deserializers,
+        ));
+    }
+
+    // Deduplicate and sort the types
+    variants.sort_by_key(ToString::to_string);
+    variants.dedup();
 
-    alias_definition
+    TypeKind::Alias {
+        variants,
+        variant_classes: vec![],
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use insta::assert_snapshot;
+    use std::collections::BTreeMap;
     use tempfile::tempdir;
 
-    #[test]
-    fn test_run_generates_lua_types() {
-        // Create a temporary directory
-        let temp_dir = tempdir().expect("Failed to create temp dir");
-        fs::create_dir_all(temp_dir.path().join("src")).unwrap();
+    fn generate_lua_types_from_str(content: &str) -> String {
+        let items = ModuleTree::from_source(Path::new("lib.rs"), content).unwrap();
 
-        let source_file = temp_dir.path().join("src/lib.rs");
-        fs::write(
-            &source_file,
-            r###"
+        luals::render(&type_definitions(&items, true).unwrap())
+    }
+
+    /// A copy of the config crate's types (at the time of writing).
+    const CONFIG_SOURCE: &str = r###"
 /// Configuration for the application.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Config {
@@ -350,15 +887,26 @@ pub struct Window {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub linked_crates: Option<Vec<String>>,
 }
-        "###,
-        )
-        .unwrap();
+"###;
+
+    /// Writes [`CONFIG_SOURCE`] to a crate's `lib.rs`, and generates its types in `format`.
+    fn generate_config_types(format: OutputFormat) -> String {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        fs::create_dir_all(temp_dir.path().join("src")).unwrap();
+
+        let source_file = temp_dir.path().join("src/lib.rs");
+        fs::write(&source_file, CONFIG_SOURCE).unwrap();
 
         let output_path = temp_dir.path().join("init.lua");
 
-        process_file(source_file, output_path.clone());
+        process_files(&[source_file], &output_path, format).unwrap();
+
+        fs::read_to_string(output_path).unwrap()
+    }
 
-        assert_snapshot!(fs::read_to_string(output_path).unwrap(), @r###"
+    #[test]
+    fn test_run_generates_lua_types() {
+        assert_snapshot!(generate_config_types(OutputFormat::LuaLs), @r###"
         ---  Configuration for the application.
         ---@class Config
         ---  Optional tmux configuration. Including sessions and windows to be created.
@@ -403,4 +951,1010 @@ pub struct Window {
 
         "###);
     }
+
+    #[test]
+    fn test_run_generates_teal_types() {
+        assert_snapshot!(generate_config_types(OutputFormat::Teal), @r###"
+        local record types
+           -- Configuration for the application.
+           record Config
+              -- Optional tmux configuration. Including sessions and windows to be created.
+              tmux: Tmux
+              -- Optional configuration for cache-shell-setup
+              shell_caching: ShellCache
+              -- Optional list of crate locations (used as a lookup path for tmux windows `linked_crates`)
+              crate_locations: {string}
+           end
+
+           record ShellCache
+              source: string
+              destination: string
+           end
+
+           -- Tmux configuration.
+           record Tmux
+              -- List of tmux sessions.
+              sessions: {Session}
+              -- The default session to attach to when `startup-tmux --attach` is ran.
+              default_session: string
+           end
+
+           -- Configuration for a tmux session.
+           record Session
+              -- Name of the session.
+              name: string
+              -- List of windows in the session.
+              windows: {Window}
+           end
+
+           -- Command to be executed in a tmux window.
+           type Command = string | {string}
+
+           -- Configuration for a tmux window.
+           record Window
+              -- Name of the window.
+              name: string
+              -- Optional path to set as the working directory for the window.
+              path: string
+              -- Optional command to run in the window.
+              command: Command
+              -- Additional environment variables to set in the window.
+              env: {string:string}
+              -- The names of any of the workspaces crates that provide binaries that should be available on  $PATH inside the new window.
+              linked_crates: {string}
+           end
+        end
+
+        return types
+        "###);
+    }
+
+    #[test]
+    fn test_run_generates_luau_types() {
+        assert_snapshot!(generate_config_types(OutputFormat::Luau), @r###"
+        -- Configuration for the application.
+        export type Config = {
+        	-- Optional tmux configuration. Including sessions and windows to be created.
+        	tmux: Tmux?,
+        	-- Optional configuration for cache-shell-setup
+        	shell_caching: ShellCache?,
+        	-- Optional list of crate locations (used as a lookup path for tmux windows `linked_crates`)
+        	crate_locations: {string}?,
+        }
+
+        export type ShellCache = {
+        	source: string,
+        	destination: string,
+        }
+
+        -- Tmux configuration.
+        export type Tmux = {
+        	-- List of tmux sessions.
+        	sessions: {Session},
+        	-- The default session to attach to when `startup-tmux --attach` is ran.
+        	default_session: string?,
+        }
+
+        -- Configuration for a tmux session.
+        export type Session = {
+        	-- Name of the session.
+        	name: string,
+        	-- List of windows in the session.
+        	windows: {Window},
+        }
+
+        -- Command to be executed in a tmux window.
+        export type Command = string | {string}
+
+        -- Configuration for a tmux window.
+        export type Window = {
+        	-- Name of the window.
+        	name: string,
+        	-- Optional path to set as the working directory for the window.
+        	path: string?,
+        	-- Optional command to run in the window.
+        	command: Command?,
+        	-- Additional environment variables to set in the window.
+        	env: {[string]: string}?,
+        	-- The names of any of the workspaces crates that provide binaries that should be available on  $PATH inside the new window.
+        	linked_crates: {string}?,
+        }
+        "###);
+    }
+
+    #[test]
+    fn test_serde_rename() {
+        let lua_types = generate_lua_types_from_str(
+            r###"
+#[derive(Deserialize)]
+pub struct Window {
+    #[serde(rename = "title")]
+    pub name: String,
+    #[serde(rename(serialize = "ser_cwd", deserialize = "cwd"))]
+    pub path: String,
+    #[serde(rename = "start-directory")]
+    pub start_directory: String,
+}
+        "###,
+        );
+
+        assert_snapshot!(lua_types, @r###"
+        ---@class Window
+        ---@field title string
+        ---@field cwd string
+        ---@field ["start-directory"] string
+        "###);
+    }
+
+    #[test]
+    fn test_serde_rename_all() {
+        let lua_types = generate_lua_types_from_str(
+            r###"
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Window {
+    pub window_name: String,
+    #[serde(rename = "explicit_name")]
+    pub renamed_field: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Session {
+    pub default_window: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct Env {
+    pub some_var: String,
+}
+        "###,
+        );
+
+        assert_snapshot!(lua_types, @r###"
+        ---@class Window
+        ---@field windowName string
+        ---@field explicit_name string
+
+        ---@class Session
+        ---@field ["default-window"] string
+
+        ---@class Env
+        ---@field SOME_VAR string
+        "###);
+    }
+
+    #[test]
+    fn test_serde_default() {
+        let lua_types = generate_lua_types_from_str(
+            r###"
+#[derive(Deserialize)]
+pub struct Window {
+    pub name: String,
+    #[serde(default)]
+    pub linked_crates: Vec<String>,
+    #[serde(default = "default_shell")]
+    pub shell: String,
+    #[serde(default)]
+    pub command: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Settings {
+    pub name: String,
+    pub sessions: Vec<String>,
+}
+        "###,
+        );
+
+        assert_snapshot!(lua_types, @r###"
+        ---@class Window
+        ---@field name string
+        ---@field linked_crates string[]|nil
+        ---@field shell string|nil
+        ---@field command string|nil
+
+        ---@class Settings
+        ---@field name string|nil
+        ---@field sessions string[]|nil
+        "###);
+    }
+
+    #[test]
+    fn test_serde_flatten() {
+        let lua_types = generate_lua_types_from_str(
+            r###"
+#[derive(Deserialize)]
+pub struct Common {
+    pub name: String,
+}
+
+#[derive(Deserialize)]
+pub struct Extra {
+    pub path: String,
+}
+
+#[derive(Deserialize)]
+pub struct Window {
+    #[serde(flatten)]
+    pub common: Common,
+    #[serde(flatten)]
+    pub extra: Option<Extra>,
+    pub command: String,
+}
+
+#[derive(Deserialize)]
+pub struct Env {
+    pub inherit: bool,
+    #[serde(flatten)]
+    pub vars: BTreeMap<String, String>,
+}
+        "###,
+        );
+
+        assert_snapshot!(lua_types, @r###"
+        ---@class Common
+        ---@field name string
+
+        ---@class Extra
+        ---@field path string
+
+        ---@class Window : Common, Extra
+        ---@field command string
+
+        ---@class Env
+        ---@field inherit boolean
+        ---@field [string] string
+        "###);
+    }
+
+    #[test]
+    fn test_serde_skip() {
+        let lua_types = generate_lua_types_from_str(
+            r###"
+#[derive(Deserialize)]
+pub struct Window {
+    pub name: String,
+    /// Computed after loading the config
+    #[serde(skip)]
+    pub resolved_path: PathBuf,
+}
+        "###,
+        );
+
+        assert_snapshot!(lua_types, @r###"
+        ---@class Window
+        ---@field name string
+        "###);
+    }
+
+    #[test]
+    fn test_serde_skip_deserializing() {
+        let lua_types = generate_lua_types_from_str(
+            r###"
+#[derive(Deserialize)]
+pub struct Window {
+    pub name: String,
+    #[serde(skip_deserializing)]
+    pub id: String,
+    #[serde(skip_serializing)]
+    pub secret: String,
+}
+        "###,
+        );
+
+        assert_snapshot!(lua_types, @r###"
+        ---@class Window
+        ---@field name string
+        ---@field secret string
+        "###);
+    }
+
+    #[test]
+    fn test_serde_deserialize_with() {
+        let lua_types = generate_lua_types_from_str(
+            r###"
+#[derive(Deserialize)]
+pub struct Window {
+    #[serde(default, deserialize_with = "string_to_path")]
+    pub path: Option<PathBuf>,
+    #[serde(deserialize_with = "crate::parse::parse_duration")]
+    pub timeout: Duration,
+    #[serde(deserialize_with = "elsewhere::unknown")]
+    pub mystery: SomethingElse,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+}
+
+fn string_to_path<'de, D>(deserializer: D) -> Result<Option<PathBuf>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let opt = Option::<String>::deserialize(deserializer)?;
+    Ok(opt.map(PathBuf::from))
+}
+
+mod parse {
+    pub fn parse_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value = <String>::deserialize(deserializer)?;
+        humantime::parse_duration(&value).map_err(serde::de::Error::custom)
+    }
+}
+        "###,
+        );
+
+        assert_snapshot!(lua_types, @r###"
+        ---@class Window
+        ---@field path string|nil
+        ---@field timeout string
+        ---@field mystery SomethingElse
+        ---@field interval Duration
+        "###);
+    }
+
+    #[test]
+    fn test_serde_with_modules() {
+        let lua_types = generate_lua_types_from_str(
+            r###"
+#[derive(Deserialize)]
+pub struct Window {
+    #[serde(with = "duration")]
+    pub timeout: Duration,
+    #[serde(with = "size")]
+    pub width: Size,
+    #[serde(deserialize_with = "size::deserialize")]
+    pub height: Size,
+}
+
+mod duration {
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value = String::deserialize(deserializer)?;
+        humantime::parse_duration(&value).map_err(serde::de::Error::custom)
+    }
+}
+
+mod size {
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Size, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value = u32::deserialize(deserializer)?;
+        Ok(Size(value))
+    }
+
+    #[derive(Deserialize)]
+    pub struct Limits {
+        #[serde(deserialize_with = "self::deserialize")]
+        pub max: Size,
+        #[serde(deserialize_with = "super::duration::deserialize")]
+        pub timeout: Duration,
+    }
+}
+        "###,
+        );
+
+        assert_snapshot!(lua_types, @r###"
+        ---@class Window
+        ---@field timeout string
+        ---@field width integer
+        ---@field height integer
+
+        ---@class Limits
+        ---@field max integer
+        ---@field timeout string
+        "###);
+    }
+
+    #[test]
+    fn test_unit_enum() {
+        let lua_types = generate_lua_types_from_str(
+            r###"
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Layout {
+    EvenHorizontal,
+    MainVertical,
+    #[serde(rename = "tiled")]
+    Tiles,
+    #[serde(skip)]
+    Internal,
+}
+
+#[derive(Deserialize)]
+pub enum DestinationStrategy {
+    Clear,
+    Merge,
+}
+        "###,
+        );
+
+        assert_snapshot!(lua_types, @r###"
+        ---@alias Layout "even-horizontal"|"main-vertical"|"tiled"
+
+        ---@alias DestinationStrategy "Clear"|"Merge"
+        "###);
+    }
+
+    #[test]
+    fn test_externally_tagged_enum() {
+        let lua_types = generate_lua_types_from_str(
+            r###"
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Destination {
+    /// Remove everything before writing
+    Clear,
+    /// Write into a specific directory
+    Directory(String),
+    Pair(String, String),
+    /// Keep a number of backups
+    Backup { path: String, #[serde(default)] keep: Option<String> },
+}
+        "###,
+        );
+
+        assert_snapshot!(lua_types, @r###"
+        ---@alias Destination "clear"|Destination.Directory|Destination.Pair|Destination.Backup
+
+        ---  Write into a specific directory
+        ---@class Destination.Directory
+        ---@field directory string
+
+        ---@class Destination.Pair
+        ---@field pair [string, string]
+
+        ---  Keep a number of backups
+        ---@class Destination.Backup
+        ---@field backup { path: string, keep: string|nil }
+        "###);
+    }
+
+    #[test]
+    fn test_internally_tagged_enum() {
+        let lua_types = generate_lua_types_from_str(
+            r###"
+#[derive(Deserialize)]
+pub struct Split {
+    pub percentage: String,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", rename_all_fields = "camelCase")]
+pub enum Pane {
+    Empty,
+    /// Run a command in the pane
+    Command {
+        /// The command to run
+        command_line: String,
+        working_dir: Option<String>,
+    },
+    Split(Split),
+}
+        "###,
+        );
+
+        assert_snapshot!(lua_types, @r###"
+        ---@class Split
+        ---@field percentage string
+
+        ---@alias Pane Pane.Empty|Pane.Command|Pane.Split
+
+        ---@class Pane.Empty
+        ---@field type "empty"
+
+        ---  Run a command in the pane
+        ---@class Pane.Command
+        ---@field type "command"
+        ---  The command to run
+        ---@field commandLine string
+        ---@field workingDir string|nil
+
+        ---@class Pane.Split : Split
+        ---@field type "split"
+        "###);
+    }
+
+    #[test]
+    fn test_adjacently_tagged_enum() {
+        let lua_types = generate_lua_types_from_str(
+            r###"
+#[derive(Deserialize)]
+#[serde(tag = "kind", content = "value")]
+pub enum Source {
+    Stdin,
+    Path(String),
+    Lines(Vec<String>),
+    Remote { url: String },
+}
+        "###,
+        );
+
+        assert_snapshot!(lua_types, @r###"
+        ---@alias Source Source.Stdin|Source.Path|Source.Lines|Source.Remote
+
+        ---@class Source.Stdin
+        ---@field kind "Stdin"
+
+        ---@class Source.Path
+        ---@field kind "Path"
+        ---@field value string
+
+        ---@class Source.Lines
+        ---@field kind "Lines"
+        ---@field value string[]
+
+        ---@class Source.Remote
+        ---@field kind "Remote"
+        ---@field value { url: string }
+        "###);
+    }
+
+    #[test]
+    fn test_untagged_enum_with_unit_variant() {
+        let lua_types = generate_lua_types_from_str(
+            r###"
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Command {
+    Nothing,
+    Single(String),
+    Multiple(Vec<String>),
+}
+        "###,
+        );
+
+        assert_snapshot!(lua_types, @"---@alias Command nil|string|string[]");
+    }
+
+    #[test]
+    fn test_hand_written_deserialize() {
+        let lua_types = generate_lua_types_from_str(
+            r###"
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum Command {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl<'de> serde::Deserialize<'de> for Command {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        todo!()
+    }
+}
+
+#[derive(Serialize)]
+pub struct Internal {
+    pub value: String,
+}
+        "###,
+        );
+
+        assert_snapshot!(lua_types, @"---@alias Command string|string[]");
+    }
+
+    #[test]
+    fn test_follows_module_tree() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        fixturify::write(
+            temp_dir.path(),
+            &BTreeMap::from([
+                (
+                    "src/lib.rs".to_string(),
+                    r###"
+mod tmux;
+#[path = "shell_cache.rs"]
+mod shell;
+
+mod nested {
+    mod inner;
+}
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub tmux: Option<tmux::Tmux>,
+    pub shell_caching: Option<shell::ShellCache>,
+}
+"###
+                    .to_string(),
+                ),
+                (
+                    "src/tmux.rs".to_string(),
+                    r###"
+mod session;
+
+use session::Session as TmuxSession;
+
+#[derive(Deserialize)]
+pub struct Tmux {
+    pub sessions: Vec<TmuxSession>,
+}
+"###
+                    .to_string(),
+                ),
+                (
+                    "src/tmux/session/mod.rs".to_string(),
+                    r###"
+#[derive(Deserialize)]
+pub struct Session {
+    pub name: String,
+}
+"###
+                    .to_string(),
+                ),
+                (
+                    "src/shell_cache.rs".to_string(),
+                    r###"
+#[derive(Deserialize)]
+pub struct ShellCache {
+    pub source: String,
+}
+"###
+                    .to_string(),
+                ),
+                (
+                    "src/nested/inner.rs".to_string(),
+                    r###"
+#[derive(Deserialize)]
+pub struct Inner {
+    pub value: String,
+}
+"###
+                    .to_string(),
+                ),
+            ]),
+        )
+        .unwrap();
+
+        let lua_types =
+            generate_from_files(&[temp_dir.path().join("src/lib.rs")], OutputFormat::LuaLs)
+                .unwrap();
+
+        assert_snapshot!(lua_types, @r###"
+        ---@class Session
+        ---@field name string
+
+        ---@class Tmux
+        ---@field sessions Session[]
+
+        ---@class ShellCache
+        ---@field source string
+
+        ---@class Inner
+        ---@field value string
+
+        ---@class Config
+        ---@field tmux Tmux|nil
+        ---@field shell_caching ShellCache|nil
+        "###);
+    }
+
+    #[test]
+    fn test_multiple_inputs() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        fixturify::write(
+            temp_dir.path(),
+            &BTreeMap::from([
+                (
+                    "config/src/lib.rs".to_string(),
+                    r###"
+mod window;
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub windows: Vec<window::Window>,
+}
+"###
+                    .to_string(),
+                ),
+                (
+                    "config/src/window.rs".to_string(),
+                    r###"
+#[derive(Deserialize)]
+pub struct Window {
+    pub name: String,
+}
+"###
+                    .to_string(),
+                ),
+                (
+                    "other/src/lib.rs".to_string(),
+                    r###"
+#[derive(Deserialize)]
+pub struct Other {
+    pub enabled: String,
+}
+"###
+                    .to_string(),
+                ),
+            ]),
+        )
+        .unwrap();
+
+        // the module that is also passed explicitly is only emitted once
+        let lua_types = generate_from_files(
+            &[
+                temp_dir.path().join("other/src/lib.rs"),
+                temp_dir.path().join("config/src/lib.rs"),
+                temp_dir.path().join("config/src/window.rs"),
+            ],
+            OutputFormat::LuaLs,
+        )
+        .unwrap();
+
+        assert_snapshot!(lua_types, @r###"
+        ---@class Other
+        ---@field enabled string
+
+        ---@class Window
+        ---@field name string
+
+        ---@class Config
+        ---@field windows Window[]
+        "###);
+    }
+
+    #[test]
+    fn test_duplicate_type_names() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        fixturify::write(
+            temp_dir.path(),
+            &BTreeMap::from([
+                (
+                    "lib.rs".to_string(),
+                    r###"
+mod other;
+
+#[derive(Deserialize)]
+pub struct Window {
+    pub name: String,
+}
+"###
+                    .to_string(),
+                ),
+                (
+                    "other.rs".to_string(),
+                    r###"
+#[derive(Deserialize)]
+pub struct Window {
+    pub title: String,
+}
+"###
+                    .to_string(),
+                ),
+            ]),
+        )
+        .unwrap();
+
+        let err = generate_from_files(&[temp_dir.path().join("lib.rs")], OutputFormat::LuaLs)
+            .unwrap_err();
+
+        assert_snapshot!(
+            err.to_string().replace(&temp_dir.path().display().to_string(), "<TEMP>"),
+            @"Duplicate type name `Window` (defined in both <TEMP>/other.rs and <TEMP>/lib.rs)"
+        );
+    }
+
+    #[test]
+    fn test_skips_test_only_modules() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        fixturify::write(
+            temp_dir.path(),
+            &BTreeMap::from([(
+                "lib.rs".to_string(),
+                r###"
+#[cfg(test)]
+mod test_helpers;
+
+#[derive(Deserialize)]
+pub struct Window {
+    pub name: String,
+}
+
+#[cfg(all(unix, test))]
+mod unix_tests {
+    #[derive(Deserialize)]
+    pub struct Fixture {
+        pub name: String,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[derive(Deserialize)]
+    pub struct Window {
+        pub title: String,
+    }
+}
+"###
+                .to_string(),
+            )]),
+        )
+        .unwrap();
+
+        let lua_types =
+            generate_from_files(&[temp_dir.path().join("lib.rs")], OutputFormat::LuaLs).unwrap();
+
+        assert_snapshot!(lua_types, @r###"
+        ---@class Window
+        ---@field name string
+        "###);
+    }
+
+    #[test]
+    fn test_missing_module_file() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        fs::write(temp_dir.path().join("lib.rs"), "mod missing;").unwrap();
+
+        let err = generate_from_files(&[temp_dir.path().join("lib.rs")], OutputFormat::LuaLs)
+            .unwrap_err();
+
+        assert_snapshot!(
+            format!("{:#}", err).replace(&temp_dir.path().display().to_string(), "<TEMP>"),
+            @"Unable to find the file for `mod missing;` declared in <TEMP>/lib.rs: Neither <TEMP>/missing.rs nor <TEMP>/missing/mod.rs exist"
+        );
+    }
+
+    #[test]
+    fn test_primitive_types() {
+        let lua_types = generate_lua_types_from_str(
+            r###"
+#[derive(Deserialize)]
+pub struct Server {
+    pub enabled: bool,
+    pub port: u16,
+    pub retries: i64,
+    pub workers: NonZeroUsize,
+    pub ratio: f64,
+    pub separator: char,
+    pub name: &'static str,
+    pub nothing: (),
+}
+        "###,
+        );
+
+        assert_snapshot!(lua_types, @r###"
+        ---@class Server
+        ---@field enabled boolean
+        ---@field port integer
+        ---@field retries integer
+        ---@field workers integer
+        ---@field ratio number
+        ---@field separator string
+        ---@field name string
+        ---@field nothing nil
+        "###);
+    }
+
+    #[test]
+    fn test_wrapper_and_collection_types() {
+        let lua_types = generate_lua_types_from_str(
+            r###"
+#[derive(Deserialize)]
+pub struct Window {
+    pub command: Box<config::Command>,
+    pub shared: Arc<Option<String>>,
+    pub counted: Rc<Vec<u8>>,
+    pub label: Cow<'static, str>,
+    pub tags: HashSet<String>,
+    pub ordered_tags: BTreeSet<String>,
+    pub queue: VecDeque<PathBuf>,
+    pub size: [u32; 2],
+    pub bounds: (i32, i32, f32),
+    pub extra: serde_json::Value,
+    pub settings: toml::Value,
+    pub script: mlua::Value,
+    pub env: HashMap<String, Value>,
+}
+        "###,
+        );
+
+        assert_snapshot!(lua_types, @r###"
+        ---@class Window
+        ---@field command Command
+        ---@field shared string|nil
+        ---@field counted integer[]
+        ---@field label string
+        ---@field tags string[]
+        ---@field ordered_tags string[]
+        ---@field queue string[]
+        ---@field size integer[]
+        ---@field bounds [integer, integer, number]
+        ---@field extra any
+        ---@field settings any
+        ---@field script any
+        ---@field env table<string, Value>
+        "###);
+    }
+
+    #[test]
+    fn test_lazy_values() {
+        let lua_types = generate_lua_types_from_str(
+            r###"
+#[derive(Deserialize)]
+pub struct Window {
+    pub command: Option<Lazy<Command>>,
+    pub title: Lazy<String>,
+    pub panes: Vec<Lazy<String>>,
+}
+        "###,
+        );
+
+        assert_snapshot!(lua_types, @r###"
+        ---@class Window
+        ---@field command Command|(fun(ctx: ConfigContext): Command)|nil
+        ---@field title string|(fun(ctx: ConfigContext): string)
+        ---@field panes (string|(fun(ctx: ConfigContext): string))[]
+        "###);
+    }
+
+    #[test]
+    fn test_untagged_enum_with_tuple_variant() {
+        let lua_types = generate_lua_types_from_str(
+            r###"
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Size {
+    Fixed(u32),
+    Dimensions(u32, u32),
+}
+        "###,
+        );
+
+        assert_snapshot!(lua_types, @"---@alias Size [integer, integer]|integer");
+    }
+
+    #[test]
+    fn test_check_files() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let source_file = temp_dir.path().join("lib.rs");
+        let output_path = temp_dir.path().join("init.lua");
+        fs::write(
+            &source_file,
+            r###"
+#[derive(Deserialize)]
+pub struct Window {
+    pub name: String,
+    pub path: Option<String>,
+}
+"###,
+        )
+        .unwrap();
+
+        process_files(&[&source_file], &output_path, OutputFormat::LuaLs).unwrap();
+        assert_eq!(
+            check_files(&[&source_file], &output_path, OutputFormat::LuaLs).unwrap(),
+            None
+        );
+
+        fs::write(
+            &output_path,
+            "---@class Window\n---@field name string\n---@field title string\n\n",
+        )
+        .unwrap();
+
+        let diff = check_files(&[&source_file], &output_path, OutputFormat::LuaLs)
+            .unwrap()
+            .expect("expected the output to be stale")
+            .replace(&temp_dir.path().display().to_string(), "<TEMP>");
+
+        assert_snapshot!(diff, @r###"
+        --- <TEMP>/init.lua
+        +++ <TEMP>/init.lua (generated)
+        @@ -1,4 +1,4 @@
+         ---@class Window
+         ---@field name string
+        ----@field title string
+        +---@field path string|nil
+         
+        "###);
+    }
 }
@@ -0,0 +1,216 @@
+use std::fmt;
+
+/// A type that a Lua config can contain, independent of the format it is rendered to.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct TypeDefinition {
+    pub(crate) name: String,
+    pub(crate) docs: Option<String>,
+    pub(crate) kind: TypeKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum TypeKind {
+    /// A table with known fields (structs and the variants of tagged enums).
+    Class(ClassDefinition),
+    /// One of several types (enums), along with the classes for any of the variants that are
+    /// represented as tables.
+    Alias {
+        variants: Vec<LuaType>,
+        variant_classes: Vec<TypeDefinition>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(crate) struct ClassDefinition {
+    /// Classes whose fields are included (from `#[serde(flatten)]`).
+    pub(crate) parents: Vec<String>,
+    pub(crate) fields: Vec<FieldDefinition>,
+}
+
+impl ClassDefinition {
+    /// The class as an inline table type (e.g. `{ name: string }`).
+    pub(crate) fn to_inline_table(&self) -> LuaType {
+        let fields = self
+            .fields
+            .iter()
+            .filter_map(|field| match &field.key {
+                FieldKey::Name(name) => Some((name.clone(), field.effective_type())),
+                FieldKey::Index(_) => None,
+            })
+            .collect();
+
+        LuaType::Table(fields)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct FieldDefinition {
+    pub(crate) key: FieldKey,
+    /// The type as declared, which doesn't account for the field having a default.
+    pub(crate) lua_type: LuaType,
+    pub(crate) docs: Option<String>,
+    pub(crate) default: Option<FieldDefault>,
+}
+
+impl FieldDefinition {
+    pub(crate) fn is_required(&self) -> bool {
+        self.default.is_none() && !self.lua_type.is_optional()
+    }
+
+    /// The type of values that a config can contain, fields with a default may be omitted.
+    pub(crate) fn effective_type(&self) -> LuaType {
+        if self.default.is_some() {
+            self.lua_type.clone().optional()
+        } else {
+            self.lua_type.clone()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum FieldKey {
+    Name(String),
+    /// Any key of the given type (from flattened maps).
+    Index(LuaType),
+}
+
+/// Where the value of a missing field comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum FieldDefault {
+    /// `#[serde(default)]`, i.e. the type's `Default` implementation.
+    Default,
+    /// `#[serde(default = "path")]`
+    Function(String),
+    /// `#[serde(default)]` on the containing struct, i.e. the field's value in the struct's
+    /// `Default` implementation.
+    Container,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum LuaType {
+    Any,
+    Unknown,
+    Nil,
+    Boolean,
+    Integer,
+    Number,
+    String,
+    /// A string literal, e.g. the tag of an enum variant.
+    Literal(String),
+    /// A class or alias defined elsewhere.
+    Named(String),
+    Array(Box<LuaType>),
+    Map(Box<LuaType>, Box<LuaType>),
+    Tuple(Vec<LuaType>),
+    Table(Vec<(String, LuaType)>),
+    /// A function, e.g. a lazily evaluated config value.
+    Function {
+        params: Vec<(String, LuaType)>,
+        returns: Box<LuaType>,
+    },
+    Union(Vec<LuaType>),
+    Optional(Box<LuaType>),
+}
+
+impl LuaType {
+    pub(crate) fn is_optional(&self) -> bool {
+        matches!(self, LuaType::Optional(_) | LuaType::Any | LuaType::Nil)
+    }
+
+    /// Allows the value to be missing, unless that is already the case.
+    pub(crate) fn optional(self) -> LuaType {
+        if self.is_optional() {
+            self
+        } else {
+            LuaType::Optional(Box::new(self))
+        }
+    }
+
+    /// The type without its `nil` case.
+    pub(crate) fn strip_nil(&self) -> &LuaType {
+        match self {
+            LuaType::Optional(inner) => inner,
+            _ => self,
+        }
+    }
+}
+
+fn is_lua_identifier(name: &str) -> bool {
+    name.chars()
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && name
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+}
+
+/// Renders the type using the LuaLS annotation syntax.
+impl fmt::Display for LuaType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LuaType::Any => write!(f, "any"),
+            LuaType::Unknown => write!(f, "unknown"),
+            LuaType::Nil => write!(f, "nil"),
+            LuaType::Boolean => write!(f, "boolean"),
+            LuaType::Integer => write!(f, "integer"),
+            LuaType::Number => write!(f, "number"),
+            LuaType::String => write!(f, "string"),
+            LuaType::Literal(value) => write!(f, "\"{}\"", value),
+            LuaType::Named(name) => write!(f, "{}", name),
+            LuaType::Array(inner) => match inner.as_ref() {
+                LuaType::Optional(_) | LuaType::Union(_) | LuaType::Function { .. } => {
+                    write!(f, "({})[]", inner)
+                }
+                _ => write!(f, "{}[]", inner),
+            },
+            LuaType::Map(key, value) => write!(f, "table<{}, {}>", key, value),
+            LuaType::Tuple(elements) => write!(f, "[{}]", join(elements, ", ")),
+            LuaType::Table(fields) => {
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(name, lua_type)| format!("{}: {}", luals_field_name(name), lua_type))
+                    .collect();
+                write!(f, "{{ {} }}", fields.join(", "))
+            }
+            LuaType::Function { params, returns } => {
+                let params: Vec<String> = params
+                    .iter()
+                    .map(|(name, lua_type)| format!("{}: {}", name, lua_type))
+                    .collect();
+                write!(f, "fun({}): {}", params.join(", "), returns)
+            }
+            LuaType::Union(members) => {
+                // without parentheses the return type of a function would absorb later members
+                let members: Vec<String> = members
+                    .iter()
+                    .map(|member| match member {
+                        LuaType::Function { .. } => format!("({})", member),
+                        _ => member.to_string(),
+                    })
+                    .collect();
+                write!(f, "{}", members.join("|"))
+            }
+            LuaType::Optional(inner) => match inner.as_ref() {
+                LuaType::Function { .. } => write!(f, "({})|nil", inner),
+                _ => write!(f, "{}|nil", inner),
+            },
+        }
+    }
+}
+
+/// Field names that aren't valid Lua identifiers (e.g. `kebab-case` renames) have to be quoted.
+pub(crate) fn luals_field_name(name: &str) -> String {
+    if is_lua_identifier(name) {
+        name.to_string()
+    } else {
+        format!("[\"{}\"]", name)
+    }
+}
+
+fn join(types: &[LuaType], separator: &str) -> String {
+    types
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(separator)
+}
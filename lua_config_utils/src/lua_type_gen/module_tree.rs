@@ -0,0 +1,270 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use syn::punctuated::Punctuated;
+use syn::visit_mut::VisitMut;
+use syn::{Attribute, Item, Token, UseTree};
+
+/// An item found while walking a crate's module tree, along with the file that defined it.
+pub(crate) struct SourceItem {
+    pub(crate) source: PathBuf,
+    /// The path of the module that defines the item, relative to the crate root (e.g. `["parse"]`
+    /// for an item of `parse.rs`).
+    pub(crate) module_path: Vec<String>,
+    pub(crate) item: Item,
+}
+
+/// Collects the items of every module reachable from the given root files (e.g. `lib.rs`).
+///
+/// Items are returned in input order, with the items of each module inserted where its `mod`
+/// declaration appears (a depth first walk), so the output is stable across runs. Files that are
+/// reachable more than once (e.g. a module that is also passed as an input) are only read once.
+#[derive(Default)]
+pub(crate) struct ModuleTree {
+    visited: HashSet<PathBuf>,
+    items: Vec<SourceItem>,
+}
+
+impl ModuleTree {
+    pub(crate) fn from_files<P: AsRef<Path>>(file_paths: &[P]) -> Result<Vec<SourceItem>> {
+        let mut tree = ModuleTree::default();
+        for file_path in file_paths {
+            tree.walk_file(file_path.as_ref(), None, &[])?;
+        }
+
+        Ok(tree.items)
+    }
+
+    /// Walks already parsed source that isn't backed by a file, which means that it may only
+    /// contain inline modules.
+    #[cfg(test)]
+    pub(crate) fn from_source(source: &Path, content: &str) -> Result<Vec<SourceItem>> {
+        let syntax = syn::parse_file(content)
+            .with_context(|| format!("Unable to parse file: {}", source.display()))?;
+
+        let mut tree = ModuleTree::default();
+        tree.walk_items(syntax.items, source, None, &[], false)?;
+
+        Ok(tree.items)
+    }
+
+    /// `module_dir` is the directory that the file's own `mod foo;` declarations are resolved
+    /// against, which defaults to the directory for a module named after the file.
+    fn walk_file(
+        &mut self,
+        file_path: &Path,
+        module_dir: Option<PathBuf>,
+        module_path: &[String],
+    ) -> Result<()> {
+        let canonical_path = file_path
+            .canonicalize()
+            .with_context(|| format!("Unable to read file: {}", file_path.display()))?;
+        if !self.visited.insert(canonical_path) {
+            return Ok(());
+        }
+
+        let content = fs::read_to_string(file_path)
+            .with_context(|| format!("Unable to read file: {}", file_path.display()))?;
+        let syntax = syn::parse_file(&content)
+            .with_context(|| format!("Unable to parse file: {}", file_path.display()))?;
+
+        let module_dir = module_dir.unwrap_or_else(|| default_module_dir(file_path));
+        self.walk_items(
+            syntax.items,
+            file_path,
+            Some(&module_dir),
+            module_path,
+            false,
+        )
+    }
+
+    fn walk_items(
+        &mut self,
+        items: Vec<Item>,
+        source: &Path,
+        module_dir: Option<&Path>,
+        module_path: &[String],
+        in_inline_module: bool,
+    ) -> Result<()> {
+        let mut aliases = UseAliases::default();
+        for item in &items {
+            if let Item::Use(item_use) = item {
+                aliases.collect(&item_use.tree);
+            }
+        }
+
+        for mut item in items {
+            let Item::Mod(item_mod) = item else {
+                aliases.visit_item_mut(&mut item);
+                self.items.push(SourceItem {
+                    source: source.to_path_buf(),
+                    module_path: module_path.to_vec(),
+                    item,
+                });
+                continue;
+            };
+
+            // test-only modules may redefine types (e.g. fixtures), and don't deserialize configs
+            if is_test_only(&item_mod.attrs) {
+                continue;
+            }
+
+            let module_name = item_mod.ident.to_string();
+            let path_attr = path_attribute(&item_mod.attrs);
+            let nested_path = [module_path, std::slice::from_ref(&module_name)].concat();
+
+            match item_mod.content {
+                Some((_, module_items)) => {
+                    let nested_dir = module_dir.map(|dir| match &path_attr {
+                        Some(path) => dir.join(path),
+                        None => dir.join(&module_name),
+                    });
+                    self.walk_items(
+                        module_items,
+                        source,
+                        nested_dir.as_deref(),
+                        &nested_path,
+                        true,
+                    )?;
+                }
+                None => {
+                    let Some(module_dir) = module_dir else {
+                        bail!(
+                            "Unable to resolve `mod {};` in {} (it is not backed by a file)",
+                            module_name,
+                            source.display()
+                        );
+                    };
+
+                    let module_file = match &path_attr {
+                        // `#[path]` is relative to the declaring file, unless it's declared
+                        // inside of an inline module
+                        Some(path) if in_inline_module => module_dir.join(path),
+                        Some(path) => source.parent().unwrap_or(Path::new("")).join(path),
+                        None => find_module_file(module_dir, &module_name).with_context(|| {
+                            format!(
+                                "Unable to find the file for `mod {};` declared in {}",
+                                module_name,
+                                source.display()
+                            )
+                        })?,
+                    };
+
+                    // files loaded with `#[path]` behave like `mod.rs` files
+                    let nested_dir = path_attr
+                        .as_ref()
+                        .map(|_| module_file.parent().unwrap_or(Path::new("")).to_path_buf());
+                    self.walk_file(&module_file, nested_dir, &nested_path)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// `lib.rs`, `main.rs` and `mod.rs` own their directory, any other `foo.rs` declares its
+/// submodules in `foo/`.
+fn default_module_dir(file_path: &Path) -> PathBuf {
+    let parent = file_path.parent().unwrap_or(Path::new("")).to_path_buf();
+
+    match file_path.file_stem().and_then(|stem| stem.to_str()) {
+        Some("lib" | "main" | "mod") | None => parent,
+        Some(stem) => parent.join(stem),
+    }
+}
+
+fn find_module_file(module_dir: &Path, module_name: &str) -> Result<PathBuf> {
+    let candidates = [
+        module_dir.join(format!("{}.rs", module_name)),
+        module_dir.join(module_name).join("mod.rs"),
+    ];
+
+    match candidates.iter().find(|candidate| candidate.is_file()) {
+        Some(module_file) => Ok(module_file.clone()),
+        None => bail!(
+            "Neither {} nor {} exist",
+            candidates[0].display(),
+            candidates[1].display()
+        ),
+    }
+}
+
+/// Whether the item is only compiled for tests, i.e. `#[cfg(test)]` or `#[cfg(all(test, ...))]`.
+fn is_test_only(attrs: &[Attribute]) -> bool {
+    fn requires_test(meta: &syn::Meta) -> bool {
+        match meta {
+            syn::Meta::Path(path) => path.is_ident("test"),
+            syn::Meta::List(list) if list.path.is_ident("all") => list
+                .parse_args_with(Punctuated::<syn::Meta, Token![,]>::parse_terminated)
+                .is_ok_and(|nested| nested.iter().any(requires_test)),
+            _ => false,
+        }
+    }
+
+    attrs.iter().any(|attr| {
+        attr.path().is_ident("cfg")
+            && attr
+                .parse_args::<syn::Meta>()
+                .is_ok_and(|meta| requires_test(&meta))
+    })
+}
+
+fn path_attribute(attrs: &[Attribute]) -> Option<String> {
+    attrs.iter().find_map(|attr| {
+        if !attr.path().is_ident("path") {
+            return None;
+        }
+        let syn::Meta::NameValue(meta) = &attr.meta else {
+            return None;
+        };
+        let syn::Expr::Lit(expr_lit) = &meta.value else {
+            return None;
+        };
+        let syn::Lit::Str(lit) = &expr_lit.lit else {
+            return None;
+        };
+
+        Some(lit.value())
+    })
+}
+
+/// The `use foo::Bar as Baz;` renames of a module, used to rewrite `Baz` back to `Bar` so that
+/// field types refer to the name that the Lua class is generated with.
+#[derive(Debug, Default)]
+struct UseAliases {
+    renames: HashMap<String, String>,
+}
+
+impl UseAliases {
+    fn collect(&mut self, tree: &UseTree) {
+        match tree {
+            UseTree::Path(use_path) => self.collect(&use_path.tree),
+            UseTree::Rename(use_rename) => {
+                self.renames
+                    .insert(use_rename.rename.to_string(), use_rename.ident.to_string());
+            }
+            UseTree::Group(use_group) => {
+                for tree in &use_group.items {
+                    self.collect(tree);
+                }
+            }
+            UseTree::Name(_) | UseTree::Glob(_) => {}
+        }
+    }
+}
+
+impl VisitMut for UseAliases {
+    fn visit_type_path_mut(&mut self, type_path: &mut syn::TypePath) {
+        if type_path.qself.is_none() && type_path.path.segments.len() == 1 {
+            let segment = &mut type_path.path.segments[0];
+            if let Some(original) = self.renames.get(&segment.ident.to_string()) {
+                segment.ident = syn::Ident::new(original, segment.ident.span());
+            }
+        }
+
+        syn::visit_mut::visit_type_path_mut(self, type_path);
+    }
+}
@@ -0,0 +1,297 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use syn::Item;
+
+use super::module_tree::SourceItem;
+use super::{OutputFormat, render, type_definitions};
+
+/// Types that can describe themselves to a [`LuaTypeRegistry`], which is usually implemented with
+/// `#[derive(LuaType)]`.
+pub trait LuaType {
+    /// Registers the type's definition (if it has one), along with every type that it refers to.
+    fn register(registry: &mut LuaTypeRegistry);
+}
+
+/// The set of types reachable from one or more root types, in the order that they were first
+/// encountered.
+#[derive(Debug, Default)]
+pub struct LuaTypeRegistry {
+    registered: HashSet<&'static str>,
+    sources: Vec<(&'static str, &'static str)>,
+}
+
+impl LuaTypeRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `T` and every type that is reachable from it.
+    pub fn register<T: LuaType + ?Sized>(&mut self) -> &mut Self {
+        T::register(self);
+        self
+    }
+
+    /// Records the source of a derived type, returning `false` when it was already registered
+    /// (so that recursive types terminate).
+    #[doc(hidden)]
+    pub fn add_source(&mut self, type_name: &'static str, source: &'static str) -> bool {
+        if !self.registered.insert(type_name) {
+            return false;
+        }
+
+        self.sources.push((type_name, source));
+        true
+    }
+
+    /// Generates the types for everything that has been registered.
+    pub fn generate(&self, format: OutputFormat) -> Result<String> {
+        let mut items = vec![];
+        for (type_name, source) in &self.sources {
+            let item: Item = syn::parse_str(source)
+                .with_context(|| format!("Unable to parse the derived source of {}", type_name))?;
+
+            items.push(SourceItem {
+                source: PathBuf::from(type_name),
+                module_path: vec![],
+                item,
+            });
+        }
+
+        let definitions = type_definitions(&items, false)?;
+
+        Ok(render(&definitions, format))
+    }
+}
+
+/// Types that map directly to a Lua type and don't need a definition.
+macro_rules! impl_builtin_lua_type {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            impl LuaType for $ty {
+                fn register(_registry: &mut LuaTypeRegistry) {}
+            }
+        )*
+    };
+}
+
+impl_builtin_lua_type!(
+    (),
+    bool,
+    char,
+    str,
+    String,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    f32,
+    f64,
+    Path,
+    PathBuf,
+    OsStr,
+    OsString,
+    serde_json::Value,
+);
+
+/// Wrappers and collections only register the types that they contain.
+macro_rules! impl_wrapper_lua_type {
+    ($($ty:ident),* $(,)?) => {
+        $(
+            impl<T: LuaType> LuaType for $ty<T> {
+                fn register(registry: &mut LuaTypeRegistry) {
+                    T::register(registry);
+                }
+            }
+        )*
+    };
+}
+
+impl_wrapper_lua_type!(Option, Vec, VecDeque, HashSet, BTreeSet);
+
+impl<T: LuaType + ?Sized> LuaType for Box<T> {
+    fn register(registry: &mut LuaTypeRegistry) {
+        T::register(registry);
+    }
+}
+
+impl<T: LuaType + ?Sized> LuaType for Arc<T> {
+    fn register(registry: &mut LuaTypeRegistry) {
+        T::register(registry);
+    }
+}
+
+impl<T: LuaType + ?Sized> LuaType for Rc<T> {
+    fn register(registry: &mut LuaTypeRegistry) {
+        T::register(registry);
+    }
+}
+
+impl<T: LuaType + ?Sized> LuaType for &T {
+    fn register(registry: &mut LuaTypeRegistry) {
+        T::register(registry);
+    }
+}
+
+impl<T: LuaType + ToOwned + ?Sized> LuaType for Cow<'_, T> {
+    fn register(registry: &mut LuaTypeRegistry) {
+        T::register(registry);
+    }
+}
+
+impl<T: LuaType> LuaType for [T] {
+    fn register(registry: &mut LuaTypeRegistry) {
+        T::register(registry);
+    }
+}
+
+impl<T: LuaType, const N: usize> LuaType for [T; N] {
+    fn register(registry: &mut LuaTypeRegistry) {
+        T::register(registry);
+    }
+}
+
+impl<K: LuaType, V: LuaType, S> LuaType for HashMap<K, V, S> {
+    fn register(registry: &mut LuaTypeRegistry) {
+        K::register(registry);
+        V::register(registry);
+    }
+}
+
+impl<K: LuaType, V: LuaType> LuaType for BTreeMap<K, V> {
+    fn register(registry: &mut LuaTypeRegistry) {
+        K::register(registry);
+        V::register(registry);
+    }
+}
+
+macro_rules! impl_tuple_lua_type {
+    ($($name:ident),+) => {
+        impl<$($name: LuaType),+> LuaType for ($($name,)+) {
+            fn register(registry: &mut LuaTypeRegistry) {
+                $($name::register(registry);)+
+            }
+        }
+    };
+}
+
+impl_tuple_lua_type!(A);
+impl_tuple_lua_type!(A, B);
+impl_tuple_lua_type!(A, B, C);
+impl_tuple_lua_type!(A, B, C, D);
+
+#[cfg(test)]
+mod tests {
+    use crate::lua_type_gen::OutputFormat;
+    use crate::{LuaType, LuaTypeRegistry};
+    use insta::assert_snapshot;
+    use serde::Deserialize;
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+
+    mod tmux {
+        use super::*;
+
+        /// Tmux configuration.
+        #[derive(LuaType)]
+        #[allow(dead_code)]
+        pub(crate) struct Tmux {
+            /// List of tmux sessions.
+            pub(crate) sessions: Vec<Session>,
+        }
+
+        /// Configuration for a tmux session.
+        #[derive(LuaType)]
+        #[allow(dead_code)]
+        pub(crate) struct Session {
+            pub(crate) name: String,
+            pub(crate) windows: Vec<Window>,
+            /// Sessions can be nested, which must not register `Session` twice.
+            pub(crate) parent: Option<Box<Session>>,
+        }
+
+        #[derive(Deserialize, LuaType)]
+        #[allow(dead_code)]
+        pub(crate) struct Window {
+            pub(crate) name: String,
+            #[lua(as = "Option<String>")]
+            pub(crate) path: Option<PathBuf>,
+            pub(crate) command: Option<Command>,
+            pub(crate) env: BTreeMap<String, String>,
+            #[serde(skip)]
+            pub(crate) internal: Internal,
+        }
+
+        /// Generated by a macro, which `syn` based parsing of the source file can't see.
+        macro_rules! define_command {
+            () => {
+                #[derive(Deserialize, LuaType)]
+                #[serde(untagged)]
+                #[allow(dead_code)]
+                pub(crate) enum Command {
+                    Single(String),
+                    Multiple(Vec<String>),
+                }
+            };
+        }
+
+        define_command!();
+
+        #[derive(Default, LuaType)]
+        pub(crate) struct Internal;
+    }
+
+    #[derive(LuaType)]
+    #[allow(dead_code)]
+    struct Config {
+        tmux: Option<tmux::Tmux>,
+        crate_locations: Option<Vec<String>>,
+    }
+
+    #[test]
+    fn test_registry_generates_reachable_types() {
+        let lua_types = LuaTypeRegistry::new()
+            .register::<Config>()
+            .generate(OutputFormat::LuaLs)
+            .unwrap();
+
+        assert_snapshot!(lua_types, @r###"
+        ---@class Config
+        ---@field tmux Tmux|nil
+        ---@field crate_locations string[]|nil
+
+        ---  Tmux configuration.
+        ---@class Tmux
+        ---  List of tmux sessions.
+        ---@field sessions Session[]
+
+        ---  Configuration for a tmux session.
+        ---@class Session
+        ---@field name string
+        ---@field windows Window[]
+        ---  Sessions can be nested, which must not register `Session` twice.
+        ---@field parent Session|nil
+
+        ---@class Window
+        ---@field name string
+        ---@field path string|nil
+        ---@field command Command|nil
+        ---@field env table<string, string>
+
+        ---@alias Command string|string[]
+        "###);
+    }
+}
@@ -0,0 +1,306 @@
+use syn::meta::ParseNestedMeta;
+use syn::{Attribute, LitStr, Token};
+
+use super::model::FieldDefault;
+
+/// The `rename_all` casing rules supported by serde.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum RenameRule {
+    Lower,
+    Upper,
+    Pascal,
+    Camel,
+    Snake,
+    ScreamingSnake,
+    Kebab,
+    ScreamingKebab,
+}
+
+impl RenameRule {
+    fn from_str(rule: &str) -> Option<Self> {
+        match rule {
+            "lowercase" => Some(RenameRule::Lower),
+            "UPPERCASE" => Some(RenameRule::Upper),
+            "PascalCase" => Some(RenameRule::Pascal),
+            "camelCase" => Some(RenameRule::Camel),
+            "snake_case" => Some(RenameRule::Snake),
+            "SCREAMING_SNAKE_CASE" => Some(RenameRule::ScreamingSnake),
+            "kebab-case" => Some(RenameRule::Kebab),
+            "SCREAMING-KEBAB-CASE" => Some(RenameRule::ScreamingKebab),
+            _ => None,
+        }
+    }
+
+    /// Applies the rule to a field name (which is expected to be `snake_case` in Rust).
+    pub(crate) fn apply_to_field(self, field: &str) -> String {
+        match self {
+            RenameRule::Lower | RenameRule::Snake => field.to_string(),
+            RenameRule::Upper | RenameRule::ScreamingSnake => field.to_ascii_uppercase(),
+            RenameRule::Pascal => {
+                let mut pascal = String::new();
+                let mut capitalize = true;
+                for ch in field.chars() {
+                    if ch == '_' {
+                        capitalize = true;
+                    } else if capitalize {
+                        pascal.push(ch.to_ascii_uppercase());
+                        capitalize = false;
+                    } else {
+                        pascal.push(ch);
+                    }
+                }
+                pascal
+            }
+            RenameRule::Camel => {
+                let pascal = RenameRule::Pascal.apply_to_field(field);
+                lowercase_first(&pascal)
+            }
+            RenameRule::Kebab => field.replace('_', "-"),
+            RenameRule::ScreamingKebab => field.replace('_', "-").to_ascii_uppercase(),
+        }
+    }
+
+    /// Applies the rule to a variant name (which is expected to be `PascalCase` in Rust).
+    pub(crate) fn apply_to_variant(self, variant: &str) -> String {
+        match self {
+            RenameRule::Pascal => variant.to_string(),
+            RenameRule::Lower => variant.to_ascii_lowercase(),
+            RenameRule::Upper => variant.to_ascii_uppercase(),
+            RenameRule::Camel => lowercase_first(variant),
+            RenameRule::Snake => {
+                let mut snake = String::new();
+                for (i, ch) in variant.char_indices() {
+                    if i > 0 && ch.is_uppercase() {
+                        snake.push('_');
+                    }
+                    snake.push(ch.to_ascii_lowercase());
+                }
+                snake
+            }
+            RenameRule::ScreamingSnake => RenameRule::Snake
+                .apply_to_variant(variant)
+                .to_ascii_uppercase(),
+            RenameRule::Kebab => RenameRule::Snake
+                .apply_to_variant(variant)
+                .replace('_', "-"),
+            RenameRule::ScreamingKebab => RenameRule::ScreamingSnake
+                .apply_to_variant(variant)
+                .replace('_', "-"),
+        }
+    }
+}
+
+fn lowercase_first(value: &str) -> String {
+    let mut chars = value.chars();
+    match chars.next() {
+        Some(first) => first.to_ascii_lowercase().to_string() + chars.as_str(),
+        None => String::new(),
+    }
+}
+
+/// How an enum is represented, see <https://serde.rs/enum-representations.html>.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) enum EnumTagging {
+    #[default]
+    External,
+    Internal {
+        tag: String,
+    },
+    Adjacent {
+        tag: String,
+        content: String,
+    },
+    Untagged,
+}
+
+/// The container level (`struct`/`enum`) serde attributes that affect deserialization.
+#[derive(Debug, Default)]
+pub(crate) struct ContainerAttrs {
+    pub(crate) rename_all: Option<RenameRule>,
+    pub(crate) rename_all_fields: Option<RenameRule>,
+    pub(crate) default: bool,
+    pub(crate) tagging: EnumTagging,
+}
+
+impl ContainerAttrs {
+    pub(crate) fn from_attrs(attrs: &[Attribute]) -> Self {
+        let mut container = ContainerAttrs::default();
+        let mut tag = None;
+        let mut content = None;
+        let mut untagged = false;
+
+        for_each_serde_meta(attrs, |meta| {
+            if meta.path.is_ident("rename_all") {
+                if let Some(rule) = parse_deserialize_name(&meta)? {
+                    container.rename_all = RenameRule::from_str(&rule);
+                }
+            } else if meta.path.is_ident("rename_all_fields") {
+                if let Some(rule) = parse_deserialize_name(&meta)? {
+                    container.rename_all_fields = RenameRule::from_str(&rule);
+                }
+            } else if meta.path.is_ident("default") {
+                container.default = true;
+                skip_meta_value(&meta)?;
+            } else if meta.path.is_ident("tag") {
+                let value: LitStr = meta.value()?.parse()?;
+                tag = Some(value.value());
+            } else if meta.path.is_ident("content") {
+                let value: LitStr = meta.value()?.parse()?;
+                content = Some(value.value());
+            } else if meta.path.is_ident("untagged") {
+                untagged = true;
+            } else {
+                skip_meta_value(&meta)?;
+            }
+
+            Ok(())
+        });
+
+        container.tagging = match (untagged, tag, content) {
+            (true, _, _) => EnumTagging::Untagged,
+            (false, Some(tag), Some(content)) => EnumTagging::Adjacent { tag, content },
+            (false, Some(tag), None) => EnumTagging::Internal { tag },
+            (false, None, _) => EnumTagging::External,
+        };
+
+        container
+    }
+}
+
+/// The field level serde attributes that affect deserialization.
+#[derive(Debug, Default)]
+pub(crate) struct FieldAttrs {
+    pub(crate) rename: Option<String>,
+    pub(crate) default: Option<FieldDefault>,
+    pub(crate) flatten: bool,
+    pub(crate) skip: bool,
+    pub(crate) deserialize_with: Option<String>,
+    /// The type from `#[lua(as = "...")]`, which takes precedence over the field's own type.
+    pub(crate) lua_as: Option<String>,
+}
+
+impl FieldAttrs {
+    pub(crate) fn from_attrs(attrs: &[Attribute]) -> Self {
+        let mut field = FieldAttrs::default();
+
+        for_each_serde_meta(attrs, |meta| {
+            if meta.path.is_ident("rename") {
+                field.rename = parse_deserialize_name(&meta)?;
+            } else if meta.path.is_ident("default") {
+                field.default = Some(if meta.input.peek(Token![=]) {
+                    let value: LitStr = meta.value()?.parse()?;
+                    FieldDefault::Function(value.value())
+                } else {
+                    FieldDefault::Default
+                });
+            } else if meta.path.is_ident("flatten") {
+                field.flatten = true;
+            } else if meta.path.is_ident("skip") || meta.path.is_ident("skip_deserializing") {
+                field.skip = true;
+            } else if meta.path.is_ident("deserialize_with") {
+                let value: LitStr = meta.value()?.parse()?;
+                field.deserialize_with = Some(value.value());
+            } else if meta.path.is_ident("with") {
+                let value: LitStr = meta.value()?.parse()?;
+                field.deserialize_with = Some(format!("{}::deserialize", value.value()));
+            } else {
+                skip_meta_value(&meta)?;
+            }
+
+            Ok(())
+        });
+
+        for attr in attrs {
+            if attr.path().is_ident("lua") {
+                // NOTE: `#[derive(LuaType)]` rejects malformed attributes at compile time
+                let _ = attr.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("as") {
+                        let value: LitStr = meta.value()?.parse()?;
+                        field.lua_as = Some(value.value());
+                    }
+                    Ok(())
+                });
+            }
+        }
+
+        field
+    }
+}
+
+/// The variant level serde attributes that affect deserialization.
+#[derive(Debug, Default)]
+pub(crate) struct VariantAttrs {
+    pub(crate) rename: Option<String>,
+    pub(crate) rename_all: Option<RenameRule>,
+    pub(crate) skip: bool,
+}
+
+impl VariantAttrs {
+    pub(crate) fn from_attrs(attrs: &[Attribute]) -> Self {
+        let mut variant = VariantAttrs::default();
+
+        for_each_serde_meta(attrs, |meta| {
+            if meta.path.is_ident("rename") {
+                variant.rename = parse_deserialize_name(&meta)?;
+            } else if meta.path.is_ident("rename_all") {
+                if let Some(rule) = parse_deserialize_name(&meta)? {
+                    variant.rename_all = RenameRule::from_str(&rule);
+                }
+            } else if meta.path.is_ident("skip") || meta.path.is_ident("skip_deserializing") {
+                variant.skip = true;
+            } else {
+                skip_meta_value(&meta)?;
+            }
+
+            Ok(())
+        });
+
+        variant
+    }
+}
+
+fn for_each_serde_meta(
+    attrs: &[Attribute],
+    mut handler: impl FnMut(ParseNestedMeta) -> syn::Result<()>,
+) {
+    for attr in attrs {
+        if attr.path().is_ident("serde") {
+            // NOTE: malformed attributes are rejected by serde itself, so there is nothing useful
+            // we can do with a parse error here
+            let _ = attr.parse_nested_meta(&mut handler);
+        }
+    }
+}
+
+/// Parses either `name = "value"` or `name(deserialize = "value", serialize = "...")`, returning
+/// the name used when deserializing.
+fn parse_deserialize_name(meta: &ParseNestedMeta) -> syn::Result<Option<String>> {
+    if meta.input.peek(Token![=]) {
+        let value: LitStr = meta.value()?.parse()?;
+        return Ok(Some(value.value()));
+    }
+
+    let mut name = None;
+    meta.parse_nested_meta(|nested| {
+        let value: LitStr = nested.value()?.parse()?;
+        if nested.path.is_ident("deserialize") {
+            name = Some(value.value());
+        }
+        Ok(())
+    })?;
+
+    Ok(name)
+}
+
+/// Consumes the value of attributes that we don't care about (e.g. `skip_serializing_if = "..."`
+/// or `bound(...)`) so that parsing can continue with the next attribute.
+fn skip_meta_value(meta: &ParseNestedMeta) -> syn::Result<()> {
+    if meta.input.peek(Token![=]) {
+        let _: syn::Expr = meta.value()?.parse()?;
+    } else if meta.input.peek(syn::token::Paren) {
+        let _content;
+        syn::parenthesized!(_content in meta.input);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,386 @@
+use super::model::{
+    ClassDefinition, FieldDefinition, FieldKey, LuaType, TypeDefinition, TypeKind, luals_field_name,
+};
+
+use tracing::warn;
+
+const INDENT: &str = "   ";
+
+/// Renders the definitions as a Teal declaration file (`.d.tl`), with every type nested in a
+/// `types` record that the module returns, e.g. `local config: types.Config = { ... }`.
+pub(crate) fn render(definitions: &[TypeDefinition]) -> String {
+    let mut output = String::from("local record types\n");
+
+    let mut first = true;
+    for definition in all_definitions(definitions) {
+        if !first {
+            output.push('\n');
+        }
+        first = false;
+
+        output.push_str(&render_definition(definition, definitions));
+    }
+
+    output.push_str("end\n\nreturn types\n");
+    output
+}
+
+/// The definitions along with the classes for enum variants, which Teal declares alongside every
+/// other record.
+fn all_definitions(definitions: &[TypeDefinition]) -> Vec<&TypeDefinition> {
+    let mut all = vec![];
+    for definition in definitions {
+        all.push(definition);
+        if let TypeKind::Alias {
+            variant_classes, ..
+        } = &definition.kind
+        {
+            all.extend(all_definitions(variant_classes));
+        }
+    }
+    all
+}
+
+fn render_definition(definition: &TypeDefinition, definitions: &[TypeDefinition]) -> String {
+    let mut output = String::new();
+    if let Some(doc) = &definition.docs {
+        output.push_str(&format!("{}-- {}\n", INDENT, doc.trim()));
+    }
+
+    let name = teal_type_name(&definition.name);
+    match &definition.kind {
+        TypeKind::Class(class) => {
+            output.push_str(&format!("{}record {}\n", INDENT, name));
+            for field in inherited_fields(class, definitions) {
+                output.push_str(&render_field(field));
+            }
+            output.push_str(&format!("{}end\n", INDENT));
+        }
+        TypeKind::Alias { variants, .. } => {
+            let literals: Vec<&String> = variants
+                .iter()
+                .filter_map(|variant| match variant {
+                    LuaType::Literal(value) => Some(value),
+                    _ => None,
+                })
+                .collect();
+
+            if !variants.is_empty() && literals.len() == variants.len() {
+                output.push_str(&format!("{}enum {}\n", INDENT, name));
+                for literal in literals {
+                    output.push_str(&format!("{}{}\"{}\"\n", INDENT, INDENT, literal));
+                }
+                output.push_str(&format!("{}end\n", INDENT));
+            } else if let Some((element, class)) = array_record(variants, definitions) {
+                // a record that is also an array of itself, i.e. either a single entry or a list
+                output.push_str(&format!("{}record {}\n", INDENT, name));
+                output.push_str(&format!("{}{}{{{}}}\n", INDENT, INDENT, element));
+                for field in inherited_fields(class, definitions) {
+                    output.push_str(&render_field(field));
+                }
+                output.push_str(&format!("{}end\n", INDENT));
+            } else {
+                output.push_str(&teal_union(&name, variants, definitions));
+            }
+        }
+    }
+
+    output
+}
+
+fn render_field(field: &FieldDefinition) -> String {
+    let mut output = String::new();
+    if let Some(doc) = &field.docs {
+        output.push_str(&format!("{}{}-- {}\n", INDENT, INDENT, doc.trim()));
+    }
+
+    match &field.key {
+        FieldKey::Name(name) => output.push_str(&format!(
+            "{}{}{}: {}\n",
+            INDENT,
+            INDENT,
+            luals_field_name(name),
+            teal_type(&field.lua_type)
+        )),
+        // records can't have an indexer alongside their fields
+        FieldKey::Index(key_type) => output.push_str(&format!(
+            "{}{}-- [{}]: {}\n",
+            INDENT,
+            INDENT,
+            teal_type(key_type),
+            teal_type(&field.lua_type)
+        )),
+    }
+
+    output
+}
+
+/// Teal records can't extend other records, so flattened fields are copied into the record.
+fn inherited_fields<'a>(
+    class: &'a ClassDefinition,
+    definitions: &'a [TypeDefinition],
+) -> Vec<&'a FieldDefinition> {
+    let mut fields = vec![];
+    for parent in &class.parents {
+        if let Some(TypeDefinition {
+            kind: TypeKind::Class(parent_class),
+            ..
+        }) = definitions
+            .iter()
+            .find(|definition| &definition.name == parent)
+        {
+            fields.extend(inherited_fields(parent_class, definitions));
+        }
+    }
+    fields.extend(&class.fields);
+    fields
+}
+
+/// Teal has no nested type names, so `Enum.Variant` becomes `EnumVariant`.
+fn teal_type_name(name: &str) -> String {
+    name.replace('.', "")
+}
+
+fn teal_type(lua_type: &LuaType) -> String {
+    match lua_type {
+        LuaType::Any | LuaType::Unknown => "any".to_string(),
+        LuaType::Nil => "nil".to_string(),
+        LuaType::Boolean => "boolean".to_string(),
+        LuaType::Integer => "integer".to_string(),
+        LuaType::Number => "number".to_string(),
+        // only enums can restrict strings to specific values
+        LuaType::String | LuaType::Literal(_) => "string".to_string(),
+        LuaType::Named(name) => teal_type_name(name),
+        LuaType::Array(inner) => format!("{{{}}}", teal_type(inner)),
+        LuaType::Map(key, value) => format!("{{{}:{}}}", teal_type(key), teal_type(value)),
+        LuaType::Tuple(elements) => {
+            let elements: Vec<String> = elements.iter().map(teal_type).collect();
+            format!("{{{}}}", elements.join(", "))
+        }
+        // there are no anonymous record types
+        LuaType::Table(_) => "{string:any}".to_string(),
+        LuaType::Function { params, returns } => {
+            let params: Vec<String> = params
+                .iter()
+                .map(|(_, param_type)| teal_type(param_type))
+                .collect();
+            format!("function({}): {}", params.join(", "), teal_type(returns))
+        }
+        LuaType::Union(members) => {
+            let members: Vec<String> = members
+                .iter()
+                .map(|member| match member {
+                    LuaType::Function { .. } => format!("({})", teal_type(member)),
+                    _ => teal_type(member),
+                })
+                .collect();
+            members.join(" | ")
+        }
+        // every type is nilable
+        LuaType::Optional(inner) => teal_type(inner),
+    }
+}
+
+/// The element type and class of a union of a record and an array of it (e.g. `Entry | {Entry}`),
+/// which Teal can declare as a record with an array part.
+fn array_record<'a>(
+    variants: &[LuaType],
+    definitions: &'a [TypeDefinition],
+) -> Option<(String, &'a ClassDefinition)> {
+    let [first, second] = variants else {
+        return None;
+    };
+    let name = match (first, second) {
+        (LuaType::Named(name), LuaType::Array(element))
+        | (LuaType::Array(element), LuaType::Named(name))
+            if **element == LuaType::Named(name.clone()) =>
+        {
+            name
+        }
+        _ => return None,
+    };
+
+    definitions.iter().find_map(|definition| match definition {
+        TypeDefinition {
+            name: definition_name,
+            kind: TypeKind::Class(class),
+            ..
+        } if definition_name == name => Some((teal_type_name(name), class)),
+        _ => None,
+    })
+}
+
+/// Renders the `type` declaration of a union. Teal unions may only contain a single table type,
+/// so any other union is declared as `any` (with a comment listing its types), and reported.
+fn teal_union(name: &str, variants: &[LuaType], definitions: &[TypeDefinition]) -> String {
+    let table_types = variants
+        .iter()
+        .filter(|variant| is_table_type(variant, definitions))
+        .count();
+
+    let mut types: Vec<String> = vec![];
+    for variant in variants {
+        let teal_type = teal_type(variant);
+        if !types.contains(&teal_type) {
+            types.push(teal_type);
+        }
+    }
+
+    if table_types > 1 {
+        warn!(
+            "`{}` is declared as `any` in Teal, whose unions can't contain more than one table type ({})",
+            name,
+            types.join(" | ")
+        );
+        format!(
+            "{INDENT}-- Teal unions can't contain more than one table type, so this is any of: {}\n{INDENT}type {} = any\n",
+            types.join(" | "),
+            name
+        )
+    } else {
+        format!("{}type {} = {}\n", INDENT, name, types.join(" | "))
+    }
+}
+
+fn is_table_type(lua_type: &LuaType, definitions: &[TypeDefinition]) -> bool {
+    match lua_type {
+        LuaType::Array(_) | LuaType::Map(_, _) | LuaType::Tuple(_) | LuaType::Table(_) => true,
+        LuaType::Optional(inner) => is_table_type(inner, definitions),
+        LuaType::Named(name) => !matches!(
+            definitions.iter().find(|definition| &definition.name == name),
+            Some(TypeDefinition {
+                kind: TypeKind::Alias { variants, .. },
+                ..
+            }) if variants.iter().all(|variant| matches!(variant, LuaType::Literal(_)))
+        ),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua_type_gen::module_tree::ModuleTree;
+    use crate::lua_type_gen::type_definitions;
+    use insta::assert_snapshot;
+    use std::path::Path;
+
+    fn generate_teal_from_str(content: &str) -> String {
+        let items = ModuleTree::from_source(Path::new("lib.rs"), content).unwrap();
+
+        render(&type_definitions(&items, true).unwrap())
+    }
+
+    #[test]
+    fn test_teal_unions_of_tables() {
+        let teal = generate_teal_from_str(
+            r###"
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Entries {
+    Single(Entry),
+    Multiple(Vec<Entry>),
+}
+
+#[derive(Deserialize)]
+pub struct Entry {
+    pub name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Source {
+    Path(String),
+    Entry(Entry),
+}
+        "###,
+        );
+
+        assert_snapshot!(teal, @r###"
+        local record types
+           record Entries
+              {Entry}
+              name: string
+           end
+
+           record Entry
+              name: string
+           end
+
+           type Source = Entry | string
+        end
+
+        return types
+        "###);
+    }
+
+    #[test]
+    fn test_teal_enums_and_flatten() {
+        let teal = generate_teal_from_str(
+            r###"
+#[derive(Deserialize)]
+pub struct Window {
+    #[serde(rename = "window-layout")]
+    pub layout: Option<Layout>,
+    pub panes: Vec<Pane>,
+    pub size: (u32, u32),
+    #[serde(flatten)]
+    pub common: Common,
+}
+
+#[derive(Deserialize)]
+pub struct Common {
+    pub env: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Layout {
+    Tiled,
+    MainVertical,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+pub enum Pane {
+    Empty,
+    Shell { command: String },
+}
+        "###,
+        );
+
+        assert_snapshot!(teal, @r###"
+        local record types
+           record Window
+              env: {string:string}
+              ["window-layout"]: Layout
+              panes: {Pane}
+              size: {integer, integer}
+           end
+
+           record Common
+              env: {string:string}
+           end
+
+           enum Layout
+              "tiled"
+              "main-vertical"
+           end
+
+           -- Teal unions can't contain more than one table type, so this is any of: PaneEmpty | PaneShell
+           type Pane = any
+
+           record PaneEmpty
+              type: string
+           end
+
+           record PaneShell
+              type: string
+              command: string
+           end
+        end
+
+        return types
+        "###);
+    }
+}
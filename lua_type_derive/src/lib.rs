@@ -0,0 +1,108 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{Attribute, Data, DeriveInput, Fields, LitStr, Type, parse_macro_input};
+
+/// Implements `lua_config_utils::LuaType`, which records the item's source so that Lua types can
+/// be generated for it (and every type that its fields refer to) without parsing any files.
+///
+/// Fields whose Lua type differs from their Rust type (e.g. because of `deserialize_with`) can
+/// override it with `#[lua(as = "Option<String>")]`.
+#[proc_macro_derive(LuaType, attributes(lua))]
+pub fn derive_lua_type(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match expand(&input) {
+        Ok(expanded) => expanded.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let source = quote!(#input).to_string();
+
+    let fields: Vec<&Fields> = match &input.data {
+        Data::Struct(data) => vec![&data.fields],
+        Data::Enum(data) => data
+            .variants
+            .iter()
+            .filter(|variant| !is_skipped(&variant.attrs))
+            .map(|variant| &variant.fields)
+            .collect(),
+        Data::Union(_) => {
+            return Err(syn::Error::new_spanned(
+                input,
+                "LuaType can't be derived for unions",
+            ));
+        }
+    };
+
+    let mut field_types = vec![];
+    for field in fields.into_iter().flat_map(|fields| fields.iter()) {
+        if is_skipped(&field.attrs) {
+            continue;
+        }
+
+        field_types.push(match lua_as_type(&field.attrs)? {
+            Some(ty) => ty,
+            None => field.ty.clone(),
+        });
+    }
+
+    Ok(quote! {
+        impl #impl_generics ::lua_config_utils::LuaType for #name #ty_generics #where_clause {
+            fn register(registry: &mut ::lua_config_utils::LuaTypeRegistry) {
+                if registry.add_source(::std::any::type_name::<Self>(), #source) {
+                    #(<#field_types as ::lua_config_utils::LuaType>::register(registry);)*
+                }
+            }
+        }
+    })
+}
+
+/// Whether the field (or variant) is `#[serde(skip)]`ed, in which case its type never appears in
+/// a config.
+fn is_skipped(attrs: &[Attribute]) -> bool {
+    let mut skipped = false;
+
+    for attr in attrs {
+        if attr.path().is_ident("serde") {
+            // NOTE: serde reports malformed attributes itself, so parse errors are ignored
+            let _ = attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("skip") || meta.path.is_ident("skip_deserializing") {
+                    skipped = true;
+                } else if meta.input.peek(syn::Token![=]) {
+                    let _: syn::Expr = meta.value()?.parse()?;
+                } else if meta.input.peek(syn::token::Paren) {
+                    let _content;
+                    syn::parenthesized!(_content in meta.input);
+                }
+                Ok(())
+            });
+        }
+    }
+
+    skipped
+}
+
+/// The type from `#[lua(as = "...")]`.
+fn lua_as_type(attrs: &[Attribute]) -> syn::Result<Option<Type>> {
+    let mut lua_as = None;
+
+    for attr in attrs {
+        if attr.path().is_ident("lua") {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("as") {
+                    let value: LitStr = meta.value()?.parse()?;
+                    lua_as = Some(value.parse()?);
+                    Ok(())
+                } else {
+                    Err(meta.error("unsupported lua attribute, expected `as = \"...\"`"))
+                }
+            })?;
+        }
+    }
+
+    Ok(lua_as)
+}